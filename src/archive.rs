@@ -0,0 +1,569 @@
+//! Export and import of hoards as a single archive. See [`export`] and [`import`].
+//!
+//! An archive is a zstd-compressed tar file. The stored files of each exported hoard, including
+//! metadata sidecars and snapshots, are under `hoards/`, laid out as in the hoards root. The
+//! operation logs of those hoards from every system are under `history/`, laid out as in the
+//! history directory, along with each system's last paths entries for them.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde_json::{Map, Value};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::checkers::history::last_paths::FILE_NAME as LAST_PATHS_FILE_NAME;
+use crate::checkers::history::HISTORY_DIR_NAME;
+use crate::hoard::symlinks::is_symlink;
+use crate::hoard::versions::VERSIONS_DIR;
+use crate::hoard::{atomic, stored_paths};
+use crate::HOARDS_DIR_SLUG;
+
+/// Errors that can occur while exporting or importing an archive.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// An I/O error occurred while working with the archive or a file in it.
+    #[error("I/O error at {path}: {error}")]
+    IO {
+        /// The path of the file being worked with.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// A last paths file could not be parsed.
+    #[error("invalid last paths file {path}: {error}")]
+    LastPaths {
+        /// The path of the file, on the system or in the archive.
+        path: PathBuf,
+        /// The error from parsing the file.
+        #[source]
+        error: serde_json::Error,
+    },
+    /// The archive contains a path that does not belong in an exported archive.
+    #[error("archive contains unexpected path {0}")]
+    InvalidEntry(PathBuf),
+    /// Importing the archive would replace existing files.
+    #[error(
+        "importing would replace existing files (use --force to replace them): {}",
+        .0.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    Conflicts(Vec<PathBuf>),
+}
+
+/// Returns a closure that wraps an I/O error with `path`.
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |error| Error::IO {
+        path: path.to_owned(),
+        error,
+    }
+}
+
+/// Reads a last paths file as a map of hoard names to entries, or an empty map if it does not
+/// exist.
+fn read_last_paths(path: &Path) -> Result<Map<String, Value>, Error> {
+    match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).map_err(|error| Error::LastPaths {
+            path: path.to_owned(),
+            error,
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Map::new()),
+        Err(err) => Err(io_err(path)(err)),
+    }
+}
+
+/// Writes the hoards `names`, with their history from `history_root`, to an archive at
+/// `output`.
+///
+/// # Errors
+///
+/// Any [`enum@Error`] from reading the hoards and history or writing the archive.
+pub(crate) fn export(
+    hoards_root: &Path,
+    history_root: &Path,
+    names: &[&str],
+    output: &Path,
+) -> Result<(), Error> {
+    let _span = tracing::debug_span!("export_archive", ?output).entered();
+    let mut history_dirs = Vec::new();
+    match fs::read_dir(history_root) {
+        Ok(entries) => {
+            for entry in entries {
+                let entry = entry.map_err(io_err(history_root))?;
+                let is_system = entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| Uuid::parse_str(name).is_ok());
+                if is_system {
+                    history_dirs.push(entry.path());
+                }
+            }
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(io_err(history_root)(err)),
+    }
+    history_dirs.sort();
+
+    // Keep track of the file being added, to report it if writing the archive fails.
+    let mut failed_path = output.to_owned();
+    atomic::write(output, None, |file| {
+        let encoder = zstd::Encoder::new(file, 0)?;
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(false);
+
+        for name in names {
+            let entries = stored_paths(hoards_root, name);
+            if !entries[2].exists() {
+                tracing::warn!(hoard = name, "hoard has not been backed up; skipping");
+                continue;
+            }
+            for path in entries {
+                let archive_path = Path::new(HOARDS_DIR_SLUG).join(
+                    path.strip_prefix(hoards_root)
+                        .expect("entry is in hoards root"),
+                );
+                failed_path.clone_from(&path);
+                if path.is_dir() {
+                    builder.append_dir_all(archive_path, &path)?;
+                } else if path.exists() {
+                    builder.append_path_with_name(&path, archive_path)?;
+                }
+            }
+        }
+
+        for dir in &history_dirs {
+            let id = dir.file_name().expect("history dirs have names");
+            let archive_dir = Path::new(HISTORY_DIR_NAME).join(id);
+            for name in names {
+                let logs = dir.join(name);
+                if logs.is_dir() {
+                    failed_path.clone_from(&logs);
+                    builder.append_dir_all(archive_dir.join(name), &logs)?;
+                }
+            }
+
+            let path = dir.join(LAST_PATHS_FILE_NAME);
+            failed_path.clone_from(&path);
+            let mut last_paths = read_last_paths(&path)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            last_paths.retain(|name, _| names.contains(&name.as_str()));
+            if !last_paths.is_empty() {
+                let content = serde_json::to_vec(&last_paths)?;
+                let mut header = tar::Header::new_gnu();
+                header.set_size(content.len() as u64);
+                header.set_mode(0o644);
+                let modified = fs::metadata(&path)?.modified()?;
+                header.set_mtime(
+                    modified
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |since| since.as_secs()),
+                );
+                header.set_cksum();
+                builder.append_data(
+                    &mut header,
+                    archive_dir.join(LAST_PATHS_FILE_NAME),
+                    content.as_slice(),
+                )?;
+            }
+        }
+
+        builder.into_inner()?.finish()?;
+        Ok(())
+    })
+    .map_err(|error| Error::IO {
+        path: failed_path,
+        error,
+    })
+}
+
+/// Where an entry in an archive is imported to.
+enum Target {
+    /// A file in the hoards root, belonging to the hoard `name`.
+    Hoard { name: String, path: PathBuf },
+    /// An operation log, or a directory containing them.
+    Log(PathBuf),
+    /// A system's last paths file, whose entries are merged into the existing one.
+    LastPaths(PathBuf),
+}
+
+impl Target {
+    fn new(hoards_root: &Path, history_root: &Path, entry_path: &Path) -> Result<Self, Error> {
+        let invalid = || Error::InvalidEntry(entry_path.to_owned());
+        let parts = entry_path
+            .components()
+            .map(|component| match component {
+                Component::Normal(part) => part.to_str().ok_or_else(invalid),
+                Component::CurDir => Ok(""),
+                _ => Err(invalid()),
+            })
+            .filter(|part| part.as_ref().map_or(true, |part| !part.is_empty()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match parts.as_slice() {
+            [root, rest @ ..] if *root == HOARDS_DIR_SLUG && !rest.is_empty() => {
+                let name = match rest {
+                    [versions, name, ..] if *versions == VERSIONS_DIR => name,
+                    [versions] if *versions == VERSIONS_DIR => return Err(invalid()),
+                    _ => rest[0].strip_suffix(".metadata.json").unwrap_or(rest[0]),
+                };
+                Ok(Self::Hoard {
+                    name: name.to_owned(),
+                    path: rest
+                        .iter()
+                        .fold(hoards_root.to_owned(), |path, part| path.join(part)),
+                })
+            }
+            [root, id, rest @ ..] if *root == HISTORY_DIR_NAME && Uuid::parse_str(id).is_ok() => {
+                let path = rest
+                    .iter()
+                    .fold(history_root.join(id), |path, part| path.join(part));
+                if rest == [LAST_PATHS_FILE_NAME] {
+                    Ok(Self::LastPaths(path))
+                } else {
+                    Ok(Self::Log(path))
+                }
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+type Archive = tar::Archive<zstd::Decoder<'static, BufReader<fs::File>>>;
+
+fn open(path: &Path) -> Result<Archive, Error> {
+    let file = fs::File::open(path).map_err(io_err(path))?;
+    let decoder = zstd::Decoder::new(file).map_err(io_err(path))?;
+    let mut archive = tar::Archive::new(decoder);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_overwrite(true);
+    Ok(archive)
+}
+
+/// Calls `f` with the target and entry of every entry in the archive at `path`.
+fn for_each_entry<F>(
+    path: &Path,
+    hoards_root: &Path,
+    history_root: &Path,
+    mut f: F,
+) -> Result<(), Error>
+where
+    F: FnMut(
+        Target,
+        &mut tar::Entry<'_, zstd::Decoder<'static, BufReader<fs::File>>>,
+    ) -> Result<(), Error>,
+{
+    let mut archive = open(path)?;
+    for entry in archive.entries().map_err(io_err(path))? {
+        let mut entry = entry.map_err(io_err(path))?;
+        let entry_path = entry.path().map_err(io_err(path))?.into_owned();
+        let target = Target::new(hoards_root, history_root, &entry_path)?;
+        f(target, &mut entry)?;
+    }
+    Ok(())
+}
+
+/// Returns the names of the hoards in the archive at `path`.
+///
+/// # Errors
+///
+/// Any [`enum@Error`] from reading the archive.
+pub(crate) fn hoard_names(path: &Path) -> Result<Vec<String>, Error> {
+    let mut names = BTreeSet::new();
+    for_each_entry(path, Path::new(""), Path::new(""), |target, _| {
+        if let Target::Hoard { name, .. } = target {
+            names.insert(name);
+        }
+        Ok(())
+    })?;
+    Ok(names.into_iter().collect())
+}
+
+fn read_entry(entry: &mut impl Read, path: &Path) -> Result<Vec<u8>, Error> {
+    let mut content = Vec::new();
+    entry.read_to_end(&mut content).map_err(io_err(path))?;
+    Ok(content)
+}
+
+fn parse_last_paths(content: &[u8], path: &Path) -> Result<Map<String, Value>, Error> {
+    serde_json::from_slice(content).map_err(|error| Error::LastPaths {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+/// Checks that `entry`, which is unpacked to `dest` under `root`, is not a hard link and is not
+/// unpacked through a symbolic link, whether one in `root` or one of the earlier entries in
+/// `links`. Adds `dest` to `links` if the entry is a symbolic link.
+fn check_links(
+    entry: &tar::Entry<'_, impl Read>,
+    root: &Path,
+    dest: &Path,
+    links: &mut BTreeSet<PathBuf>,
+) -> Result<(), Error> {
+    let entry_type = entry.header().entry_type();
+    let through_link = dest
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| *parent != root)
+        .any(|parent| links.contains(parent) || is_symlink(parent));
+    // Hard links can point anywhere, and exported archives never contain them.
+    if entry_type.is_hard_link() || through_link {
+        let entry_path = entry.path().map_err(io_err(dest))?.into_owned();
+        return Err(Error::InvalidEntry(entry_path));
+    }
+    if entry_type.is_symlink() {
+        links.insert(dest.to_owned());
+    }
+    Ok(())
+}
+
+/// Imports the hoards and history in the archive at `path` into `hoards_root` and
+/// `history_root`.
+///
+/// Unless `force` is set, nothing is imported if any hoard in the archive already exists in the
+/// hoards root, or if any operation log or last paths entry differs from an existing one. With
+/// `force`, those are replaced. If `dry_run` is set, only the checks are done.
+///
+/// # Errors
+///
+/// - [`Error::Conflicts`] if importing would replace existing files without `force`.
+/// - [`Error::InvalidEntry`] if the archive contains a hard link or a path through a symbolic
+///   link, which could write files outside of the hoards root and history directory.
+/// - Any other [`enum@Error`] from reading the archive or writing files.
+pub(crate) fn import(
+    hoards_root: &Path,
+    history_root: &Path,
+    path: &Path,
+    force: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    let _span = tracing::debug_span!("import_archive", ?path).entered();
+
+    // First pass: check entries and find conflicts.
+    let mut conflicts = BTreeSet::new();
+    let mut replaced = BTreeSet::new();
+    // Symbolic links in the archive, which no later entry may be unpacked through.
+    let mut links = BTreeSet::new();
+    for_each_entry(path, hoards_root, history_root, |target, entry| {
+        let (root, dest) = match &target {
+            Target::Hoard { path, .. } => (hoards_root, path),
+            Target::Log(path) | Target::LastPaths(path) => (history_root, path),
+        };
+        check_links(entry, root, dest, &mut links)?;
+
+        match target {
+            Target::Hoard { name, .. } => {
+                for existing in stored_paths(hoards_root, &name) {
+                    if existing.exists() {
+                        conflicts.insert(existing.clone());
+                        replaced.insert(existing);
+                    }
+                }
+            }
+            Target::Log(dest) => {
+                if entry.header().entry_type().is_file() && dest.exists() {
+                    let content = read_entry(entry, &dest)?;
+                    if fs::read(&dest).map_err(io_err(&dest))? != content {
+                        conflicts.insert(dest);
+                    }
+                }
+            }
+            Target::LastPaths(dest) => {
+                let imported = parse_last_paths(&read_entry(entry, &dest)?, &dest)?;
+                let existing = read_last_paths(&dest)?;
+                let differs = imported.iter().any(|(name, value)| {
+                    existing.get(name).is_some_and(|existing| existing != value)
+                });
+                if differs {
+                    conflicts.insert(dest);
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    if !conflicts.is_empty() {
+        if !force {
+            return Err(Error::Conflicts(conflicts.into_iter().collect()));
+        }
+        tracing::warn!(?conflicts, "replacing existing files");
+    }
+    if dry_run {
+        tracing::info!("archive can be imported");
+        return Ok(());
+    }
+
+    for path in &replaced {
+        tracing::debug!(?path, "removing existing hoard files");
+        let result = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+        result.map_err(io_err(path))?;
+    }
+
+    // Second pass: unpack.
+    let mut last_paths: BTreeMap<PathBuf, Map<String, Value>> = BTreeMap::new();
+    for_each_entry(path, hoards_root, history_root, |target, entry| {
+        let dest = match target {
+            Target::Hoard { path, .. } | Target::Log(path) => path,
+            Target::LastPaths(dest) => {
+                let imported = parse_last_paths(&read_entry(entry, &dest)?, &dest)?;
+                last_paths.entry(dest).or_default().extend(imported);
+                return Ok(());
+            }
+        };
+        tracing::trace!(path = ?dest, "unpacking");
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(io_err(parent))?;
+        }
+        entry.unpack(&dest).map_err(io_err(&dest))?;
+        Ok(())
+    })?;
+
+    for (dest, imported) in last_paths {
+        let mut merged = read_last_paths(&dest)?;
+        merged.extend(imported);
+        let content = serde_json::to_vec(&merged).map_err(|error| Error::LastPaths {
+            path: dest.clone(),
+            error,
+        })?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(io_err(parent))?;
+        }
+        fs::write(&dest, content).map_err(io_err(&dest))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "6c3a1cd6-0dd0-4a8d-9e2c-3c4a64f1f1a5";
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let src = tempfile::tempdir().expect("failed to create temp dir");
+        let (hoards, history) = (src.path().join("hoards"), src.path().join("history"));
+        write(&hoards.join("first").join("pile").join("file"), "content");
+        write(&hoards.join("first.metadata.json"), "{}");
+        let snapshot = hoards.join(VERSIONS_DIR).join("first").join("2021");
+        write(&snapshot.join("first").join("pile").join("file"), "old");
+        write(&hoards.join("second").join("file"), "not exported");
+        write(&history.join(ID).join("first").join("1.log"), "log");
+        write(
+            &history.join(ID).join("second").join("1.log"),
+            "not exported",
+        );
+        write(
+            &history.join(ID).join(LAST_PATHS_FILE_NAME),
+            r#"{"first": {"a": 1}, "second": {"b": 2}}"#,
+        );
+
+        let archive = src.path().join("export.tar.zst");
+        export(&hoards, &history, &["first"], &archive).expect("export should succeed");
+        assert_eq!(hoard_names(&archive).unwrap(), vec!["first"]);
+
+        let dest = tempfile::tempdir().expect("failed to create temp dir");
+        let (hoards, history) = (dest.path().join("hoards"), dest.path().join("history"));
+        write(
+            &history.join(ID).join(LAST_PATHS_FILE_NAME),
+            r#"{"other": {"c": 3}}"#,
+        );
+        import(&hoards, &history, &archive, false, false).expect("import should succeed");
+        assert_eq!(
+            fs::read_to_string(hoards.join("first").join("pile").join("file")).unwrap(),
+            "content"
+        );
+        assert!(hoards.join("first.metadata.json").exists());
+        let snapshot = hoards.join(VERSIONS_DIR).join("first").join("2021");
+        assert!(snapshot.join("first").join("pile").join("file").exists());
+        assert!(!hoards.join("second").exists());
+        assert!(history.join(ID).join("first").join("1.log").exists());
+        assert!(!history.join(ID).join("second").exists());
+        let last_paths = read_last_paths(&history.join(ID).join(LAST_PATHS_FILE_NAME)).unwrap();
+        assert_eq!(
+            last_paths.keys().collect::<Vec<_>>(),
+            vec!["first", "other"]
+        );
+
+        // Importing again conflicts with the now-existing hoard.
+        write(&hoards.join("first").join("pile").join("file"), "changed");
+        let err = import(&hoards, &history, &archive, false, false).unwrap_err();
+        assert!(matches!(err, Error::Conflicts(paths) if paths.contains(&hoards.join("first"))));
+        import(&hoards, &history, &archive, true, false).expect("forced import should succeed");
+        assert_eq!(
+            fs::read_to_string(hoards.join("first").join("pile").join("file")).unwrap(),
+            "content"
+        );
+    }
+
+    #[test]
+    fn test_import_rejects_paths_through_links() {
+        let src = tempfile::tempdir().expect("failed to create temp dir");
+        let outside = src.path().join("outside");
+        fs::create_dir_all(&outside).unwrap();
+        let archive = src.path().join("evil.tar.zst");
+
+        let file = fs::File::create(&archive).unwrap();
+        let mut builder = tar::Builder::new(zstd::Encoder::new(file, 0).unwrap().auto_finish());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "hoards/evil/link", &outside)
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "hoards/evil/link/file", &b"evil"[..])
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let dest = tempfile::tempdir().expect("failed to create temp dir");
+        let (hoards, history) = (dest.path().join("hoards"), dest.path().join("history"));
+        let err = import(&hoards, &history, &archive, true, false).unwrap_err();
+        assert!(
+            matches!(err, Error::InvalidEntry(path) if path == Path::new("hoards/evil/link/file"))
+        );
+        assert!(!outside.join("file").exists());
+        assert!(!hoards.exists());
+    }
+
+    #[test]
+    fn test_import_rejects_hard_links() {
+        let src = tempfile::tempdir().expect("failed to create temp dir");
+        let archive = src.path().join("evil.tar.zst");
+
+        let file = fs::File::create(&archive).unwrap();
+        let mut builder = tar::Builder::new(zstd::Encoder::new(file, 0).unwrap().auto_finish());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "hoards/evil/file", "/etc/passwd")
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let dest = tempfile::tempdir().expect("failed to create temp dir");
+        let (hoards, history) = (dest.path().join("hoards"), dest.path().join("history"));
+        let err = import(&hoards, &history, &archive, true, false).unwrap_err();
+        assert!(matches!(err, Error::InvalidEntry(path) if path == Path::new("hoards/evil/file")));
+        assert!(!hoards.exists());
+    }
+}
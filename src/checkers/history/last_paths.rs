@@ -13,7 +13,7 @@ use std::{fs, io};
 use thiserror::Error;
 use time::OffsetDateTime;
 
-const FILE_NAME: &str = "last_paths.json";
+pub(crate) const FILE_NAME: &str = "last_paths.json";
 
 /// Errors that may occur while working with a [`LastPaths`] or related types.
 #[derive(Debug, Error)]
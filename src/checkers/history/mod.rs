@@ -10,14 +10,14 @@ pub mod last_paths;
 pub mod operation;
 
 const UUID_FILE_NAME: &str = "uuid";
-const HISTORY_DIR_NAME: &str = "history";
+pub(crate) const HISTORY_DIR_NAME: &str = "history";
 
 fn get_uuid_file() -> PathBuf {
     let _span = tracing::debug_span!("get_uuid_file").entered();
     get_dirs().config_dir().join(UUID_FILE_NAME)
 }
 
-fn get_history_root_dir() -> PathBuf {
+pub(crate) fn get_history_root_dir() -> PathBuf {
     let _span = tracing::debug_span!("get_history_root_dir").entered();
     get_dirs().data_dir().join(HISTORY_DIR_NAME)
 }
@@ -27,7 +27,7 @@ fn get_history_dir_for_id(id: Uuid) -> PathBuf {
     get_history_root_dir().join(id.to_string())
 }
 
-fn get_history_dirs_not_for_id(id: &Uuid) -> Result<Vec<PathBuf>, io::Error> {
+pub(crate) fn get_history_dirs_not_for_id(id: &Uuid) -> Result<Vec<PathBuf>, io::Error> {
     let _span = tracing::debug_span!("get_history_dir_not_for_id", %id).entered();
     let root = get_history_root_dir();
     if !root.exists() {
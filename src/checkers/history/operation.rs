@@ -8,21 +8,24 @@
 //! was the last one to touch a file.
 
 use crate::checkers::Checker;
-use crate::hoard::{Direction, Hoard as ConfigHoard, Pile as ConfigPile};
+use crate::hoard::symlinks::{enter_dir, is_symlink};
+use crate::hoard::{Direction, Hoard as ConfigHoard, Pile as ConfigPile, Symlinks};
 use md5::{Digest, Md5};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::Sha256;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::{fmt, fs, io};
 use thiserror::Error;
 use time::format_description::FormatItem;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-static TIME_FORMAT: Lazy<Vec<FormatItem<'static>>> = Lazy::new(|| {
+/// The format of operation log timestamps, also used to name snapshots of previous file versions.
+pub(crate) static TIME_FORMAT: Lazy<Vec<FormatItem<'static>>> = Lazy::new(|| {
     time::format_description::parse(
         "[year]_[month]_[day]-[hour repr:24]_[minute]_[second].[subsecond digits:6]",
     )
@@ -55,7 +58,7 @@ pub enum Error {
 /// This keeps track of the timestamp of the operation (which may include multiple hoards),
 /// all hoards involved in the operation (and the related [`HoardOperation`]), and a record
 /// of the latest operation log for each external system at the time of invocation.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(clippy::module_name_repetitions)]
 pub struct HoardOperation {
     /// Timestamp of last operation
@@ -66,6 +69,23 @@ pub struct HoardOperation {
     pub(crate) hoard_name: String,
     /// Mapping of pile files to checksums
     pub(crate) hoard: Hoard,
+    /// The hoard this operation was created from, if any.
+    ///
+    /// Used to re-hash files when comparing against an operation that used a different
+    /// checksum algorithm.
+    #[serde(skip)]
+    config: Option<ConfigHoard>,
+}
+
+// Not derived because `config` only exists at runtime: an operation read from disk should equal
+// the one that was written.
+impl PartialEq for HoardOperation {
+    fn eq(&self, other: &Self) -> bool {
+        self.timestamp == other.timestamp
+            && self.is_backup == other.is_backup
+            && self.hoard_name == other.hoard_name
+            && self.hoard == other.hoard
+    }
 }
 
 impl Checker for HoardOperation {
@@ -144,6 +164,7 @@ impl HoardOperation {
             is_backup: matches!(direction, Direction::Backup),
             hoard_name: name.into(),
             hoard: Hoard::try_from(hoard)?,
+            config: Some(hoard.clone()),
         })
     }
 
@@ -163,13 +184,44 @@ impl HoardOperation {
 
     /// Checks if files in both operations are the same.
     ///
+    /// If a file's checksums were created with different algorithms, the file is hashed again
+    /// with the algorithm used by `other`, if this operation was created from a hoard.
+    ///
     /// # Errors
     ///
-    /// Returns [`Error::RestoreRequired`] if they do not have the same files (and hashes).
+    /// - [`Error::RestoreRequired`] if they do not have the same files (and hashes).
+    /// - Any I/O error from hashing a file again.
     pub fn check_has_same_files(&self, other: &Self) -> Result<(), Error> {
-        (self.hoard == other.hoard)
-            .then(|| ())
-            .ok_or(Error::RestoreRequired)
+        let is_same = match (&self.hoard, &other.hoard) {
+            (Hoard::Anonymous(this), Hoard::Anonymous(other)) => {
+                let root = match &self.config {
+                    Some(ConfigHoard::Anonymous(pile)) => pile.path.as_deref(),
+                    _ => None,
+                };
+                this.has_same_files(other, root)?
+            }
+            (Hoard::Named(this), Hoard::Named(other)) => {
+                this.len() == other.len()
+                    && this.iter().try_fold(true, |is_same, (name, this)| {
+                        if !is_same {
+                            return Ok(false);
+                        }
+                        let Some(other) = other.get(name) else {
+                            return Ok(false);
+                        };
+                        let root = match &self.config {
+                            Some(ConfigHoard::Named(piles)) => {
+                                piles.piles.get(name).and_then(|pile| pile.path.as_deref())
+                            }
+                            _ => None,
+                        };
+                        this.has_same_files(other, root)
+                    })?
+            }
+            _ => false,
+        };
+
+        is_same.then(|| ()).ok_or(Error::RestoreRequired)
     }
 
     fn from_file(path: &Path) -> Result<Self, Error> {
@@ -217,13 +269,7 @@ impl HoardOperation {
                 None => Some(operation),
                 Some(path) => match operation {
                     Err(err) => Some(Err(err)),
-                    Ok(operation) => match &operation.hoard {
-                        Hoard::Anonymous(pile) => pile.0.contains_key(path).then(|| Ok(operation)),
-                        Hoard::Named(piles) => piles
-                            .values()
-                            .any(|pile| pile.0.contains_key(path))
-                            .then(|| Ok(operation)),
-                    },
+                    Ok(operation) => operation.has_file(path).then(|| Ok(operation)),
                 },
             })
             .reduce(|left, right| {
@@ -243,6 +289,47 @@ impl HoardOperation {
             .transpose()
     }
 
+    /// Returns whether this operation recorded a checksum for `path` in any pile.
+    ///
+    /// `path` must be relative to the root of one of the Hoard's Piles.
+    #[must_use]
+    pub fn has_file(&self, path: &Path) -> bool {
+        match &self.hoard {
+            Hoard::Anonymous(pile) => pile.0.contains_key(path),
+            Hoard::Named(piles) => piles.values().any(|pile| pile.0.contains_key(path)),
+        }
+    }
+
+    /// Returns the files whose checksums are the same in this operation and `previous`, as
+    /// pairs of pile name (`None` for anonymous piles) and path relative to the pile.
+    ///
+    /// Files hashed with different algorithms in the two operations are never considered the
+    /// same.
+    #[must_use]
+    pub fn unchanged_files(&self, previous: &Self) -> Vec<(Option<&str>, &Path)> {
+        match (&self.hoard, &previous.hoard) {
+            (Hoard::Anonymous(pile), Hoard::Anonymous(previous)) => pile
+                .0
+                .iter()
+                .filter(|(path, checksum)| previous.get(path) == Some(*checksum))
+                .map(|(path, _)| (None, path.as_path()))
+                .collect(),
+            (Hoard::Named(piles), Hoard::Named(previous)) => piles
+                .iter()
+                .filter_map(|(name, pile)| {
+                    previous.get(name).map(|previous| (name, pile, previous))
+                })
+                .flat_map(|(name, pile, previous)| {
+                    pile.0
+                        .iter()
+                        .filter(move |(path, checksum)| previous.get(path) == Some(*checksum))
+                        .map(move |(path, _)| (Some(name.as_str()), path.as_path()))
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
     /// Returns the latest operation recorded on this machine (by UUID).
     ///
     /// `file`, if provided, must be a path relative to the root of one of the Hoard's Piles.
@@ -259,6 +346,22 @@ impl HoardOperation {
         Self::latest_hoard_operation_from_system_dir(&self_folder, hoard, file, false)
     }
 
+    /// Returns the latest backup operation recorded on this machine (by UUID).
+    ///
+    /// `file`, if provided, must be a path relative to the root of one of the Hoard's Piles.
+    ///
+    /// # Errors
+    ///
+    /// - Any errors that occur while reading from the filesystem
+    /// - Any parsing errors from `serde_json` when parsing the file
+    pub fn latest_local_backup(hoard: &str, file: Option<&Path>) -> Result<Option<Self>, Error> {
+        let _span = tracing::debug_span!("latest_local_backup", %hoard).entered();
+        tracing::debug!("finding latest backup Operation file for this machine");
+        let uuid = super::get_or_generate_uuid()?;
+        let self_folder = super::get_history_dir_for_id(uuid);
+        Self::latest_hoard_operation_from_system_dir(&self_folder, hoard, file, true)
+    }
+
     /// Returns the latest backup operation recorded on any other machine (by UUID).
     ///
     /// `file`, if provided, must be a path relative to the root of one of the Hoard's Piles.
@@ -324,6 +427,33 @@ impl HoardOperation {
 
         Ok(remote.is_some() || local.is_some())
     }
+
+    /// Returns whether the given `file` was deleted by the latest remote backup.
+    ///
+    /// This is the case if the latest remote backup is more recent than the latest local
+    /// operation involving `file` and does not contain `file` itself.
+    ///
+    /// `file` must be a path relative to the root of one of the Hoard's Piles.
+    ///
+    /// # Errors
+    ///
+    /// - Any errors returned by [`latest_local`] or [`latest_remote_backup`].
+    pub fn file_deleted_remotely(hoard: &str, file: &Path) -> Result<bool, Error> {
+        let Some(remote) = Self::latest_remote_backup(hoard, None)? else {
+            return Ok(false);
+        };
+
+        if remote.has_file(file) {
+            return Ok(false);
+        }
+
+        let result = match Self::latest_local(hoard, Some(file))? {
+            Some(local) => remote.timestamp > local.timestamp,
+            None => Self::latest_remote_backup(hoard, Some(file))?.is_some(),
+        };
+
+        Ok(result)
+    }
 }
 
 /// Operation log information for a single hoard.
@@ -353,43 +483,190 @@ impl TryFrom<&ConfigHoard> for Hoard {
     }
 }
 
+/// The algorithms that can be used to create a [`Checksum`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[allow(clippy::upper_case_acronyms)]
+pub enum ChecksumType {
+    /// MD5. Fast but may have collisions.
+    MD5,
+    /// SHA-256. Collision resistant.
+    #[default]
+    SHA256,
+    /// BLAKE3. Collision resistant and fast.
+    BLAKE3,
+}
+
 /// Enum to differentiate between different types of checksum.
 ///
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Checksum {
     /// An MD5 checksum. Fast but may have collisions.
     #[serde(rename = "md5")]
     MD5(String),
+    /// A SHA-256 checksum.
+    #[serde(rename = "sha256")]
+    SHA256(String),
+    /// A BLAKE3 checksum.
+    #[serde(rename = "blake3")]
+    BLAKE3(String),
+}
+
+impl Checksum {
+    /// Create a checksum of `content` using the given algorithm.
+    #[must_use]
+    pub fn from_content(content: &[u8], checksum_type: ChecksumType) -> Self {
+        match checksum_type {
+            ChecksumType::MD5 => Self::MD5(format!("{:x}", Md5::digest(content))),
+            ChecksumType::SHA256 => Self::SHA256(format!("{:x}", Sha256::digest(content))),
+            ChecksumType::BLAKE3 => Self::BLAKE3(blake3::hash(content).to_hex().to_string()),
+        }
+    }
+
+    /// Create a checksum of a preserved symbolic link, based on the path it points to.
+    #[must_use]
+    pub fn from_symlink_target(target: &Path, checksum_type: ChecksumType) -> Self {
+        Self::from_content(target.to_string_lossy().as_bytes(), checksum_type)
+    }
+
+    /// The algorithm used to create this checksum.
+    #[must_use]
+    pub fn checksum_type(&self) -> ChecksumType {
+        match self {
+            Self::MD5(_) => ChecksumType::MD5,
+            Self::SHA256(_) => ChecksumType::SHA256,
+            Self::BLAKE3(_) => ChecksumType::BLAKE3,
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MD5(sum) => write!(f, "md5({sum})"),
+            Self::SHA256(sum) => write!(f, "sha256({sum})"),
+            Self::BLAKE3(sum) => write!(f, "blake3({sum})"),
+        }
+    }
+}
+
+/// Deserializes pile checksums, accepting the bare MD5 strings written by older versions.
+#[allow(single_use_lifetimes)]
+fn deserialize_checksums<'de, D>(deserializer: D) -> Result<HashMap<PathBuf, Checksum>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MaybeLegacy {
+        Legacy(String),
+        Typed(Checksum),
+    }
+
+    Ok(HashMap::<PathBuf, MaybeLegacy>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(path, checksum)| match checksum {
+            MaybeLegacy::Legacy(sum) => (path, Checksum::MD5(sum)),
+            MaybeLegacy::Typed(checksum) => (path, checksum),
+        })
+        .collect())
 }
 
 /// A mapping of file path (relative to pile) to file checksum.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-pub struct Pile(HashMap<PathBuf, String>);
+pub struct Pile(#[serde(deserialize_with = "deserialize_checksums")] HashMap<PathBuf, Checksum>);
 
 impl Pile {
-    pub(crate) fn get(&'_ self, key: &Path) -> Option<&'_ str> {
-        self.0.get(key).map(String::as_str)
+    pub(crate) fn get(&'_ self, key: &Path) -> Option<&'_ Checksum> {
+        self.0.get(key)
+    }
+
+    /// Returns whether both piles have the same files with the same content.
+    ///
+    /// Files with checksums of different types are hashed again from `root`, if provided.
+    fn has_same_files(&self, other: &Self, root: Option<&Path>) -> Result<bool, Error> {
+        if self.0.len() != other.0.len() {
+            return Ok(false);
+        }
+
+        for (path, checksum) in &self.0 {
+            let Some(other) = other.0.get(path) else {
+                return Ok(false);
+            };
+
+            let is_same = if checksum.checksum_type() == other.checksum_type() {
+                checksum == other
+            } else if let Some(root) = root {
+                tracing::trace!(
+                    ?path,
+                    "checksum types differ, hashing file with {:?}",
+                    other.checksum_type()
+                );
+                let content = fs::read(root.join(path))?;
+                Checksum::from_content(&content, other.checksum_type()) == *other
+            } else {
+                false
+            };
+
+            if !is_same {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
     }
 }
 
-fn hash_path(path: &Path, root: &Path) -> Result<HashMap<PathBuf, String>, Error> {
+/// Settings for hashing the files in a pile with [`hash_path`].
+#[derive(Clone, Copy)]
+struct HashOptions<'a> {
+    root: &'a Path,
+    checksum_type: ChecksumType,
+    symlinks: Symlinks,
+}
+
+/// Hashes the content of the file at `path` with the given algorithm.
+pub(crate) fn hash_file(path: &Path, checksum_type: ChecksumType) -> io::Result<Checksum> {
+    let bytes = fs::read(path)?;
+    Ok(Checksum::from_content(&bytes, checksum_type))
+}
+
+fn hash_path(
+    path: &Path,
+    options: HashOptions<'_>,
+    ancestors: &mut Vec<PathBuf>,
+) -> Result<HashMap<PathBuf, Checksum>, Error> {
     let mut map = HashMap::new();
-    if path.is_file() {
-        tracing::trace!(file=%path.display(), "Hashing file");
-        let bytes = fs::read(path)?;
-        let digest = Md5::digest(&bytes);
-        let rel_path = path
-            .strip_prefix(root)
+    let rel_path = || {
+        path.strip_prefix(options.root)
             .expect("paths in hash_path should always be children of the given root")
-            .to_path_buf();
-        map.insert(rel_path, format!("{:x}", digest));
+            .to_path_buf()
+    };
+
+    if is_symlink(path) && options.symlinks != Symlinks::Follow {
+        if options.symlinks == Symlinks::Preserve {
+            tracing::trace!(file=%path.display(), "Hashing symbolic link target");
+            let target = fs::read_link(path)?;
+            map.insert(
+                rel_path(),
+                Checksum::from_symlink_target(&target, options.checksum_type),
+            );
+        }
+    } else if path.is_file() {
+        tracing::trace!(file=%path.display(), "Hashing file");
+        map.insert(rel_path(), hash_file(path, options.checksum_type)?);
     } else if path.is_dir() {
         tracing::trace!(dir=%path.display(), "Hashing all files in dir");
-        for item in fs::read_dir(path)? {
-            let item = item?;
-            let path = item.path();
-            map.extend(hash_path(&path, root)?);
+        if let Some(canonical) = enter_dir(ancestors, path)? {
+            ancestors.push(canonical);
+            for item in fs::read_dir(path)? {
+                let item = item?;
+                let path = item.path();
+                map.extend(hash_path(&path, options, ancestors)?);
+            }
+            ancestors.pop();
         }
     } else {
         tracing::warn!(path=%path.display(), "path is neither file nor directory, skipping");
@@ -402,9 +679,21 @@ impl TryFrom<&ConfigPile> for Pile {
     type Error = Error;
     fn try_from(pile: &ConfigPile) -> Result<Self, Self::Error> {
         let _span = tracing::trace_span!("pile_to_operation", ?pile).entered();
+        let checksum_type = pile
+            .config
+            .as_ref()
+            .and_then(|config| config.checksum_type)
+            .unwrap_or_default();
         pile.path.as_ref().map_or_else(
             || Ok(Self(HashMap::new())),
-            |path| hash_path(path, path).map(Self),
+            |path| {
+                let options = HashOptions {
+                    root: path,
+                    checksum_type,
+                    symlinks: pile.symlinks(),
+                };
+                hash_path(path, options, &mut Vec::new()).map(Self)
+            },
         )
     }
 }
@@ -544,4 +833,128 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn test_checksum_from_content() {
+        let content = b"hello world";
+        assert_eq!(
+            Checksum::from_content(content, ChecksumType::MD5),
+            Checksum::MD5("5eb63bbbe01eeed093cb22bb8f5acdc3".into())
+        );
+        assert_eq!(
+            Checksum::from_content(content, ChecksumType::SHA256),
+            Checksum::SHA256(
+                "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".into()
+            )
+        );
+        assert_eq!(
+            Checksum::from_content(content, ChecksumType::BLAKE3),
+            Checksum::BLAKE3(
+                "d74981efa70a0c880b8d8c1985d075dbcbf679b99a5f9914e5aaf96b831a9e24".into()
+            )
+        );
+        assert_eq!(ChecksumType::default(), ChecksumType::SHA256);
+    }
+
+    #[test]
+    fn test_pile_reads_legacy_and_typed_checksums() {
+        let pile: Pile =
+            serde_json::from_str(r#"{"old.txt": "legacy", "new.txt": {"sha256": "typed"}}"#)
+                .expect("failed to parse pile");
+        assert_eq!(
+            pile.get(Path::new("old.txt")),
+            Some(&Checksum::MD5("legacy".into()))
+        );
+        assert_eq!(
+            pile.get(Path::new("new.txt")),
+            Some(&Checksum::SHA256("typed".into()))
+        );
+
+        let json = serde_json::to_string(&pile).expect("failed to serialize pile");
+        let reparsed: Pile = serde_json::from_str(&json).expect("failed to reparse pile");
+        assert_eq!(pile, reparsed);
+    }
+
+    #[test]
+    fn test_pile_has_same_files_across_checksum_types() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        fs::write(root.path().join("file"), b"content").unwrap();
+
+        let hash = |checksum_type| {
+            let options = HashOptions {
+                root: root.path(),
+                checksum_type,
+                symlinks: Symlinks::Follow,
+            };
+            Pile(hash_path(root.path(), options, &mut Vec::new()).unwrap())
+        };
+        let md5 = hash(ChecksumType::MD5);
+        let sha = hash(ChecksumType::SHA256);
+        assert!(sha.has_same_files(&md5, Some(root.path())).unwrap());
+        // Cannot compare different checksum types without the files.
+        assert!(!sha.has_same_files(&md5, None).unwrap());
+
+        fs::write(root.path().join("file"), b"changed").unwrap();
+        assert!(!sha.has_same_files(&md5, Some(root.path())).unwrap());
+    }
+
+    #[test]
+    fn test_operation_equals_itself_read_from_disk() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        fs::write(root.path().join("file"), b"content").unwrap();
+        let hoard = ConfigHoard::Anonymous(ConfigPile {
+            config: None,
+            path: Some(root.path().to_owned()),
+        });
+        let operation = HoardOperation::new("hoard", &hoard, Direction::Backup)
+            .expect("failed to create operation");
+        assert!(operation.config.is_some());
+
+        let json = serde_json::to_string(&operation).expect("failed to serialize operation");
+        let reparsed: HoardOperation =
+            serde_json::from_str(&json).expect("failed to reparse operation");
+        assert!(reparsed.config.is_none());
+        assert_eq!(operation, reparsed);
+    }
+
+    #[test]
+    fn test_unchanged_files_compares_checksums_per_pile() {
+        let operation = |files: Vec<(&str, Checksum)>| HoardOperation {
+            timestamp: OffsetDateTime::now_utc(),
+            is_backup: true,
+            hoard_name: "hoard".into(),
+            hoard: Hoard::Named(
+                std::iter::once((
+                    "pile".to_string(),
+                    Pile(
+                        files
+                            .into_iter()
+                            .map(|(path, checksum)| (PathBuf::from(path), checksum))
+                            .collect(),
+                    ),
+                ))
+                .collect(),
+            ),
+            config: None,
+        };
+        let sha = |content: &[u8]| Checksum::from_content(content, ChecksumType::SHA256);
+        let md5 = |content: &[u8]| Checksum::from_content(content, ChecksumType::MD5);
+
+        let previous = operation(vec![
+            ("same", sha(b"same")),
+            ("changed", sha(b"old")),
+            ("rehashed", md5(b"rehashed")),
+        ]);
+        let current = operation(vec![
+            ("same", sha(b"same")),
+            ("changed", sha(b"new")),
+            ("rehashed", sha(b"rehashed")),
+            ("new", sha(b"new")),
+        ]);
+
+        assert_eq!(
+            current.unchanged_files(&previous),
+            vec![(Some("pile"), Path::new("same"))]
+        );
+    }
 }
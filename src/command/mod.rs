@@ -2,6 +2,7 @@
 
 mod edit;
 
+use std::path::PathBuf;
 use structopt::StructOpt;
 use thiserror::Error;
 
@@ -32,9 +33,40 @@ pub enum Command {
     Restore {
         /// The name(s) of the hoard(s) to restore. Will restore all hoards if empty.
         hoards: Vec<String>,
+        /// Restore the files saved in the snapshot with this timestamp (see `hoard versions`)
+        /// instead of the current files in the hoard.
+        #[structopt(long)]
+        version: Option<String>,
+    },
+    /// Delete stored file contents that are no longer referenced by any pile, when using the
+    /// content-addressed layout.
+    Gc,
+    /// List the snapshots of previous file versions kept for a hoard.
+    Versions {
+        /// The name of the hoard to list snapshots for.
+        hoard: String,
+    },
+    /// Pack the stored files and operation logs of the given hoard(s) into a single archive.
+    Export {
+        /// The name(s) of the hoard(s) to export. Will export all hoards if empty.
+        hoards: Vec<String>,
+        /// The archive file to create.
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Unpack an archive created by `hoard export` into the hoards root.
+    Import {
+        /// The archive file to import.
+        #[structopt(parse(from_os_str))]
+        archive: PathBuf,
     },
     /// List configured hoards.
     List,
+    /// Pull the configured git remote into the hoards root, then show the status of all
+    /// hoards.
+    Pull,
+    /// Push the hoards root to the configured git remote, then show the status of all hoards.
+    Push,
     /// Open the configuration file in the system default editor.
     Edit,
     /// Show which files differ for a given hoard. Optionally show unified diffs for text files
@@ -49,6 +81,20 @@ pub enum Command {
     /// Provides a summary of which hoards have changes and if the diffs can be resolved
     /// with a single command.
     Status,
+    /// Re-encrypt the files stored for a hoard with the currently configured encryption,
+    /// without restoring them to the filesystem.
+    Rekey {
+        /// The name of the hoard to re-encrypt.
+        hoard: String,
+        /// A command that prints the password the files were previously encrypted with.
+        ///
+        /// Each occurrence of the flag adds one argument of the command.
+        #[structopt(long, number_of_values = 1)]
+        old_password_cmd: Vec<String>,
+        /// A file containing the private keys the files were previously encrypted to.
+        #[structopt(long, parse(from_os_str))]
+        old_identity_file: Option<PathBuf>,
+    },
 }
 
 impl Default for Command {
@@ -66,4 +112,32 @@ mod tests {
         // The default command is validate if one is not given
         assert_eq!(Command::Validate, Command::default());
     }
+
+    #[test]
+    fn rekey_old_password_cmd_does_not_take_hoard_name() {
+        let expected = Command::Rekey {
+            hoard: "myhoard".into(),
+            old_password_cmd: vec!["pass".into(), "show".into(), "old".into()],
+            old_identity_file: None,
+        };
+        let flags = [
+            "--old-password-cmd",
+            "pass",
+            "--old-password-cmd",
+            "show",
+            "--old-password-cmd",
+            "old",
+        ];
+
+        let mut before = vec!["hoard", "rekey"];
+        before.extend(flags);
+        before.push("myhoard");
+        let command = Command::from_iter_safe(before).expect("flags before the name should parse");
+        assert_eq!(command, expected);
+
+        let mut after = vec!["hoard", "rekey", "myhoard"];
+        after.extend(flags);
+        let command = Command::from_iter_safe(after).expect("flags after the name should parse");
+        assert_eq!(command, expected);
+    }
 }
@@ -61,6 +61,12 @@ impl Pile {
     pub(crate) fn layer_config(&mut self, config: Option<&PileConfig>) {
         PileConfig::layer_options(&mut self.config, config);
     }
+
+    fn set_config_location(&mut self, location: &str) {
+        if let Some(config) = &mut self.config {
+            config.set_location(location);
+        }
+    }
 }
 
 /// A set of multiple related piles (i.e. in a single hoard).
@@ -95,6 +101,15 @@ impl MultipleEntries {
     pub(crate) fn layer_config(&mut self, config: Option<&PileConfig>) {
         PileConfig::layer_options(&mut self.config, config);
     }
+
+    fn set_config_location(&mut self, hoard: &str) {
+        if let Some(config) = &mut self.config {
+            config.set_location(&format!("[hoards.{hoard}.config]"));
+        }
+        for (pile, entry) in &mut self.items {
+            entry.set_config_location(&format!("[hoards.{hoard}.{pile}.config]"));
+        }
+    }
 }
 
 /// A definition of a Hoard.
@@ -143,14 +158,26 @@ impl Hoard {
             Hoard::Multiple(multi) => multi.layer_config(config),
         }
     }
+
+    /// Records where the hoard named `name` and its piles were configured, so that errors in
+    /// their configuration can point to it. Must be called before layering any configs.
+    pub(crate) fn set_config_location(&mut self, name: &str) {
+        match self {
+            Hoard::Single(pile) => pile.set_config_location(&format!("[hoards.{name}.config]")),
+            Hoard::Multiple(multi) => multi.set_config_location(name),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::checkers::history::operation::ChecksumType;
     use crate::hoard::pile_config::{
-        AsymmetricEncryption, Config as PileConfig, Encryption, SymmetricEncryption,
+        AsymmetricEncryption, Config as PileConfig, ContentType, Encryption, RegexPattern,
+        Symlinks, SymmetricEncryption,
     };
+    use crate::hoard::Compression;
 
     mod config {
         use super::*;
@@ -170,6 +197,7 @@ mod tests {
                     "password".into(),
                 ))),
                 ignore: vec![glob::Pattern::new("ignore me").unwrap()],
+                ..PileConfig::default()
             });
             let old_specific = specific.clone();
             let general = None;
@@ -185,6 +213,7 @@ mod tests {
                     "password".into(),
                 ))),
                 ignore: vec![glob::Pattern::new("ignore me").unwrap()],
+                ..PileConfig::default()
             });
             PileConfig::layer_options(&mut specific, general.as_ref());
             assert_eq!(specific, general);
@@ -200,16 +229,34 @@ mod tests {
                     glob::Pattern::new("ignore me").unwrap(),
                     glob::Pattern::new("duplicate").unwrap(),
                 ],
+                ..PileConfig::default()
             });
             let old_specific = specific.clone();
             let general = Some(PileConfig {
                 encryption: Some(Encryption::Asymmetric(AsymmetricEncryption {
-                    public_key: "somekey".into(),
+                    public_keys: vec!["somekey".into()],
+                    identity_file: None,
                 })),
                 ignore: vec![
                     glob::Pattern::new("me too").unwrap(),
                     glob::Pattern::new("duplicate").unwrap(),
                 ],
+                include: vec![glob::Pattern::new("*.sav").unwrap()],
+                checksum_type: Some(ChecksumType::BLAKE3),
+                propagate_deletions: Some(false),
+                symlinks: Some(Symlinks::Preserve),
+                preserve_ownership: Some(true),
+                preserve_xattrs: None,
+                versions: Some(3),
+                compress: Some(Compression::Zstd),
+                max_file_size: Some(1024),
+                min_file_size: None,
+                modified_within: Some(std::time::Duration::from_secs(60)),
+                modified_before: None,
+                ignore_regex: vec![RegexPattern::new("^tmp-")],
+                include_regex: Vec::new(),
+                ignore_content_types: vec![ContentType::Sqlite],
+                include_content_types: Vec::new(),
             });
             PileConfig::layer_options(&mut specific, general.as_ref());
             assert!(specific.is_some());
@@ -217,6 +264,34 @@ mod tests {
                 specific.as_ref().unwrap().encryption,
                 old_specific.unwrap().encryption
             );
+            assert_eq!(
+                specific.as_ref().unwrap().checksum_type,
+                Some(ChecksumType::BLAKE3)
+            );
+            assert_eq!(
+                specific.as_ref().unwrap().symlinks,
+                Some(Symlinks::Preserve)
+            );
+            assert_eq!(specific.as_ref().unwrap().preserve_ownership, Some(true));
+            assert_eq!(specific.as_ref().unwrap().versions, Some(3));
+            assert_eq!(specific.as_ref().unwrap().compress, Some(Compression::Zstd));
+            assert_eq!(specific.as_ref().unwrap().max_file_size, Some(1024));
+            assert_eq!(
+                specific.as_ref().unwrap().modified_within,
+                Some(std::time::Duration::from_secs(60))
+            );
+            assert_eq!(
+                specific.as_ref().unwrap().include,
+                vec![glob::Pattern::new("*.sav").unwrap()]
+            );
+            assert_eq!(
+                specific.as_ref().unwrap().ignore_content_types,
+                vec![ContentType::Sqlite]
+            );
+            assert_eq!(
+                specific.as_ref().unwrap().ignore_regex,
+                vec![RegexPattern::new("^tmp-")]
+            );
             assert_eq!(
                 specific.unwrap().ignore,
                 vec![
@@ -262,7 +337,7 @@ mod tests {
     mod serde {
         use super::*;
         use maplit::hashmap;
-        use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+        use serde_test::{assert_de_tokens, assert_de_tokens_error, assert_tokens, Token};
 
         #[test]
         fn single_entry_no_config() {
@@ -291,9 +366,11 @@ mod tests {
             let hoard = Hoard::Single(Pile {
                 config: Some(PileConfig {
                     encryption: Some(Encryption::Asymmetric(AsymmetricEncryption {
-                        public_key: "public key".to_string(),
+                        public_keys: vec!["public key".to_string()],
+                        identity_file: Some("${HOME}/identity.txt".to_string()),
                     })),
                     ignore: Vec::new(),
+                    ..PileConfig::default()
                 }),
                 items: hashmap! {
                     "bar_env|foo_env".to_string() => "/some/path".to_string()
@@ -314,12 +391,17 @@ mod tests {
                     Token::Some,
                     Token::Struct {
                         name: "AsymmetricEncryption",
-                        len: 2,
+                        len: 3,
                     },
                     Token::Str("type"),
                     Token::Str("asymmetric"),
-                    Token::Str("public_key"),
+                    Token::Str("public_keys"),
+                    Token::Seq { len: Some(1) },
                     Token::Str("public key"),
+                    Token::SeqEnd,
+                    Token::Str("identity_file"),
+                    Token::Some,
+                    Token::Str("${HOME}/identity.txt"),
                     Token::StructEnd,
                     Token::Str("ignore"),
                     Token::Seq { len: Some(0) },
@@ -332,6 +414,26 @@ mod tests {
             );
         }
 
+        #[test]
+        fn single_public_key() {
+            let expected = Some(Encryption::Asymmetric(AsymmetricEncryption {
+                public_keys: vec!["age1key".to_string()],
+                identity_file: None,
+            }));
+            for name in ["public_key", "public_keys"] {
+                let config: PileConfig = toml::from_str(&format!(
+                    "encrypt = {{ type = \"asymmetric\", {name} = \"age1key\" }}"
+                ))
+                .expect("a single public key should parse");
+                assert_eq!(config.encryption, expected);
+            }
+
+            let config: PileConfig =
+                toml::from_str("encrypt = { type = \"asymmetric\", public_key = [\"age1key\"] }")
+                    .expect("a list of public keys should parse");
+            assert_eq!(config.encryption, expected);
+        }
+
         #[test]
         fn multiple_entry_no_config() {
             let hoard = Hoard::Multiple(MultipleEntries {
@@ -372,6 +474,7 @@ mod tests {
                         "correcthorsebatterystaple".into(),
                     ))),
                     ignore: Vec::new(),
+                    ..PileConfig::default()
                 }),
                 items: hashmap! {
                     "item1".to_string() => Pile {
@@ -446,6 +549,7 @@ mod tests {
                     glob::Pattern::new("**/valid*").unwrap(),
                     glob::Pattern::new("*/also_valid/**").unwrap(),
                 ],
+                ..PileConfig::default()
             };
 
             assert_tokens::<PileConfig>(
@@ -466,5 +570,44 @@ mod tests {
                 ],
             );
         }
+
+        #[test]
+        fn test_file_sizes_and_durations() {
+            let config = PileConfig {
+                max_file_size: Some(10 * 1024 * 1024),
+                min_file_size: Some(1),
+                modified_within: Some(std::time::Duration::from_secs(2 * 24 * 60 * 60)),
+                ..PileConfig::default()
+            };
+
+            assert_de_tokens::<PileConfig>(
+                &config,
+                &[
+                    Token::Struct {
+                        name: "Config",
+                        len: 3,
+                    },
+                    Token::Str("max_file_size"),
+                    Token::Str("10 MiB"),
+                    Token::Str("min_file_size"),
+                    Token::U64(1),
+                    Token::Str("modified_within"),
+                    Token::Str("2days"),
+                    Token::StructEnd,
+                ],
+            );
+            assert_de_tokens_error::<PileConfig>(
+                &[
+                    Token::Struct {
+                        name: "Config",
+                        len: 1,
+                    },
+                    Token::Str("modified_before"),
+                    Token::Str("a while"),
+                    Token::StructEnd,
+                ],
+                "invalid duration \"a while\": expected number at 0",
+            );
+        }
     }
 }
@@ -3,6 +3,7 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 
 use serde::de::DeserializeOwned;
@@ -18,7 +19,8 @@ use crate::CONFIG_FILE_STEM;
 use crate::HOARDS_DIR_SLUG;
 
 use super::Config;
-use crate::hoard::PileConfig;
+use crate::git::Config as GitConfig;
+use crate::hoard::{Layout, PileConfig, StorageBackend};
 
 pub mod environment;
 pub mod envtrie;
@@ -144,6 +146,18 @@ pub struct Builder {
     #[serde(skip)]
     #[structopt(short, long)]
     force: bool,
+    #[serde(skip)]
+    #[structopt(long)]
+    dry_run: bool,
+    #[serde(skip)]
+    #[structopt(short, long)]
+    jobs: Option<NonZeroUsize>,
+    #[structopt(skip)]
+    layout: Option<Layout>,
+    #[structopt(skip)]
+    storage: Option<StorageBackend>,
+    #[structopt(skip)]
+    git: Option<GitConfig>,
     #[structopt(skip)]
     hoards: Option<HashMap<String, Hoard>>,
     #[structopt(skip)]
@@ -187,6 +201,11 @@ impl Builder {
             environments: None,
             exclusivity: None,
             force: false,
+            dry_run: false,
+            jobs: None,
+            layout: None,
+            storage: None,
+            git: None,
             global_config: None,
         }
     }
@@ -360,6 +379,8 @@ impl Builder {
         }
 
         self.force = self.force || other.force;
+        self.dry_run = self.dry_run || other.dry_run;
+        self.jobs = self.jobs.or(other.jobs);
 
         self
     }
@@ -462,10 +483,24 @@ impl Builder {
         tracing::debug!(?command);
         let force = self.force;
         tracing::debug!(?force);
-
+        let dry_run = self.dry_run;
+        tracing::debug!(?dry_run);
+        let jobs = self.jobs.unwrap_or(NonZeroUsize::MIN);
+        tracing::debug!(?jobs);
+        let layout = self.layout.unwrap_or_default();
+        tracing::debug!(?layout);
+        let storage = self.storage.unwrap_or_default();
+        tracing::debug!(?storage);
+        let git = self.git;
+        tracing::debug!(?git);
+
+        if let Some(global_config) = &mut self.global_config {
+            global_config.set_location("[config]");
+        }
         if let Some(hoards) = &mut self.hoards {
             tracing::debug!("layering global config onto hoards");
-            for (_, hoard) in hoards.iter_mut() {
+            for (name, hoard) in hoards.iter_mut() {
+                hoard.set_config_location(name);
                 hoard.layer_config(self.global_config.as_ref());
             }
         }
@@ -488,6 +523,11 @@ impl Builder {
             config_file,
             hoards,
             force,
+            dry_run,
+            jobs,
+            layout,
+            storage,
+            git,
         })
     }
 }
@@ -508,6 +548,11 @@ mod tests {
                 exclusivity: None,
                 hoards: None,
                 force: false,
+                dry_run: false,
+                jobs: None,
+                layout: None,
+                storage: None,
+                git: None,
                 global_config: None,
             }
         }
@@ -518,11 +563,17 @@ mod tests {
                 config_file: Some(PathBuf::from("/testing/config.toml")),
                 command: Some(Command::Restore {
                     hoards: vec!["test".into()],
+                    version: None,
                 }),
                 environments: None,
                 exclusivity: None,
                 hoards: None,
                 force: false,
+                dry_run: false,
+                jobs: None,
+                layout: None,
+                storage: None,
+                git: None,
                 global_config: None,
             }
         }
@@ -542,6 +593,11 @@ mod tests {
                 hoards: None,
                 exclusivity: None,
                 force: false,
+                dry_run: false,
+                jobs: None,
+                layout: None,
+                storage: None,
+                git: None,
                 global_config: None,
             };
 
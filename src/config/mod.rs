@@ -1,16 +1,30 @@
 //! See [`Config`].
 
 pub use self::builder::Builder;
+use crate::archive;
 use crate::checkers::history::last_paths::{Error as LastPathsError, LastPaths};
 use crate::checkers::history::operation::{Error as HoardOperationError, HoardOperation};
+use crate::checkers::history::{
+    get_history_dirs_not_for_id, get_history_root_dir, get_or_generate_uuid, HISTORY_DIR_NAME,
+};
 use crate::checkers::Checker;
 use crate::command::{Command, EditError};
+use crate::git::{self, Config as GitConfig};
+use crate::hoard::content_addressed::{self, Checkout};
 use crate::hoard::iter::{DiffSource, HoardDiff, HoardFilesIter};
-use crate::hoard::{self, Direction, Hoard};
+use crate::hoard::run;
+use crate::hoard::versions::{self, Snapshot};
+use crate::hoard::{
+    self, CopyRun, Direction, Directory, Hoard, Layout, PasswordCache, Pile, PreviousKeys,
+    S3Config, Storage, StorageBackend, StorageError, S3,
+};
 use directories::ProjectDirs;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
+use time::OffsetDateTime;
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
@@ -46,6 +60,23 @@ pub enum Error {
     /// The requested hoard does not exist.
     #[error("no such hoard is configured: {0}")]
     NoSuchHoard(String),
+    /// The requested snapshot does not exist for a hoard.
+    #[error("no snapshot {version} exists for {name} (see `hoard versions {name}`)")]
+    NoSuchVersion {
+        /// The name of the hoard.
+        name: String,
+        /// The requested snapshot timestamp.
+        version: String,
+    },
+    /// Error occurred while listing or pruning the snapshots of a hoard.
+    #[error("failed to manage snapshots of {name}: {error}")]
+    Versions {
+        /// The name of the hoard.
+        name: String,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
     /// Error occurred while restoring a hoard.
     #[error("failed to back up {name}: {error}")]
     Restore {
@@ -55,6 +86,15 @@ pub enum Error {
         #[source]
         error: hoard::Error,
     },
+    /// Error occurred while re-encrypting a hoard.
+    #[error("failed to re-encrypt {name}: {error}")]
+    Rekey {
+        /// The name of the hoard that failed to re-encrypt.
+        name: String,
+        /// The error that occurred.
+        #[source]
+        error: hoard::Error,
+    },
     /// An error occurred while comparing paths for this run to the previous one.
     #[error("error while comparing previous run to current run: {0}")]
     LastPaths(#[from] LastPathsError),
@@ -72,7 +112,22 @@ pub enum Error {
     },
     /// An error occurred while diffing files.
     #[error("error while diffing files: {0}")]
-    Diff(#[from] crate::hoard::iter::Error),
+    Diff(#[from] hoard::iter::Error),
+    /// An error occurred while working with content-addressed storage.
+    #[error("error in content-addressed storage: {0}")]
+    ContentAddressed(#[from] content_addressed::Error),
+    /// An error occurred while synchronizing with the configured storage.
+    #[error("error while synchronizing storage: {0}")]
+    Storage(#[from] StorageError),
+    /// An error occurred while exporting or importing an archive.
+    #[error("error in archive: {0}")]
+    Archive(#[from] archive::Error),
+    /// An error occurred while running git.
+    #[error("git error: {0}")]
+    Git(#[from] git::Error),
+    /// `hoard pull` or `hoard push` was run without a `[git]` section in the configuration.
+    #[error("no git repository is configured for the hoards root")]
+    NoGitConfig,
     /// An error occurred while creating the [`HoardFilesIter`].
     #[error("error creating file iterator: {0}")]
     Iterator(#[from] crate::filters::Error),
@@ -93,6 +148,16 @@ pub struct Config {
     hoards: HashMap<String, Hoard>,
     /// Whether to force the operation to continue despite possible inconsistencies.
     force: bool,
+    /// Whether to only report the changes an operation would make.
+    dry_run: bool,
+    /// The maximum number of piles to copy concurrently.
+    jobs: NonZeroUsize,
+    /// How files are laid out in the hoards root.
+    layout: Layout,
+    /// Where the hoards root is stored.
+    storage: StorageBackend,
+    /// The git repository the hoards root is committed to, if any.
+    git: Option<GitConfig>,
 }
 
 impl Default for Config {
@@ -169,12 +234,42 @@ impl Config {
         self.hoards_root.join(name)
     }
 
+    /// Returns [`Error::NoSuchVersion`] if the hoard `name` has no snapshot named `version`.
+    fn check_version_exists(&self, name: &str, version: &str) -> Result<(), Error> {
+        let snapshots =
+            versions::list(&self.hoards_root, name).map_err(|error| Error::Versions {
+                name: name.to_owned(),
+                error,
+            })?;
+        if snapshots.iter().any(|snapshot| snapshot == version) {
+            Ok(())
+        } else {
+            Err(Error::NoSuchVersion {
+                name: name.to_owned(),
+                version: version.to_owned(),
+            })
+        }
+    }
+
     fn get_hoard<'a>(&'a self, name: &'_ str) -> Result<&'a Hoard, Error> {
         self.hoards
             .get(name)
             .ok_or_else(|| Error::NoSuchHoard(name.to_owned()))
     }
 
+    /// Returns the configured names of the hoards in the archive at `path`.
+    fn get_archived_hoards(&self, path: &Path) -> Result<Vec<&str>, Error> {
+        archive::hoard_names(path)?
+            .into_iter()
+            .map(|name| {
+                self.hoards
+                    .get_key_value(&name)
+                    .map(|(name, _)| name.as_str())
+                    .ok_or(Error::NoSuchHoard(name))
+            })
+            .collect()
+    }
+
     #[allow(dead_code)]
     fn iter_hoard_files(&self, name: &str, direction: Direction) -> Result<HoardFilesIter, Error> {
         let hoard = self.get_hoard(name)?;
@@ -183,11 +278,267 @@ impl Config {
         HoardFilesIter::new(&hoards_root, direction, name, hoard).map_err(Error::from)
     }
 
-    fn hoard_file_diffs(&self, name: &str) -> Result<Vec<HoardDiff>, Error> {
+    fn hoard_file_diffs(
+        &self,
+        name: &str,
+        passwords: &PasswordCache,
+    ) -> Result<Vec<HoardDiff>, Error> {
         let hoard = self.get_hoard(name)?;
         let hoards_root = self.get_hoards_root_path();
 
-        HoardFilesIter::file_diffs(&hoards_root, name, hoard).map_err(Error::from)
+        HoardFilesIter::file_diffs(&hoards_root, name, hoard, passwords).map_err(Error::from)
+    }
+
+    /// Returns the files that were intentionally deleted on the other side and should be deleted
+    /// while backing up or restoring the hoard `name`.
+    ///
+    /// Each item is the root directory of the relevant pile and the path of the file to delete.
+    /// The content of stored files is never read, so no keys are needed to decrypt them.
+    fn deletions(
+        &self,
+        name: &str,
+        hoard: &Hoard,
+        direction: Direction,
+    ) -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+        let source = match direction {
+            Direction::Backup => DiffSource::Local,
+            Direction::Restore => DiffSource::Remote,
+        };
+        let prefix = self.get_prefix(name);
+
+        let deletions = HoardFilesIter::deletions(&self.hoards_root, name, hoard)?
+            .into_iter()
+            .filter_map(|hoard_diff| match hoard_diff {
+                HoardDiff::Deleted {
+                    path,
+                    hoard_path,
+                    pile_name,
+                    diff_source,
+                } if diff_source == source => {
+                    let pile = hoard.pile(pile_name.as_deref())?;
+                    if !pile.propagates_deletions() {
+                        tracing::debug!(?path, "not propagating deletion for pile");
+                        return None;
+                    }
+                    match direction {
+                        Direction::Backup => {
+                            let root =
+                                pile_name.map_or_else(|| prefix.clone(), |name| prefix.join(name));
+                            Some((root, hoard_path))
+                        }
+                        Direction::Restore => pile.path.clone().map(|root| (root, path)),
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+
+        Ok(deletions)
+    }
+
+    /// Returns the names of the hoards the stored [`Command`] works with in the hoards root,
+    /// and whether it changes them.
+    fn affected_hoards(&self) -> Result<Option<(Vec<&str>, bool)>, Error> {
+        let affected = match &self.command {
+            Command::Backup { hoards } => Some((
+                self.get_hoards(hoards)?.into_keys().collect(),
+                !self.dry_run,
+            )),
+            Command::Restore { hoards, .. } | Command::Export { hoards, .. } => {
+                Some((self.get_hoards(hoards)?.into_keys().collect(), false))
+            }
+            Command::Import { archive } => {
+                Some((self.get_archived_hoards(archive)?, !self.dry_run))
+            }
+            Command::Status => Some((self.hoards.keys().map(String::as_str).collect(), false)),
+            Command::Diff { hoard, .. } | Command::Versions { hoard } => {
+                Some((vec![hoard.as_str()], false))
+            }
+            Command::Rekey { hoard, .. } => Some((vec![hoard.as_str()], true)),
+            Command::Validate
+            | Command::Cleanup
+            | Command::Gc
+            | Command::List
+            | Command::Edit
+            | Command::Pull
+            | Command::Push => None,
+        };
+        Ok(affected)
+    }
+
+    /// Commits the files stored for the hoards `names` and this system's operation logs to the
+    /// configured git repository, if any, after they were changed in `direction`.
+    fn commit_to_git(&self, names: &[&str], direction: Direction) -> Result<(), Error> {
+        if self.git.is_none() {
+            return Ok(());
+        }
+
+        let id = get_or_generate_uuid().map_err(HoardOperationError::from)?;
+        let mut paths: Vec<PathBuf> = names
+            .iter()
+            .flat_map(|name| hoard::stored_paths(&self.hoards_root, name))
+            .collect();
+        if self.layout == Layout::ContentAddressed {
+            paths.push(self.hoards_root.join(content_addressed::OBJECTS_DIR));
+        }
+        paths.push(get_history_root_dir().join(id.to_string()));
+
+        let action = match direction {
+            Direction::Backup => "Back up",
+            Direction::Restore => "Restore",
+        };
+        let message = format!("{} {}\n\nSystem: {}", action, names.join(", "), id);
+        git::commit(&self.hoards_root, &paths, &message)?;
+        Ok(())
+    }
+
+    /// Runs the stored [`Command`] on a checkout of the content-addressed hoards `names`,
+    /// packing them back into the hoards root afterwards if `commit` is `true`.
+    fn run_checked_out(&self, names: &[&str], commit: bool) -> Result<(), Error> {
+        let dir = get_dirs()
+            .data_dir()
+            .join(format!("checkout-{}", std::process::id()));
+        let checkout = Checkout::new(&self.hoards_root, &dir, names)?;
+        let checked_out = Self {
+            hoards_root: checkout.path().to_owned(),
+            layout: Layout::Directory,
+            git: None,
+            ..self.clone()
+        };
+        checked_out.run()?;
+
+        if commit {
+            // Piles in snapshots are packed like the hoard itself, so unchanged files are shared.
+            let mut piles = HashMap::new();
+            for name in names {
+                let hoard = self.get_hoard(name)?;
+                let prefix = checked_out.get_prefix(name);
+                let snapshots =
+                    versions::list(checkout.path(), name).map_err(|error| Error::Versions {
+                        name: (*name).to_string(),
+                        error,
+                    })?;
+                let prefixes = snapshots.iter().map(|snapshot| {
+                    Snapshot::existing(checkout.path(), name, snapshot).path_for(&prefix)
+                });
+                for prefix in prefixes.chain(Some(prefix.clone())) {
+                    for (_, pile, pile_prefix) in hoard.piles(&prefix) {
+                        piles.insert(pile_prefix, pile.checksum_type());
+                    }
+                }
+            }
+            checkout.commit(&piles)?;
+        }
+
+        match &self.command {
+            Command::Backup { .. } if !self.dry_run => {
+                self.commit_to_git(names, Direction::Backup)?;
+            }
+            Command::Restore { .. } if !self.dry_run => {
+                self.commit_to_git(names, Direction::Restore)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether the stored [`Command`] reads the hoards root or operation logs, and
+    /// whether it changes them.
+    fn storage_access(&self) -> (bool, bool) {
+        match &self.command {
+            // The repository is in the configured hoards root, and status is shown by running
+            // the status command afterwards.
+            Command::Validate | Command::List | Command::Edit | Command::Pull | Command::Push => {
+                (false, false)
+            }
+            Command::Status
+            | Command::Diff { .. }
+            | Command::Versions { .. }
+            | Command::Export { .. } => (true, false),
+            Command::Backup { .. }
+            | Command::Restore { .. }
+            | Command::Import { .. }
+            | Command::Rekey { .. }
+            | Command::Gc
+            | Command::Cleanup => (true, !self.dry_run),
+        }
+    }
+
+    /// Runs the stored [`Command`] on a local copy of the hoards root stored in the S3 bucket
+    /// configured by `config`.
+    ///
+    /// The local copy and the operation logs of other systems are updated from the bucket
+    /// first. Afterwards, changes to the hoards root and this system's operation logs are
+    /// uploaded, if the command makes any.
+    fn run_with_s3(&self, config: &S3Config) -> Result<(), Error> {
+        let (reads, writes) = self.storage_access();
+        if !reads {
+            return self.run_locally();
+        }
+
+        let remote = S3::new(config.clone())?;
+        let cache = Directory::new(get_dirs().cache_dir().join("s3"));
+        let data = Directory::new(get_dirs().data_dir().to_owned());
+        let hoards = Path::new(crate::HOARDS_DIR_SLUG);
+        let history = Path::new(HISTORY_DIR_NAME);
+        let id = get_or_generate_uuid().map_err(|error| StorageError::IO {
+            path: history.to_owned(),
+            error,
+        })?;
+        let own_logs = history.join(id.to_string());
+
+        // Other systems' logs are found both remotely and locally, to delete stale copies.
+        let mut other_logs: Vec<PathBuf> = remote
+            .list(history)
+            .map_err(|error| StorageError::IO {
+                path: history.to_owned(),
+                error,
+            })?
+            .into_iter()
+            .filter_map(|path| path.iter().nth(1).map(|id| history.join(id)))
+            .chain(
+                get_history_dirs_not_for_id(&id)
+                    .map_err(|error| StorageError::IO {
+                        path: history.to_owned(),
+                        error,
+                    })?
+                    .into_iter()
+                    .filter_map(|path| path.file_name().map(|id| history.join(id))),
+            )
+            .filter(|path| *path != own_logs)
+            .collect();
+        other_logs.sort();
+        other_logs.dedup();
+
+        let mut pulled = hoard::mirror(&remote, &cache, hoards)?;
+        for logs in &other_logs {
+            pulled += hoard::mirror(&remote, &data, logs)?;
+        }
+        tracing::debug!(count = pulled, "pulled changes from S3");
+
+        Self {
+            hoards_root: cache.root().join(hoards),
+            storage: StorageBackend::Directory,
+            git: None,
+            ..self.clone()
+        }
+        .run()?;
+
+        if writes {
+            let mut pushed = hoard::mirror(&cache, &remote, hoards)?;
+            pushed += hoard::mirror(&data, &remote, &own_logs)?;
+            // Cleaning up removes old logs from every system, not just this one, and importing
+            // may add logs for any system.
+            if matches!(self.command, Command::Cleanup | Command::Import { .. }) {
+                for logs in &other_logs {
+                    pushed += hoard::mirror(&data, &remote, logs)?;
+                }
+            }
+            tracing::debug!(count = pushed, "pushed changes to S3");
+        }
+
+        Ok(())
     }
 
     /// Run the stored [`Command`] using this [`Config`].
@@ -196,13 +547,29 @@ impl Config {
     ///
     /// Any [`enum@Error`] that might happen while running the command.
     pub fn run(&self) -> Result<(), Error> {
-        #![allow(clippy::too_many_lines)]
         tracing::trace!(command = ?self.command, "running command");
+        if let StorageBackend::S3(config) = &self.storage {
+            return self.run_with_s3(config);
+        }
+        self.run_locally()
+    }
+
+    /// Runs the stored [`Command`] on the hoards root directory.
+    fn run_locally(&self) -> Result<(), Error> {
+        #![allow(clippy::too_many_lines)]
+        if self.layout == Layout::ContentAddressed {
+            if let Some((names, commit)) = self.affected_hoards()? {
+                return self.run_checked_out(&names, commit);
+            }
+        }
+
+        // Passwords from `password_cmd`s are only fetched once per run.
+        let passwords = PasswordCache::default();
         match &self.command {
             Command::Status => {
                 for hoard in self.hoards.keys() {
                     let source = self
-                        .hoard_file_diffs(hoard)?
+                        .hoard_file_diffs(hoard, &passwords)?
                         .into_iter()
                         .map(|hoard_diff| {
                             #[allow(clippy::match_same_arms)]
@@ -210,6 +577,7 @@ impl Config {
                                 HoardDiff::BinaryModified { diff_source, .. } => diff_source,
                                 HoardDiff::TextModified { diff_source, .. } => diff_source,
                                 HoardDiff::PermissionsModified { diff_source, .. } => diff_source,
+                                HoardDiff::MetadataModified { diff_source, .. } => diff_source,
                                 HoardDiff::Created { diff_source, .. } => diff_source,
                                 HoardDiff::Recreated { diff_source, .. } => diff_source,
                                 HoardDiff::Deleted { diff_source, .. } => diff_source,
@@ -237,7 +605,7 @@ impl Config {
                 }
             }
             Command::Diff { hoard, verbose } => {
-                for hoard_diff in self.hoard_file_diffs(hoard)? {
+                for hoard_diff in self.hoard_file_diffs(hoard, &passwords)? {
                     match hoard_diff {
                         HoardDiff::BinaryModified { path, diff_source } => {
                             tracing::info!(
@@ -285,13 +653,27 @@ impl Config {
                                 },
                             );
                         }
+                        HoardDiff::MetadataModified {
+                            path,
+                            changes,
+                            diff_source,
+                        } => {
+                            tracing::info!(
+                                "{}: {} changed {}",
+                                path.display(),
+                                changes.join(", "),
+                                diff_source
+                            );
+                        }
                         HoardDiff::Created { path, diff_source } => {
                             tracing::info!("{}: created {}", path.display(), diff_source);
                         }
                         HoardDiff::Recreated { path, diff_source } => {
                             tracing::info!("{}: recreated {}", path.display(), diff_source);
                         }
-                        HoardDiff::Deleted { path, diff_source } => {
+                        HoardDiff::Deleted {
+                            path, diff_source, ..
+                        } => {
                             tracing::info!("{}: deleted {}", path.display(), diff_source);
                         }
                     }
@@ -321,44 +703,248 @@ impl Config {
                     });
                 }
             },
-            Command::Backup { hoards } | Command::Restore { hoards } => {
+            Command::Rekey {
+                hoard: name,
+                old_password_cmd,
+                old_identity_file,
+            } => {
+                let hoard = self.get_hoard(name)?;
+                let rekey_err = |error| Error::Rekey {
+                    name: name.clone(),
+                    error,
+                };
+                let previous = PreviousKeys::new(
+                    (!old_password_cmd.is_empty()).then_some(old_password_cmd.as_slice()),
+                    old_identity_file.clone(),
+                    &passwords,
+                )
+                .map_err(|error| rekey_err(error.into()))?;
+
+                tracing::info!(hoard = %name, "re-encrypting");
+                let _span = tracing::info_span!("rekey", hoard = %name).entered();
+                hoard
+                    .rekey(&self.get_prefix(name), &previous, &passwords)
+                    .map_err(rekey_err)?;
+
+                // Keep saved versions restorable with the new encryption settings.
+                let snapshots =
+                    versions::list(&self.hoards_root, name).map_err(|error| Error::Versions {
+                        name: name.clone(),
+                        error,
+                    })?;
+                for snapshot in snapshots {
+                    let prefix = Snapshot::existing(&self.hoards_root, name, &snapshot)
+                        .path_for(&self.get_prefix(name));
+                    for (_, pile, prefix) in hoard.piles(&prefix) {
+                        if prefix.exists() {
+                            pile.rekey(&prefix, &previous, &passwords)
+                                .map_err(rekey_err)?;
+                        }
+                    }
+                }
+            }
+            Command::Gc => {
+                let dry_run = self.dry_run.then(hoard::DryRun::default);
+                let count =
+                    content_addressed::collect_garbage(&self.hoards_root, dry_run.as_ref())?;
+                if dry_run.is_some() {
+                    tracing::info!("would remove {} unreferenced object(s)", count);
+                } else {
+                    tracing::info!("removed {} unreferenced object(s)", count);
+                }
+            }
+            Command::Export { hoards, output } => {
                 let hoards = self.get_hoards(hoards)?;
-                let direction = match self.command {
-                    Command::Backup { .. } => Direction::Backup,
-                    Command::Restore { .. } => Direction::Restore,
+                let mut names: Vec<&str> = hoards.into_keys().collect();
+                names.sort_unstable();
+                archive::export(&self.hoards_root, &get_history_root_dir(), &names, output)?;
+                tracing::info!("exported {} to {}", names.join(", "), output.display());
+            }
+            Command::Import { archive: path } => {
+                let names = self.get_archived_hoards(path)?;
+                archive::import(
+                    &self.hoards_root,
+                    &get_history_root_dir(),
+                    path,
+                    self.force,
+                    self.dry_run,
+                )?;
+                if !self.dry_run {
+                    tracing::info!("imported {} from {}", names.join(", "), path.display());
+                }
+            }
+            Command::Pull | Command::Push => {
+                let git = self.git.as_ref().ok_or(Error::NoGitConfig)?;
+                if matches!(self.command, Command::Pull) {
+                    git.pull(&self.hoards_root)?;
+                } else {
+                    git.push(&self.hoards_root)?;
+                }
+                Self {
+                    command: Command::Status,
+                    ..self.clone()
+                }
+                .run()?;
+            }
+            Command::Versions { hoard: name } => {
+                self.get_hoard(name)?;
+                let snapshots =
+                    versions::list(&self.hoards_root, name).map_err(|error| Error::Versions {
+                        name: name.clone(),
+                        error,
+                    })?;
+                if snapshots.is_empty() {
+                    tracing::info!("no snapshots of {} found", name);
+                } else {
+                    tracing::info!("{}", snapshots.join("\n"));
+                }
+            }
+            Command::Backup { hoards } | Command::Restore { hoards, .. } => {
+                let hoards = self.get_hoards(hoards)?;
+                let (direction, version) = match &self.command {
+                    Command::Backup { .. } => (Direction::Backup, None),
+                    Command::Restore { version, .. } => (Direction::Restore, version.as_deref()),
                     // Only Command::Backup and Command::Restore should be possible
                     _ => return Ok(()),
                 };
 
+                if let Some(version) = version {
+                    for name in hoards.keys() {
+                        self.check_version_exists(name, version)?;
+                    }
+                }
+
                 let mut checkers = Checkers::new(&hoards, direction)?;
                 if !self.force {
                     checkers.check()?;
                 }
 
-                for (name, hoard) in hoards {
-                    let prefix = self.get_prefix(name);
+                let mut run = CopyRun::new(self.dry_run, self.jobs);
+                if direction == Direction::Backup {
+                    for (name, hoard) in &hoards {
+                        run.mark_unchanged(checkers.unchanged_files(name, hoard)?);
+                    }
+                }
+                if direction == Direction::Backup && !self.dry_run {
+                    for (name, hoard) in &hoards {
+                        // Snapshots are named after the operation that replaced their files.
+                        let timestamp = checkers.timestamp(name);
+                        for (_, pile, prefix) in hoard.piles(&self.get_prefix(name)) {
+                            if pile.versions() > 0 {
+                                let snapshot = Snapshot::new(&self.hoards_root, name, timestamp)
+                                    .map_err(HoardOperationError::from)?;
+                                run.add_snapshot(prefix, snapshot);
+                            }
+                        }
+                    }
+                }
 
+                // Determine deletions before copying anything changes the state of the files.
+                // Restoring a snapshot only restores the files saved in it.
+                let mut deletions = HashMap::new();
+                if version.is_none() {
+                    for (name, hoard) in &hoards {
+                        deletions.insert(*name, self.deletions(name, hoard, direction)?);
+                    }
+                }
+
+                let copy_err = |name: &str, error| match direction {
+                    Direction::Backup => Error::Backup {
+                        name: name.to_string(),
+                        error,
+                    },
+                    Direction::Restore => Error::Restore {
+                        name: name.to_string(),
+                        error,
+                    },
+                };
+                let hoard_span = |name: &str| match direction {
+                    Direction::Backup => tracing::info_span!("backup", hoard = %name),
+                    Direction::Restore => tracing::info_span!("restore", hoard = %name),
+                };
+
+                let mut piles = Vec::new();
+                for (name, hoard) in &hoards {
                     match direction {
-                        Direction::Backup => {
-                            tracing::info!(hoard = %name, "backing up");
-                            let _span = tracing::info_span!("backup", hoard = %name).entered();
-                            hoard.backup(&prefix).map_err(|error| Error::Backup {
-                                name: name.to_string(),
-                                error,
+                        Direction::Backup => tracing::info!(hoard = %name, "backing up"),
+                        Direction::Restore => tracing::info!(hoard = %name, "restoring"),
+                    }
+                    let prefix = match version {
+                        None => self.get_prefix(name),
+                        Some(version) => Snapshot::existing(&self.hoards_root, name, version)
+                            .path_for(&self.get_prefix(name)),
+                    };
+                    for (pile_name, pile, prefix) in hoard.piles(&prefix) {
+                        // A snapshot only contains the piles that had files replaced or deleted.
+                        if version.is_some() && !prefix.exists() {
+                            continue;
+                        }
+                        piles.push((*name, pile_name, pile, prefix));
+                    }
+                }
+
+                // Piles that share files on the system, like a directory and a file inside it,
+                // are copied one after another. Other piles are copied concurrently.
+                let groups = run::group_overlapping(piles, |(_, _, pile, _)| pile.path.clone());
+                run.try_for_each(groups, |group| {
+                    group
+                        .into_iter()
+                        .try_for_each(|(name, pile_name, pile, prefix)| {
+                            let _span = hoard_span(name).entered();
+                            let _pile_span =
+                                pile_name.map(|pile| tracing::info_span!("pile", %pile).entered());
+                            match direction {
+                                Direction::Backup => pile.backup(&prefix, &passwords, &run),
+                                Direction::Restore => pile.restore(&prefix, &passwords, &run),
+                            }
+                            .map_err(|error| copy_err(name, error))
+                        })
+                })?;
+
+                // Only delete files once everything was copied successfully.
+                for (name, deletions) in deletions {
+                    let _span = hoard_span(name).entered();
+                    for (root, path) in deletions {
+                        if let Some(snapshot) = run.snapshot(&root) {
+                            snapshot.save(&root, &path).map_err(|error| {
+                                copy_err(
+                                    name,
+                                    hoard::Error::Snapshot {
+                                        path: path.clone(),
+                                        error,
+                                    },
+                                )
                             })?;
                         }
-                        Direction::Restore => {
-                            tracing::info!(hoard = %name, "restoring");
-                            let _span = tracing::info_span!("restore", hoard = %name).entered();
-                            hoard.restore(&prefix).map_err(|error| Error::Restore {
-                                name: name.to_string(),
-                                error,
+                        Pile::delete_file(&root, &path, run.dry_run())
+                            .map_err(|error| copy_err(name, error))?;
+                    }
+                }
+
+                if direction == Direction::Backup && !self.dry_run {
+                    for (name, hoard) in &hoards {
+                        let keep = hoard.versions();
+                        if keep > 0 {
+                            versions::prune(&self.hoards_root, name, keep).map_err(|error| {
+                                Error::Versions {
+                                    name: (*name).to_string(),
+                                    error,
+                                }
                             })?;
                         }
                     }
                 }
 
-                checkers.commit_to_disk()?;
+                tracing::info!("{}", run);
+
+                // Restoring a snapshot does not sync the system with the hoard, so it is not
+                // recorded as an operation.
+                if run.dry_run().is_none() && version.is_none() {
+                    checkers.commit_to_disk()?;
+                    let mut names: Vec<&str> = hoards.keys().copied().collect();
+                    names.sort_unstable();
+                    self.commit_to_git(&names, direction)?;
+                }
             }
         }
 
@@ -389,6 +975,49 @@ impl Checkers {
         })
     }
 
+    /// Returns the timestamp of the operation being performed on the hoard `name`.
+    fn timestamp(&self, name: &str) -> OffsetDateTime {
+        self.operations
+            .get(name)
+            .map_or_else(OffsetDateTime::now_utc, |operation| operation.timestamp)
+    }
+
+    /// Returns the system paths of files in `hoard` that are unchanged since the last backup
+    /// on this system, so backing them up can be skipped.
+    ///
+    /// Only backups record what is in the hoard: a restore records the files on the system before
+    /// they were restored. If a remote backup is more recent than the last local backup, the
+    /// files in the hoard may differ from the local ones, so no files are considered unchanged.
+    fn unchanged_files(&self, name: &str, hoard: &Hoard) -> Result<Vec<PathBuf>, Error> {
+        let Some(current) = self.operations.get(name) else {
+            return Ok(Vec::new());
+        };
+        let Some(previous) = HoardOperation::latest_local_backup(name, None)? else {
+            return Ok(Vec::new());
+        };
+        if let Some(remote) = HoardOperation::latest_remote_backup(name, None)? {
+            if remote.timestamp > previous.timestamp {
+                return Ok(Vec::new());
+            }
+        }
+
+        let paths = current
+            .unchanged_files(&previous)
+            .into_iter()
+            .filter_map(|(pile_name, rel_path)| {
+                let root = hoard.pile(pile_name)?.path.as_ref()?;
+                // Anonymous piles of a single file record it with an empty relative path.
+                Some(if rel_path.as_os_str().is_empty() {
+                    root.clone()
+                } else {
+                    root.join(rel_path)
+                })
+            })
+            .collect();
+
+        Ok(paths)
+    }
+
     fn check(&mut self) -> Result<(), Error> {
         let _span = tracing::info_span!("running_checks").entered();
         for last_path in &mut self.last_paths.values_mut() {
@@ -3,11 +3,15 @@
 //! Unified diffs are optionally available for text files. Following Git's example,
 //! non-text binary files can only be detected as differing or the same.
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
 use similar::{ChangeTag, TextDiff};
 
+use crate::hoard::encryption::Cipher;
+use crate::hoard::format::StoredFormat;
+use crate::hoard::symlinks::is_symlink;
+
 const CONTEXT_RADIUS: usize = 5;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -18,8 +22,9 @@ enum FileContent {
 }
 
 impl FileContent {
-    fn read(file: fs::File) -> io::Result<Self> {
-        let bytes: Vec<u8> = file.bytes().collect::<io::Result<_>>()?;
+    fn read(file: fs::File, format: StoredFormat, cipher: Option<&Cipher>) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        format.decoder(cipher, file)?.read_to_end(&mut bytes)?;
         match String::from_utf8(bytes) {
             Ok(s) => Ok(Self::Text(s)),
             Err(err) => Ok(Self::Binary(err.into_bytes())),
@@ -44,17 +49,24 @@ pub(crate) enum Diff {
     Binary,
     /// Content is the same, but permissions differ.
     Permissions(fs::Permissions, fs::Permissions),
+    /// Content and permissions are the same, but other preserved metadata differs. Contains
+    /// descriptions of what differs.
+    Metadata(Vec<&'static str>),
     /// The left path to diff_files did not exist, but the right path did.
     LeftNotExists,
     /// The left path to diff_paths existed, but the right path did not.
     RightNotExists,
 }
 
-fn content_and_meta_for(path: &Path) -> io::Result<(FileContent, Option<fs::Metadata>)> {
+fn content_and_meta_for(
+    path: &Path,
+    format: StoredFormat,
+    cipher: Option<&Cipher>,
+) -> io::Result<(FileContent, Option<fs::Metadata>)> {
     match fs::File::open(path) {
         Ok(file) => {
             let meta = file.metadata()?;
-            let content = FileContent::read(file)?;
+            let content = FileContent::read(file, format, cipher)?;
             Ok((content, Some(meta)))
         }
         Err(err) => match err.kind() {
@@ -64,9 +76,50 @@ fn content_and_meta_for(path: &Path) -> io::Result<(FileContent, Option<fs::Meta
     }
 }
 
-pub(crate) fn diff_files(left_path: &Path, right_path: &Path) -> io::Result<Option<Diff>> {
-    let (left, left_meta) = content_and_meta_for(left_path)?;
-    let (right, right_meta) = content_and_meta_for(right_path)?;
+/// Returns the target of the symbolic link at `path`, or `None` if nothing exists there.
+///
+/// Regular files are returned as an empty target so that they differ from any link.
+fn link_target(path: &Path) -> io::Result<Option<PathBuf>> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::read_link(path).map(Some),
+        Ok(_) => Ok(Some(PathBuf::new())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Diffs preserved symbolic links by the path they point to, rather than their content.
+fn diff_symlinks(left_path: &Path, right_path: &Path) -> io::Result<Option<Diff>> {
+    let diff = match (link_target(left_path)?, link_target(right_path)?) {
+        (None, None) => None,
+        (None, Some(_)) => Some(Diff::LeftNotExists),
+        (Some(_), None) => Some(Diff::RightNotExists),
+        (Some(left), Some(right)) => (left != right).then_some(Diff::Binary),
+    };
+    Ok(diff)
+}
+
+/// Diffs the files at `left_path` and `right_path`.
+///
+/// If `left_format` says that `left_path` (i.e. a file in an encrypted pile) is encrypted, it is
+/// decrypted with `left_cipher` so that the plaintext content is compared. Likewise, the content
+/// of `left_path` is decompressed if `left_format` says that it is compressed.
+///
+/// If `preserve_symlinks` is set, symbolic links are compared by their targets instead of being
+/// followed.
+pub(crate) fn diff_files(
+    left_path: &Path,
+    right_path: &Path,
+    left_format: StoredFormat,
+    left_cipher: Option<&Cipher>,
+    preserve_symlinks: bool,
+) -> io::Result<Option<Diff>> {
+    if preserve_symlinks && (is_symlink(left_path) || is_symlink(right_path)) {
+        return diff_symlinks(left_path, right_path);
+    }
+
+    let (left, left_meta) = content_and_meta_for(left_path, left_format, left_cipher)?;
+    let (right, right_meta) = content_and_meta_for(right_path, StoredFormat::default(), None)?;
 
     let permissions_diff = if let (Some(left_meta), Some(right_meta)) = (left_meta, right_meta) {
         let left_perms = left_meta.permissions();
@@ -116,13 +169,21 @@ pub(crate) fn diff_files(left_path: &Path, right_path: &Path) -> io::Result<Opti
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::hoard::Compression;
     use std::path::PathBuf;
 
     #[test]
     fn test_diff_non_existent_files() {
         let left_path = PathBuf::from("/does/not/exist");
         let right_path = PathBuf::from("/also/does/not/exist");
-        let diff = diff_files(&left_path, &right_path).expect("diff should not fail");
+        let diff = diff_files(
+            &left_path,
+            &right_path,
+            StoredFormat::default(),
+            None,
+            false,
+        )
+        .expect("diff should not fail");
 
         assert!(diff.is_none());
     }
@@ -149,4 +210,24 @@ mod test {
             assert_eq!(FileContent::Missing.into_bytes(), None);
         }
     }
+
+    #[test]
+    fn test_diff_compressed_file() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let left_path = dir.path().join("left");
+        let right_path = dir.path().join("right");
+        let compressed = zstd::encode_all(&b"same content\n"[..], 0).unwrap();
+        fs::write(&left_path, compressed).unwrap();
+        let format = StoredFormat::new(None, Compression::Zstd);
+        fs::write(&right_path, "same content\n").unwrap();
+
+        let diff =
+            diff_files(&left_path, &right_path, format, None, false).expect("diff should not fail");
+        assert!(diff.is_none());
+
+        fs::write(&right_path, "other content\n").unwrap();
+        let diff =
+            diff_files(&left_path, &right_path, format, None, false).expect("diff should not fail");
+        assert!(matches!(diff, Some(Diff::Text(_))));
+    }
 }
@@ -0,0 +1,104 @@
+//! Provides a [`Filter`] based on when files were last modified.
+//!
+//! To use this filter, set `modified_within` and/or `modified_before` under `config` to a
+//! duration. For example, to only back up files changed in the last month:
+//!
+//! ```ignore
+//! [config]
+//!     modified_within = "30days"
+//! ```
+use std::convert::Infallible;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::Filter;
+use crate::hoard::PileConfig;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct AgeFilter {
+    /// Files modified before this are skipped.
+    newer_than: Option<SystemTime>,
+    /// Files modified after this are skipped.
+    older_than: Option<SystemTime>,
+}
+
+impl AgeFilter {
+    /// Whether this filter keeps every file.
+    fn is_empty(&self) -> bool {
+        self.newer_than.is_none() && self.older_than.is_none()
+    }
+}
+
+impl Filter for AgeFilter {
+    type Error = Infallible;
+
+    fn new(pile_config: &PileConfig) -> Result<Self, Self::Error> {
+        // Use the same time for every file, however long the run takes.
+        let now = SystemTime::now();
+        Ok(Self {
+            newer_than: pile_config
+                .modified_within
+                .and_then(|age| now.checked_sub(age)),
+            older_than: pile_config
+                .modified_before
+                .and_then(|age| now.checked_sub(age)),
+        })
+    }
+
+    fn keep(&self, _prefix: &Path, path: &Path) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        // Not every platform records modification times, so files without one are always kept.
+        let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+            return true;
+        };
+        if self.newer_than.is_some_and(|time| modified < time) {
+            tracing::debug!(?path, "skipping file not modified within modified_within");
+            return false;
+        }
+        if self.older_than.is_some_and(|time| modified > time) {
+            tracing::debug!(?path, "skipping file modified after modified_before");
+            return false;
+        }
+        true
+    }
+
+    fn keep_dir(&self, _prefix: &Path, _path: &Path) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_age_filter() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+        fs::write(&new, "new").unwrap();
+        let file = fs::File::create(&old).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60 * 60 * 24 * 10))
+            .unwrap();
+
+        let config = PileConfig {
+            modified_within: Some(Duration::from_secs(60 * 60 * 24)),
+            ..PileConfig::default()
+        };
+        let filter = AgeFilter::new(&config).unwrap();
+        assert!(filter.keep(dir.path(), &new));
+        assert!(!filter.keep(dir.path(), &old));
+        assert!(filter.keep_dir(dir.path(), dir.path()));
+
+        let config = PileConfig {
+            modified_before: Some(Duration::from_secs(60 * 60 * 24)),
+            ..PileConfig::default()
+        };
+        let filter = AgeFilter::new(&config).unwrap();
+        assert!(!filter.keep(dir.path(), &new));
+        assert!(filter.keep(dir.path(), &old));
+    }
+}
@@ -0,0 +1,216 @@
+//! Provides a [`Filter`] based on the content of files.
+//!
+//! To use this filter, list content types in `ignore_content_types` and/or
+//! `include_content_types` under `config`. For example:
+//!
+//! ```ignore
+//! [config]
+//!     ignore_content_types = ["sqlite", "image"]
+//! ```
+//!
+//! The type of a file is detected from its first few kilobytes. Like diffs, files that are valid
+//! UTF-8 are text, and all other files are binary. Binary files are further identified by the
+//! magic bytes at their start. See [`ContentType`] for the supported types.
+use std::convert::Infallible;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::Filter;
+use crate::hoard::{ContentType, PileConfig};
+
+/// How many bytes from the start of a file are used to detect its type.
+const SNIFF_LEN: u64 = 8 * 1024;
+
+/// Bytes that must appear at an offset from the start of a file.
+type Magic = (usize, &'static [u8]);
+
+/// Magic bytes identifying files of a [`ContentType`], all of which must match.
+const MAGIC: &[(ContentType, &[Magic])] = &[
+    (ContentType::Sqlite, &[(0, b"SQLite format 3\0")]),
+    (ContentType::Image, &[(0, b"\x89PNG\r\n\x1a\n")]),
+    (ContentType::Image, &[(0, b"\xff\xd8\xff")]),
+    (ContentType::Image, &[(0, b"GIF87a")]),
+    (ContentType::Image, &[(0, b"GIF89a")]),
+    (ContentType::Image, &[(0, b"RIFF"), (8, b"WEBP")]),
+    (ContentType::Image, &[(0, b"II*\0")]),
+    (ContentType::Image, &[(0, b"MM\0*")]),
+    (ContentType::Image, &[(0, b"\0\0\x01\0")]),
+    (ContentType::Archive, &[(0, b"PK\x03\x04")]),
+    (ContentType::Archive, &[(0, b"\x1f\x8b")]),
+    (ContentType::Archive, &[(0, b"\x28\xb5\x2f\xfd")]),
+    (ContentType::Archive, &[(0, b"\xfd7zXZ\0")]),
+    (ContentType::Archive, &[(0, b"BZh")]),
+    (ContentType::Archive, &[(0, b"7z\xbc\xaf\x27\x1c")]),
+    (ContentType::Executable, &[(0, b"\x7fELF")]),
+    (ContentType::Executable, &[(0, b"MZ")]),
+    (ContentType::Executable, &[(0, b"\xfe\xed\xfa\xce")]),
+    (ContentType::Executable, &[(0, b"\xfe\xed\xfa\xcf")]),
+    (ContentType::Executable, &[(0, b"\xce\xfa\xed\xfe")]),
+    (ContentType::Executable, &[(0, b"\xcf\xfa\xed\xfe")]),
+    (ContentType::Executable, &[(0, b"\0asm")]),
+    (ContentType::Pdf, &[(0, b"%PDF-")]),
+];
+
+/// Detects the type of content starting with `bytes`.
+///
+/// Returns [`ContentType::Binary`] for binary content without known magic bytes.
+fn detect(bytes: &[u8]) -> ContentType {
+    let is_text = !bytes.contains(&0)
+        && match std::str::from_utf8(bytes) {
+            Ok(_) => true,
+            // The sample may end in the middle of a character.
+            Err(err) => err.error_len().is_none(),
+        };
+    if is_text {
+        return ContentType::Text;
+    }
+    MAGIC
+        .iter()
+        .find(|(_, parts)| {
+            parts
+                .iter()
+                .all(|(offset, magic)| bytes.get(*offset..offset + magic.len()) == Some(magic))
+        })
+        .map_or(ContentType::Binary, |(content_type, _)| *content_type)
+}
+
+/// Whether a file of the `detected` type is of the `configured` type.
+fn is_of_type(detected: ContentType, configured: ContentType) -> bool {
+    detected == configured || (configured == ContentType::Binary && detected != ContentType::Text)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ContentFilter {
+    ignore: Vec<ContentType>,
+    include: Vec<ContentType>,
+}
+
+impl ContentFilter {
+    fn detect_file(path: &Path) -> io::Result<ContentType> {
+        let mut bytes = Vec::new();
+        fs::File::open(path)?
+            .take(SNIFF_LEN)
+            .read_to_end(&mut bytes)?;
+        Ok(detect(&bytes))
+    }
+}
+
+impl Filter for ContentFilter {
+    type Error = Infallible;
+
+    fn new(pile_config: &PileConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            ignore: pile_config.ignore_content_types.clone(),
+            include: pile_config.include_content_types.clone(),
+        })
+    }
+
+    fn keep(&self, _prefix: &Path, path: &Path) -> bool {
+        if self.ignore.is_empty() && self.include.is_empty() {
+            return true;
+        }
+        // A file whose first bytes cannot be read has no type to match, so it is kept and copying
+        // it reports the error instead of the file silently going missing.
+        let Ok(detected) = Self::detect_file(path) else {
+            return true;
+        };
+        tracing::trace!(?path, ?detected, "detected content type");
+        if !self.include.is_empty()
+            && !self
+                .include
+                .iter()
+                .any(|configured| is_of_type(detected, *configured))
+        {
+            tracing::debug!(
+                ?path,
+                ?detected,
+                "skipping file not in include_content_types"
+            );
+            return false;
+        }
+        if let Some(configured) = self
+            .ignore
+            .iter()
+            .find(|configured| is_of_type(detected, **configured))
+        {
+            tracing::debug!(
+                ?path,
+                ?detected,
+                ?configured,
+                "skipping file in ignore_content_types"
+            );
+            return false;
+        }
+        true
+    }
+
+    fn keep_dir(&self, _prefix: &Path, _path: &Path) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect(b""), ContentType::Text);
+        assert_eq!(detect("héllo\n".as_bytes()), ContentType::Text);
+        // Cut off in the middle of "é".
+        assert_eq!(detect(&"héllo".as_bytes()[..2]), ContentType::Text);
+        assert_eq!(detect(b"nul\0byte"), ContentType::Binary);
+        assert_eq!(detect(b"\xff\xfe\xfd"), ContentType::Binary);
+        assert_eq!(detect(b"SQLite format 3\0\x10\0"), ContentType::Sqlite);
+        assert_eq!(
+            detect(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"),
+            ContentType::Image
+        );
+        assert_eq!(detect(b"RIFF\x10\0\0\0WEBPVP8 "), ContentType::Image);
+        assert_eq!(detect(b"\x7fELF\x02\x01\x01\0"), ContentType::Executable);
+        // Magic bytes are only checked for binary content.
+        assert_eq!(detect(b"MZ is a text file"), ContentType::Text);
+    }
+
+    #[test]
+    fn test_content_filter() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let (text, db, blob) = (
+            dir.path().join("config.toml"),
+            dir.path().join("cache.db"),
+            dir.path().join("blob"),
+        );
+        fs::write(&text, "key = \"value\"\n").unwrap();
+        fs::write(&db, b"SQLite format 3\0\x10\0\x01\x01").unwrap();
+        fs::write(&blob, [0, 1, 2, 3]).unwrap();
+
+        let config = PileConfig {
+            ignore_content_types: vec![ContentType::Sqlite],
+            ..PileConfig::default()
+        };
+        let filter = ContentFilter::new(&config).unwrap();
+        assert!(filter.keep(dir.path(), &text));
+        assert!(!filter.keep(dir.path(), &db));
+        assert!(filter.keep(dir.path(), &blob));
+        assert!(filter.keep_dir(dir.path(), dir.path()));
+
+        let config = PileConfig {
+            ignore_content_types: vec![ContentType::Binary],
+            ..PileConfig::default()
+        };
+        let filter = ContentFilter::new(&config).unwrap();
+        assert!(filter.keep(dir.path(), &text));
+        assert!(!filter.keep(dir.path(), &db));
+        assert!(!filter.keep(dir.path(), &blob));
+
+        let config = PileConfig {
+            include_content_types: vec![ContentType::Text, ContentType::Sqlite],
+            ..PileConfig::default()
+        };
+        let filter = ContentFilter::new(&config).unwrap();
+        assert!(filter.keep(dir.path(), &text));
+        assert!(filter.keep(dir.path(), &db));
+        assert!(!filter.keep(dir.path(), &blob));
+    }
+}
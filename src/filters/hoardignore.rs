@@ -0,0 +1,190 @@
+//! Provides a [`Filter`] based on `.hoardignore` files in pile directories.
+//!
+//! A `.hoardignore` file uses the same syntax and semantics as a `.gitignore` file: it applies to
+//! the directory containing it and everything below, patterns can be anchored to that directory
+//! with a leading `/`, patterns ending in `/` only match directories, and patterns starting with
+//! `!` re-include paths excluded by an earlier pattern. Patterns in deeper files take precedence.
+//!
+//! As with git, a file cannot be re-included if a directory containing it is excluded, because
+//! excluded directories are never entered.
+//!
+//! When restoring, the files stored in the hoard are used instead of those on the system, which
+//! may not exist yet. See [`HoardignoreFilter::with_files`].
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::{fs, io};
+
+use ::ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ::ignore::Match;
+
+use super::Filter;
+use crate::hoard::PileConfig;
+
+/// The name of the files read by [`HoardignoreFilter`].
+pub(crate) const FILE_NAME: &str = ".hoardignore";
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HoardignoreFilter {
+    /// The parsed `.hoardignore` file of each directory seen so far that has one.
+    matchers: Arc<Mutex<HashMap<PathBuf, Gitignore>>>,
+    /// Whether all `.hoardignore` files were given to [`HoardignoreFilter::with_files`], so none
+    /// are read from the directories being filtered.
+    preloaded: bool,
+}
+
+/// Parses the `content` of the `.hoardignore` file at `path`, skipping invalid lines.
+fn parse(path: &Path, content: &str) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(path.parent().unwrap_or(path));
+    for line in content.lines() {
+        if let Err(error) = builder.add_line(Some(path.to_owned()), line) {
+            tracing::warn!(?path, %error, "ignoring invalid line in ignore file");
+        }
+    }
+    builder.build().unwrap_or_else(|error| {
+        tracing::warn!(?path, %error, "ignoring invalid ignore file");
+        Gitignore::empty()
+    })
+}
+
+impl HoardignoreFilter {
+    /// Returns a filter that uses the given `.hoardignore` files, as pairs of path and content,
+    /// instead of reading them from the directories being filtered.
+    pub(crate) fn with_files(files: impl IntoIterator<Item = (PathBuf, String)>) -> Self {
+        let matchers = files
+            .into_iter()
+            .filter_map(|(path, content)| {
+                let matcher = parse(&path, &content);
+                path.parent().map(|dir| (dir.to_owned(), matcher))
+            })
+            .collect();
+        Self {
+            matchers: Arc::new(Mutex::new(matchers)),
+            preloaded: true,
+        }
+    }
+
+    /// Returns the matcher for the `.hoardignore` file in `dir`, reading it if necessary.
+    fn matcher(&self, dir: &Path) -> Option<Gitignore> {
+        let mut matchers = self.matchers.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(matcher) = matchers.get(dir) {
+            return Some(matcher.clone());
+        }
+        if self.preloaded {
+            return None;
+        }
+
+        // Directories without a file are not remembered, as one may be created later.
+        let path = dir.join(FILE_NAME);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    tracing::warn!(?path, %error, "failed to read ignore file");
+                }
+                return None;
+            }
+        };
+        tracing::trace!(?path, "read ignore file");
+        let matcher = parse(&path, &content);
+        matchers.insert(dir.to_owned(), matcher.clone());
+        Some(matcher)
+    }
+
+    /// Whether `path` is kept according to the `.hoardignore` files between it and `prefix`.
+    fn matches(&self, prefix: &Path, path: &Path, is_dir: bool) -> bool {
+        let _span = tracing::trace_span!("hoardignore_filter", ?prefix, ?path).entered();
+        // Deeper files take precedence, so check them first.
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(prefix) {
+                break;
+            }
+            if let Some(matcher) = self.matcher(dir) {
+                match matcher.matched(path, is_dir) {
+                    Match::None => {}
+                    Match::Ignore(glob) => {
+                        tracing::trace!(?glob, "path is ignored");
+                        return false;
+                    }
+                    Match::Whitelist(glob) => {
+                        tracing::trace!(?glob, "path is re-included");
+                        return true;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Filter for HoardignoreFilter {
+    type Error = Infallible;
+
+    fn new(_pile_config: &PileConfig) -> Result<Self, Self::Error> {
+        Ok(Self::default())
+    }
+
+    fn keep(&self, prefix: &Path, path: &Path) -> bool {
+        self.matches(prefix, path, false)
+    }
+
+    fn keep_dir(&self, prefix: &Path, path: &Path) -> bool {
+        self.matches(prefix, path, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_files_and_negation() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let root = root.path();
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(
+            root.join(FILE_NAME),
+            "*.log\n!keep.log\n/top-only\ncache/\n",
+        )
+        .unwrap();
+        fs::write(root.join("nested").join(FILE_NAME), "!nested.log\n*.tmp\n").unwrap();
+
+        let filter = HoardignoreFilter::new(&PileConfig::default()).unwrap();
+        assert!(!filter.keep(root, &root.join("debug.log")));
+        assert!(filter.keep(root, &root.join("keep.log")));
+        assert!(filter.keep(root, &root.join("file.txt")));
+        assert!(!filter.keep(root, &root.join("top-only")));
+        assert!(filter.keep(root, &root.join("nested").join("top-only")));
+        assert!(!filter.keep_dir(root, &root.join("nested").join("cache")));
+        assert!(filter.keep(root, &root.join("nested").join("cache")));
+        assert!(!filter.keep(root, &root.join("nested").join("debug.log")));
+        assert!(filter.keep(root, &root.join("nested").join("nested.log")));
+        assert!(!filter.keep(root, &root.join("nested").join("file.tmp")));
+        assert!(filter.keep(root, &root.join("file.tmp")));
+    }
+
+    #[test]
+    fn test_files_created_later_are_read() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let root = root.path();
+        let filter = HoardignoreFilter::new(&PileConfig::default()).unwrap();
+        assert!(filter.keep(root, &root.join("debug.log")));
+
+        fs::write(root.join(FILE_NAME), "*.log\n").unwrap();
+        assert!(!filter.keep(root, &root.join("debug.log")));
+    }
+
+    #[test]
+    fn test_with_files_does_not_read_directories() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let root = root.path();
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("nested").join(FILE_NAME), "*.tmp\n").unwrap();
+
+        let filter = HoardignoreFilter::with_files([(root.join(FILE_NAME), "*.log\n".to_owned())]);
+        assert!(!filter.keep(root, &root.join("debug.log")));
+        assert!(!filter.keep(root, &root.join("nested").join("debug.log")));
+        assert!(filter.keep(root, &root.join("nested").join("file.tmp")));
+    }
+}
@@ -6,12 +6,14 @@ use crate::hoard::PileConfig;
 /// ```ignore
 /// [config]
 ///     ignore = ["some*glob"]
+///     ignore_regex = ["^some.*regex$"]
 /// ```
 ///
 /// This can be put under global, hoard, or pile scope.
 use glob::{Pattern, PatternError};
+use regex::Regex;
 
-use super::Filter;
+use super::{any_regex_matches, compile_regexes, Filter};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -25,17 +27,30 @@ pub enum Error {
     },
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub(crate) struct IgnoreFilter {
     globs: Vec<Pattern>,
+    regexes: Vec<Regex>,
+}
+
+impl PartialEq for IgnoreFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.globs == other.globs
+            && self
+                .regexes
+                .iter()
+                .map(Regex::as_str)
+                .eq(other.regexes.iter().map(Regex::as_str))
+    }
 }
 
 impl Filter for IgnoreFilter {
-    type Error = Error;
+    type Error = super::Error;
 
     fn new(pile_config: &PileConfig) -> Result<Self, Self::Error> {
         Ok(IgnoreFilter {
             globs: pile_config.ignore.clone(),
+            regexes: compile_regexes("ignore_regex", &pile_config.ignore_regex)?,
         })
     }
 
@@ -47,7 +62,7 @@ impl Filter for IgnoreFilter {
             let matches = glob.matches_path(rel_path);
             tracing::trace!("{:?} matches glob {:?}: {}", rel_path, glob, matches);
             !matches
-        })
+        }) && !any_regex_matches(&self.regexes, rel_path)
     }
 }
 
@@ -61,6 +76,7 @@ mod tests {
             let config = PileConfig {
                 encryption: None,
                 ignore: vec![Pattern::new("testing/**").unwrap()],
+                ..PileConfig::default()
             };
             IgnoreFilter::new(&config).expect("filter should be valid")
         };
@@ -68,6 +84,7 @@ mod tests {
             let config = PileConfig {
                 encryption: None,
                 ignore: vec![Pattern::new("test/**").unwrap()],
+                ..PileConfig::default()
             };
             IgnoreFilter::new(&config).expect("filter should be valid")
         };
@@ -0,0 +1,91 @@
+use crate::hoard::PileConfig;
+/// Provides a [`Filter`] based on glob include patterns.
+///
+/// To use this filter, add a list of glob patterns to `include` under `config`. For example:
+///
+/// ```ignore
+/// [config]
+///     include = ["*.sav"]
+/// ```
+///
+/// Regular expressions can be given in `include_regex` as well. Only files matching at least one
+/// glob or expression are kept. If neither is given, all files are kept. This can be put under
+/// global, hoard, or pile scope.
+use glob::Pattern;
+use regex::Regex;
+
+use super::{any_regex_matches, compile_regexes, Error, Filter};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub(crate) struct IncludeFilter {
+    globs: Vec<Pattern>,
+    regexes: Vec<Regex>,
+}
+
+impl PartialEq for IncludeFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.globs == other.globs
+            && self
+                .regexes
+                .iter()
+                .map(Regex::as_str)
+                .eq(other.regexes.iter().map(Regex::as_str))
+    }
+}
+
+impl Filter for IncludeFilter {
+    type Error = Error;
+
+    fn new(pile_config: &PileConfig) -> Result<Self, Self::Error> {
+        Ok(IncludeFilter {
+            globs: pile_config.include.clone(),
+            regexes: compile_regexes("include_regex", &pile_config.include_regex)?,
+        })
+    }
+
+    fn keep(&self, prefix: &Path, path: &Path) -> bool {
+        let _span = tracing::trace_span!("include_filter", ?prefix, ?path).entered();
+        let rel_path = path.strip_prefix(prefix).unwrap_or(path);
+        // A pile that is a single file is always included.
+        if (self.globs.is_empty() && self.regexes.is_empty()) || rel_path.as_os_str().is_empty() {
+            return true;
+        }
+        self.globs.iter().any(|glob| {
+            let matches = glob.matches_path(rel_path);
+            tracing::trace!("{:?} matches glob {:?}: {}", rel_path, glob, matches);
+            matches
+        }) || any_regex_matches(&self.regexes, rel_path)
+    }
+
+    fn keep_dir(&self, _prefix: &Path, _path: &Path) -> bool {
+        // Files in any directory may match.
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include_filter() {
+        let config = PileConfig {
+            include: vec![
+                Pattern::new("*.sav").unwrap(),
+                Pattern::new("config/*").unwrap(),
+            ],
+            ..PileConfig::default()
+        };
+        let filter = IncludeFilter::new(&config).expect("filter should be valid");
+        let prefix = Path::new("/games/save");
+        assert!(filter.keep(prefix, &prefix.join("slot1.sav")));
+        assert!(filter.keep(prefix, &prefix.join("config").join("game.ini")));
+        assert!(!filter.keep(prefix, &prefix.join("crash.log")));
+        assert!(filter.keep_dir(prefix, &prefix.join("logs")));
+        assert!(filter.keep(prefix, prefix));
+
+        let filter = IncludeFilter::new(&PileConfig::default()).unwrap();
+        assert!(filter.keep(prefix, &prefix.join("crash.log")));
+    }
+}
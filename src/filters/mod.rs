@@ -1,10 +1,16 @@
 //! Provides filters for determining whether a path should be backed up or not.
 
-use crate::hoard::PileConfig;
-use std::path::Path;
+use crate::hoard::{PileConfig, RegexPattern};
+use regex::Regex;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+pub(crate) mod age;
+pub(crate) mod content;
+pub(crate) mod hoardignore;
 pub(crate) mod ignore;
+pub(crate) mod include;
+pub(crate) mod size;
 
 /// The [`Filter`] trait provides a common interface for all filters.
 pub trait Filter: Sized {
@@ -18,6 +24,12 @@ pub trait Filter: Sized {
     fn new(pile_config: &PileConfig) -> Result<Self, Self::Error>;
     /// Whether or not the file should be kept (backed up).
     fn keep(&self, prefix: &Path, path: &Path) -> bool;
+    /// Whether or not the directory should be descended into.
+    ///
+    /// Defaults to [`Filter::keep`].
+    fn keep_dir(&self, prefix: &Path, path: &Path) -> bool {
+        self.keep(prefix, path)
+    }
 }
 
 /// Any errors that may occur while filtering.
@@ -26,25 +38,146 @@ pub enum Error {
     /// An error occurred in the Ignore filter.
     #[error("error occurred in the ignore filter: {0}")]
     Ignore(#[from] ignore::Error),
+    /// A regular expression in the configuration could not be compiled.
+    #[error("invalid regular expression \"{pattern}\" in `{option}` of {location}: {error}")]
+    InvalidRegex {
+        /// The option the expression was given in, like `ignore_regex`.
+        option: &'static str,
+        /// The configuration table the option was set in.
+        location: String,
+        /// The invalid expression.
+        pattern: String,
+        /// Why the expression is invalid.
+        #[source]
+        error: regex::Error,
+    },
+}
+
+/// Compiles the regular expressions given for `option`.
+fn compile_regexes(option: &'static str, patterns: &[RegexPattern]) -> Result<Vec<Regex>, Error> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern.as_str()).map_err(|error| Error::InvalidRegex {
+                option,
+                location: pattern.location().unwrap_or("the configuration").to_owned(),
+                pattern: pattern.as_str().to_owned(),
+                error,
+            })
+        })
+        .collect()
+}
+
+/// Whether any of `regexes` matches `rel_path`, with components separated by `/` on all platforms.
+fn any_regex_matches(regexes: &[Regex], rel_path: &Path) -> bool {
+    if regexes.is_empty() {
+        return false;
+    }
+    let rel_path = rel_path
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    regexes.iter().any(|regex| {
+        let matches = regex.is_match(&rel_path);
+        tracing::trace!("{:?} matches regex {:?}: {}", rel_path, regex, matches);
+        matches
+    })
 }
 
 /// A wrapper for all implmented filters.
+///
+/// A path is kept if it matches the include filter, is not ignored by either the configured
+/// patterns or a `.hoardignore` file, and passes the size, age, and content type limits.
 #[derive(Debug, Clone)]
 pub struct Filters {
+    include: include::IncludeFilter,
     ignore: ignore::IgnoreFilter,
+    hoardignore: hoardignore::HoardignoreFilter,
+    size: size::SizeFilter,
+    age: age::AgeFilter,
+    content: content::ContentFilter,
+}
+
+impl Filters {
+    /// Calls [`Filter::keep_dir`] if `is_dir` is `true` and [`Filter::keep`] otherwise.
+    pub(crate) fn keep_path(&self, prefix: &Path, path: &Path, is_dir: bool) -> bool {
+        if is_dir {
+            self.keep_dir(prefix, path)
+        } else {
+            self.keep(prefix, path)
+        }
+    }
+
+    /// Returns these filters without the ones that only apply when backing up.
+    ///
+    /// Files in the hoard already passed the size, age, and content type limits when they were
+    /// backed up, and the files they replace on the system may be older or differ in size or
+    /// content.
+    #[must_use]
+    pub(crate) fn for_restore(self) -> Self {
+        Self {
+            size: size::SizeFilter::default(),
+            age: age::AgeFilter::default(),
+            content: content::ContentFilter::default(),
+            ..self
+        }
+    }
+
+    /// Returns these filters using the given `.hoardignore` files, as pairs of path and content,
+    /// instead of reading them from the directories being filtered.
+    #[must_use]
+    pub(crate) fn with_hoardignore_files(
+        self,
+        files: impl IntoIterator<Item = (PathBuf, String)>,
+    ) -> Self {
+        Self {
+            hoardignore: hoardignore::HoardignoreFilter::with_files(files),
+            ..self
+        }
+    }
 }
 
 impl Filter for Filters {
     type Error = Error;
 
     fn new(pile_config: &PileConfig) -> Result<Self, Self::Error> {
+        let include = include::IncludeFilter::new(pile_config)?;
         let ignore = ignore::IgnoreFilter::new(pile_config)?;
-        Ok(Self { ignore })
+        let hoardignore =
+            hoardignore::HoardignoreFilter::new(pile_config).unwrap_or_else(|never| match never {});
+        let size = size::SizeFilter::new(pile_config).unwrap_or_else(|never| match never {});
+        let age = age::AgeFilter::new(pile_config).unwrap_or_else(|never| match never {});
+        let content =
+            content::ContentFilter::new(pile_config).unwrap_or_else(|never| match never {});
+        Ok(Self {
+            include,
+            ignore,
+            hoardignore,
+            size,
+            age,
+            content,
+        })
     }
 
     fn keep(&self, prefix: &Path, path: &Path) -> bool {
         let _span = tracing::trace_span!("run_filters", ?prefix, ?path).entered();
-        self.ignore.keep(prefix, path)
+        self.include.keep(prefix, path)
+            && self.ignore.keep(prefix, path)
+            && self.hoardignore.keep(prefix, path)
+            && self.size.keep(prefix, path)
+            && self.age.keep(prefix, path)
+            && self.content.keep(prefix, path)
+    }
+
+    fn keep_dir(&self, prefix: &Path, path: &Path) -> bool {
+        let _span = tracing::trace_span!("run_dir_filters", ?prefix, ?path).entered();
+        self.include.keep_dir(prefix, path)
+            && self.ignore.keep_dir(prefix, path)
+            && self.hoardignore.keep_dir(prefix, path)
+            && self.size.keep_dir(prefix, path)
+            && self.age.keep_dir(prefix, path)
+            && self.content.keep_dir(prefix, path)
     }
 }
 
@@ -57,9 +190,68 @@ mod tests {
         let config = PileConfig {
             encryption: None,
             ignore: vec![glob::Pattern::new("valid/**").unwrap()],
+            ..PileConfig::default()
         };
         let filters = Filters::new(&config).expect("config should be valid");
         assert!(format!("{:?}", filters).contains("Filters"));
         assert_eq!(filters.clone().ignore, filters.ignore);
+        assert_eq!(filters.clone().include, filters.include);
+    }
+
+    #[test]
+    fn test_include_then_ignore() {
+        let config = PileConfig {
+            include: vec![glob::Pattern::new("*.sav").unwrap()],
+            ignore: vec![glob::Pattern::new("autosave*").unwrap()],
+            ..PileConfig::default()
+        };
+        let filters = Filters::new(&config).expect("config should be valid");
+        let prefix = Path::new("/saves");
+        assert!(filters.keep(prefix, &prefix.join("slot1.sav")));
+        assert!(!filters.keep(prefix, &prefix.join("autosave1.sav")));
+        assert!(!filters.keep(prefix, &prefix.join("notes.txt")));
+        assert!(filters.keep_dir(prefix, &prefix.join("slots")));
+    }
+
+    #[test]
+    fn test_regexes() {
+        let config = PileConfig {
+            include: vec![glob::Pattern::new("*.sav").unwrap()],
+            include_regex: vec![RegexPattern::new(r"^slot\d+\.dat$")],
+            ignore_regex: vec![
+                RegexPattern::new(r"^[0-9a-f]{8}(-[0-9a-f]{4}){3}-[0-9a-f]{12}\.tmp$"),
+                RegexPattern::new(r"^autosave\d+\.sav$"),
+                RegexPattern::new(r"(^|/)cache$"),
+            ],
+            ..PileConfig::default()
+        };
+        let filters = Filters::new(&config).expect("config should be valid");
+        let prefix = Path::new("/saves");
+        assert!(filters.keep(prefix, &prefix.join("quick.sav")));
+        assert!(filters.keep(prefix, &prefix.join("slot12.dat")));
+        assert!(!filters.keep(prefix, &prefix.join("slot.dat")));
+        assert!(!filters.keep(prefix, &prefix.join("autosave3.sav")));
+        assert!(!filters.keep(
+            prefix,
+            &prefix.join("0a1b2c3d-0000-1111-2222-33334444aaaa.tmp")
+        ));
+        assert!(!filters.keep_dir(prefix, &prefix.join("profile").join("cache")));
+        assert!(filters.keep_dir(prefix, &prefix.join("profile")));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let mut config = PileConfig {
+            ignore_regex: vec![RegexPattern::new("valid"), RegexPattern::new("(unclosed")],
+            ..PileConfig::default()
+        };
+        config.set_location("[hoards.games.saves.config]");
+        let err = Filters::new(&config).expect_err("regex should be invalid");
+        assert!(matches!(
+            &err,
+            Error::InvalidRegex { option: "ignore_regex", pattern, .. } if pattern == "(unclosed"
+        ));
+        let message = err.to_string();
+        assert!(message.contains("`ignore_regex` of [hoards.games.saves.config]"));
     }
 }
@@ -0,0 +1,104 @@
+//! Provides a [`Filter`] based on file sizes.
+//!
+//! To use this filter, set `max_file_size` and/or `min_file_size` under `config`, either as a
+//! number of bytes or as a string with a unit. For example:
+//!
+//! ```ignore
+//! [config]
+//!     max_file_size = "100 MiB"
+//! ```
+use std::convert::Infallible;
+use std::fs;
+use std::path::Path;
+
+use super::Filter;
+use crate::hoard::PileConfig;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SizeFilter {
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl SizeFilter {
+    /// Whether this filter keeps every file.
+    fn is_empty(&self) -> bool {
+        self.min.is_none() && self.max.is_none()
+    }
+}
+
+impl Filter for SizeFilter {
+    type Error = Infallible;
+
+    fn new(pile_config: &PileConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            min: pile_config.min_file_size,
+            max: pile_config.max_file_size,
+        })
+    }
+
+    fn keep(&self, _prefix: &Path, path: &Path) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        // Without metadata the size is unknown, so keep the file and let copying it report why.
+        let Ok(metadata) = fs::metadata(path) else {
+            return true;
+        };
+        let size = metadata.len();
+        if self.max.is_some_and(|max| size > max) {
+            tracing::debug!(
+                ?path,
+                size,
+                max = self.max,
+                "skipping file larger than max_file_size"
+            );
+            return false;
+        }
+        if self.min.is_some_and(|min| size < min) {
+            tracing::debug!(
+                ?path,
+                size,
+                min = self.min,
+                "skipping file smaller than min_file_size"
+            );
+            return false;
+        }
+        true
+    }
+
+    fn keep_dir(&self, _prefix: &Path, _path: &Path) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_filter() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let (small, large) = (dir.path().join("small"), dir.path().join("large"));
+        fs::write(&small, [0; 10]).unwrap();
+        fs::write(&large, [0; 1000]).unwrap();
+
+        let config = PileConfig {
+            min_file_size: Some(5),
+            max_file_size: Some(100),
+            ..PileConfig::default()
+        };
+        let filter = SizeFilter::new(&config).unwrap();
+        assert!(filter.keep(dir.path(), &small));
+        assert!(!filter.keep(dir.path(), &large));
+        assert!(filter.keep_dir(dir.path(), dir.path()));
+
+        let config = PileConfig {
+            min_file_size: Some(100),
+            ..PileConfig::default()
+        };
+        let filter = SizeFilter::new(&config).unwrap();
+        assert!(!filter.keep(dir.path(), &small));
+        assert!(filter.keep(dir.path(), &large));
+    }
+}
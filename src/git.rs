@@ -0,0 +1,277 @@
+//! Keeping the hoards root in a git repository. See [`Config`].
+//!
+//! All operations run the `git` program, so they use the user's own git configuration,
+//! including their identity and any credentials needed for the remote.
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const DEFAULT_REMOTE: &str = "origin";
+
+/// Errors that can occur while running git.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The `git` program could not be run.
+    #[error("failed to run git: {0}")]
+    Spawn(#[from] io::Error),
+    /// git exited with an error.
+    #[error("`git {}` failed ({status}): {stderr}", args.join(" "))]
+    Exit {
+        /// The arguments git was run with.
+        args: Vec<String>,
+        /// The exit status of git.
+        status: ExitStatus,
+        /// What git printed to stderr.
+        stderr: String,
+    },
+}
+
+/// Configuration for committing changes to the hoards root to a git repository.
+///
+/// The hoards root must already be inside a git repository. If the history directory is in the
+/// same repository, for example when the repository is the data directory containing both, the
+/// operation logs of this system are committed as well.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// The remote used by `hoard pull` and `hoard push`. Defaults to `origin`.
+    #[serde(default)]
+    remote: Option<String>,
+    /// The remote branch used by `hoard pull` and `hoard push`. Defaults to the name of the
+    /// current branch.
+    #[serde(default)]
+    branch: Option<String>,
+}
+
+/// Runs git with `args` in `dir` and returns its output if it succeeded.
+///
+/// If `dir` does not exist, for example a hoards root that was not backed up to yet, git is run
+/// in its closest existing parent instead.
+fn git<S: AsRef<OsStr>>(dir: &Path, args: &[S]) -> Result<Output, Error> {
+    let dir = dir
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(|| Path::new("."));
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(Error::Exit {
+            args: args
+                .iter()
+                .map(|arg| arg.as_ref().to_string_lossy().into_owned())
+                .collect(),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        })
+    }
+}
+
+/// Returns the root of the work tree containing `dir`.
+fn toplevel(dir: &Path) -> Result<PathBuf, Error> {
+    let output = git(dir, &["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ))
+}
+
+/// Stages all changes to `paths` in the repository containing `dir` and commits them with
+/// `message`. Changes to other files are neither staged nor committed.
+///
+/// Paths outside the repository are ignored. Returns whether anything was committed.
+///
+/// # Errors
+///
+/// Any [`enum@Error`] from running git, including `dir` not being in a repository.
+pub(crate) fn commit(dir: &Path, paths: &[PathBuf], message: &str) -> Result<bool, Error> {
+    let _span = tracing::debug_span!("git_commit", ?dir).entered();
+    let root = toplevel(dir)?;
+    let paths: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|path| {
+            // Paths that do not exist yet are resolved through their parent.
+            let path = path.canonicalize().ok().or_else(|| {
+                let parent = path.parent()?.canonicalize().ok()?;
+                Some(parent.join(path.file_name()?))
+            })?;
+            if let Ok(rel_path) = path.strip_prefix(&root) {
+                Some(rel_path.to_owned())
+            } else {
+                tracing::debug!(?path, "not committing path outside the repository");
+                None
+            }
+        })
+        .collect();
+
+    let (existing, missing): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        paths.iter().partition(|path| root.join(path).exists());
+    if !existing.is_empty() {
+        let mut args = vec![OsStr::new("add"), OsStr::new("-A"), OsStr::new("--")];
+        args.extend(existing.iter().map(|path| path.as_os_str()));
+        git(&root, &args)?;
+    }
+    if !missing.is_empty() {
+        let mut args = vec![
+            OsStr::new("rm"),
+            OsStr::new("-r"),
+            OsStr::new("-q"),
+            OsStr::new("--cached"),
+            OsStr::new("--ignore-unmatch"),
+            OsStr::new("--"),
+        ];
+        args.extend(missing.iter().map(|path| path.as_os_str()));
+        git(&root, &args)?;
+    }
+
+    // Only paths with staged changes can be committed, as the others may be unknown to git.
+    let mut changed = Vec::new();
+    for path in &paths {
+        let args = [
+            OsStr::new("diff"),
+            OsStr::new("--cached"),
+            OsStr::new("--quiet"),
+            OsStr::new("--"),
+            path.as_os_str(),
+        ];
+        match git(&root, &args) {
+            Ok(_) => {}
+            Err(Error::Exit { status, .. }) if status.code() == Some(1) => changed.push(path),
+            Err(err) => return Err(err),
+        }
+    }
+    if changed.is_empty() {
+        tracing::debug!("no changes to commit");
+        return Ok(false);
+    }
+
+    let mut args = vec![
+        OsStr::new("commit"),
+        OsStr::new("-q"),
+        OsStr::new("-m"),
+        OsStr::new(message),
+        OsStr::new("--"),
+    ];
+    args.extend(changed.iter().map(|path| path.as_os_str()));
+    git(&root, &args)?;
+    tracing::info!("committed changes to git");
+    Ok(true)
+}
+
+impl Config {
+    fn remote(&self) -> &str {
+        self.remote.as_deref().unwrap_or(DEFAULT_REMOTE)
+    }
+
+    fn branch(&self, dir: &Path) -> Result<String, Error> {
+        if let Some(branch) = &self.branch {
+            return Ok(branch.clone());
+        }
+        // Unlike `rev-parse`, this also works before the first commit on the branch.
+        let output = git(dir, &["symbolic-ref", "--short", "HEAD"])?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned())
+    }
+
+    /// Fetches the configured branch of the remote and fast-forwards the repository containing
+    /// `dir` to it.
+    ///
+    /// # Errors
+    ///
+    /// Any [`enum@Error`] from running git, including the histories having diverged.
+    pub(crate) fn pull(&self, dir: &Path) -> Result<(), Error> {
+        let branch = self.branch(dir)?;
+        tracing::info!(remote = self.remote(), %branch, "pulling");
+        git(dir, &["pull", "-q", "--ff-only", self.remote(), &branch])?;
+        Ok(())
+    }
+
+    /// Pushes the current branch of the repository containing `dir` to the configured branch
+    /// of the remote.
+    ///
+    /// # Errors
+    ///
+    /// Any [`enum@Error`] from running git, including the remote having newer commits.
+    pub(crate) fn push(&self, dir: &Path) -> Result<(), Error> {
+        let branch = self.branch(dir)?;
+        tracing::info!(remote = self.remote(), %branch, "pushing");
+        git(
+            dir,
+            &["push", "-q", self.remote(), &format!("HEAD:{branch}")],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn clone(remote: &Path, dest: &Path) {
+        git(
+            Path::new("."),
+            &[
+                OsStr::new("clone"),
+                OsStr::new("-q"),
+                remote.as_os_str(),
+                dest.as_os_str(),
+            ],
+        )
+        .expect("failed to clone");
+        git(dest, &["config", "user.name", "Hoard Test"]).unwrap();
+        git(dest, &["config", "user.email", "hoard@example.com"]).unwrap();
+    }
+
+    #[test]
+    fn test_commit_push_and_pull() {
+        let tmp = tempfile::tempdir().expect("failed to create temp dir");
+        let remote = tmp.path().join("remote.git");
+        git(
+            tmp.path(),
+            &[
+                OsStr::new("init"),
+                OsStr::new("-q"),
+                OsStr::new("--bare"),
+                remote.as_os_str(),
+            ],
+        )
+        .expect("failed to create bare repository");
+        let (first, second) = (tmp.path().join("first"), tmp.path().join("second"));
+        clone(&remote, &first);
+        clone(&remote, &second);
+        git(&first, &["checkout", "-q", "-b", "main"]).unwrap();
+        let config = Config::default();
+
+        let hoards = first.join("hoards");
+        fs::create_dir_all(hoards.join("hoard")).unwrap();
+        fs::write(hoards.join("hoard").join("file"), "content").unwrap();
+        fs::write(hoards.join("unrelated"), "not committed").unwrap();
+        let paths = crate::hoard::stored_paths(&hoards, "hoard");
+        assert!(commit(&hoards, &paths, "back up hoard").expect("commit should succeed"));
+        assert!(!commit(&hoards, &paths, "back up hoard").expect("commit should succeed"));
+        config.push(&first).expect("push should succeed");
+
+        git(&second, &["checkout", "-q", "-b", "main"]).unwrap();
+        config.pull(&second).expect("pull should succeed");
+        let file = second.join("hoards").join("hoard").join("file");
+        assert_eq!(fs::read_to_string(file).unwrap(), "content");
+        assert!(!second.join("hoards").join("unrelated").exists());
+
+        // Deleting the hoard is committed as well.
+        fs::remove_dir_all(hoards.join("hoard")).unwrap();
+        assert!(commit(&hoards, &paths, "delete hoard").expect("commit should succeed"));
+        config.push(&first).expect("push should succeed");
+        config.pull(&second).expect("pull should succeed");
+        assert!(!second.join("hoards").join("hoard").exists());
+    }
+}
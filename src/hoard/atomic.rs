@@ -0,0 +1,154 @@
+//! Atomic replacement of files.
+//!
+//! Content is written to a temporary file next to the destination, flushed to disk, and then
+//! renamed over the destination. If anything fails along the way, the destination is left
+//! untouched and the temporary file is removed.
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The suffix used for temporary files, so that leftovers from a crash are recognizable.
+pub(crate) const TMP_SUFFIX: &str = "hoard-tmp";
+
+/// Numbers the temporary files created by this process, so that concurrent writes to the same
+/// destination from different threads never share a temporary file.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the path that writing to `dest` should replace.
+///
+/// If `dest` is a symbolic link, the file it points to is replaced instead of the link itself,
+/// like writing to the link would.
+fn resolve(dest: &Path) -> PathBuf {
+    match fs::canonicalize(dest) {
+        Ok(path) if path != dest => {
+            tracing::trace!(link=?dest, target=?path, "writing through symbolic link");
+            path
+        }
+        _ => dest.to_owned(),
+    }
+}
+
+fn tmp_path(dest: &Path) -> io::Result<PathBuf> {
+    let file_name = dest.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no file name", dest.display()),
+        )
+    })?;
+    let tmp_name = format!(
+        ".{}.{}.{}.{TMP_SUFFIX}",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    Ok(dest.with_file_name(tmp_name))
+}
+
+/// Atomically replaces `dest` with the content written by `write_content`, giving it
+/// `permissions` if provided.
+///
+/// # Errors
+///
+/// Any I/O error from creating, writing, syncing, or renaming the temporary file, or from
+/// `write_content` itself.
+pub(crate) fn write<F>(
+    dest: &Path,
+    permissions: Option<fs::Permissions>,
+    write_content: F,
+) -> io::Result<()>
+where
+    F: FnOnce(&mut fs::File) -> io::Result<()>,
+{
+    let dest = resolve(dest);
+    let tmp = tmp_path(&dest)?;
+    let _span = tracing::trace_span!("atomic_write", ?dest, ?tmp).entered();
+
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        write_content(&mut file)?;
+        file.flush()?;
+        if let Some(permissions) = permissions {
+            file.set_permissions(permissions)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &dest)?;
+        // Make sure the rename itself survives a crash.
+        #[cfg(unix)]
+        if let Some(parent) = dest.parent() {
+            fs::File::open(parent)?.sync_all()?;
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        if let Err(err) = fs::remove_file(&tmp) {
+            if err.kind() != io::ErrorKind::NotFound {
+                tracing::warn!(path=?tmp, error=%err, "failed to remove temporary file");
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_replaces_content_and_cleans_up() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let dest = dir.path().join("file");
+        fs::write(&dest, "old content").unwrap();
+        let permissions = fs::metadata(&dest).unwrap().permissions();
+
+        write(&dest, Some(permissions), |file| file.write_all(b"new"))
+            .expect("write should succeed");
+
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_tmp_paths_are_unique() {
+        let dest = Path::new("/some/dir/file");
+        let first = tmp_path(dest).unwrap();
+        let second = tmp_path(dest).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), dest.parent());
+        assert!(first.to_string_lossy().ends_with(TMP_SUFFIX));
+    }
+
+    #[test]
+    fn test_failed_write_leaves_original_untouched() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let dest = dir.path().join("file");
+        fs::write(&dest, "old content").unwrap();
+        let permissions = fs::metadata(&dest).unwrap().permissions();
+
+        let result = write(&dest, Some(permissions), |file| {
+            file.write_all(b"partial")?;
+            Err(io::Error::new(io::ErrorKind::Other, "interrupted"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old content");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_through_symlink() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let target = dir.path().join("target");
+        let link = dir.path().join("link");
+        fs::write(&target, "old content").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write(&link, None, |file| file.write_all(b"content")).expect("write should succeed");
+
+        assert!(crate::hoard::symlinks::is_symlink(&link));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+    }
+}
@@ -0,0 +1,127 @@
+//! Compression of files stored in the hoard. See [`Compression`].
+//!
+//! Compression is applied before encryption when backing up and reversed after decryption when
+//! restoring, as encrypted content does not compress. Whether a stored file is compressed is
+//! taken from its recorded [`StoredFormat`](super::format::StoredFormat), never guessed from its
+//! content, as files may well be zstd-compressed to begin with.
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+/// How files in a pile are compressed in the hoard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Store files as-is.
+    #[default]
+    None,
+    /// Compress files with [zstd](https://facebook.github.io/zstd/).
+    Zstd,
+}
+
+impl Compression {
+    /// Returns whether files are stored as-is.
+    pub(crate) fn is_none(self) -> bool {
+        self == Self::None
+    }
+
+    /// Returns a writer that compresses everything written to it into `writer`.
+    /// [`Encoder::finish`] must be called once everything is written.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from setting up the compressor.
+    pub(crate) fn encoder<W: Write>(self, writer: W) -> io::Result<Encoder<W>> {
+        match self {
+            Self::None => Ok(Encoder::None(writer)),
+            Self::Zstd => zstd::stream::write::Encoder::new(writer, 0).map(Encoder::Zstd),
+        }
+    }
+
+    /// Returns a reader of the decompressed content of `reader`, which must have been compressed
+    /// with this compression.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from setting up the decompressor.
+    pub(crate) fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Self::None => Ok(Box::new(reader)),
+            Self::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+        }
+    }
+}
+
+/// Compresses everything written to it. See [`Compression::encoder`].
+pub(crate) enum Encoder<W: Write> {
+    /// Writes everything as-is.
+    None(W),
+    /// Compresses with zstd.
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the end of the compressed content and returns the inner writer.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from writing to the inner writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::None(writer) => Ok(writer),
+            Self::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::None(writer) => writer.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::None(writer) => writer.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(compression: Compression, content: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut encoder = compression.encoder(Vec::new()).unwrap();
+        encoder.write_all(content).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut decompressed = Vec::new();
+        compression
+            .decoder(compressed.as_slice())
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        (compressed, decompressed)
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let content = b"hoard content ".repeat(100);
+        let (compressed, decompressed) = round_trip(Compression::Zstd, &content);
+        assert!(compressed.len() < content.len());
+        assert_eq!(decompressed, content);
+    }
+
+    #[test]
+    fn test_compressed_content_round_trips() {
+        // Content that is zstd-compressed already must come back exactly as it was.
+        let content = zstd::encode_all(&b"already compressed"[..], 0).unwrap();
+        assert_eq!(round_trip(Compression::Zstd, &content).1, content);
+        let (stored, restored) = round_trip(Compression::None, &content);
+        assert_eq!(stored, content);
+        assert_eq!(restored, content);
+    }
+}
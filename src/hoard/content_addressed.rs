@@ -0,0 +1,665 @@
+//! Content-addressed storage of the hoards root. See [`Layout::ContentAddressed`].
+//!
+//! With this layout, the content of every file in a pile is stored once in an object store,
+//! `$HOARDS_ROOT/.hoard-objects`, named by its checksum. In place of its directory, each pile
+//! has a [`Manifest`] file mapping the paths of its files to the objects holding their content.
+//! Any other files in the hoards root, like metadata sidecars, are stored as-is.
+//!
+//! The rest of `hoard` only works with the directory layout, so commands check out the
+//! hoards they work with into a private directory with that layout (see [`Checkout`]). Checked
+//! out files are copies, never links, as objects may be shared by any number of piles and must
+//! not change when a checked out file does. Commands that change the hoard then pack the
+//! checked out files back into objects and manifests.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::atomic;
+use super::dry_run::DryRun;
+use super::metadata::Sidecar;
+use super::symlinks::create_symlink;
+use super::versions;
+use crate::checkers::history::operation::{hash_file, Checksum, ChecksumType};
+
+/// The name of the object store directory in the hoards root.
+pub(crate) const OBJECTS_DIR: &str = ".hoard-objects";
+/// The suffix added to a pile's path to get the path of its manifest.
+const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// Errors that can occur while working with content-addressed storage.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// An I/O error occurred while working with a file in the hoards root or a checkout.
+    #[error("I/O error at {path}: {error}")]
+    IO {
+        /// The path of the file being worked with.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// A manifest could not be read or written.
+    #[error("invalid manifest {path}: {error}")]
+    Manifest {
+        /// The path of the manifest.
+        path: PathBuf,
+        /// The error that occurred while (de)serializing.
+        #[source]
+        error: serde_json::Error,
+    },
+}
+
+/// Returns a closure that wraps an I/O error with `path`.
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |error| Error::IO {
+        path: path.to_owned(),
+        error,
+    }
+}
+
+/// How files are laid out in the hoards root.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Each pile is a directory mirroring the files on the system.
+    #[default]
+    Directory,
+    /// File contents are stored once by checksum and each pile is a manifest of its files.
+    ContentAddressed,
+}
+
+/// Hard links `src` to `dest`, falling back to copying it.
+///
+/// Only used to move files out of a checkout, which is deleted afterwards.
+fn link_or_copy(src: &Path, dest: &Path) -> Result<(), Error> {
+    if fs::hard_link(src, dest).is_err() {
+        fs::copy(src, dest).map_err(io_err(src))?;
+    }
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).map_err(io_err(parent)),
+        None => Ok(()),
+    }
+}
+
+/// Removes the file or directory at `path`, if it exists.
+fn remove_all(path: &Path) -> Result<(), Error> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    };
+    result.map_err(io_err(path))
+}
+
+/// The permission mode of a file, which is only recorded on Unix.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn file_mode(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn file_mode(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// The path of the manifest for the pile stored at `prefix`.
+fn manifest_path(prefix: &Path) -> PathBuf {
+    let mut name = prefix.file_name().unwrap_or_default().to_os_string();
+    name.push(MANIFEST_SUFFIX);
+    prefix.with_file_name(name)
+}
+
+/// A single file in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    /// A regular file, whose content is the object with the given checksum.
+    Object {
+        checksum: Checksum,
+        /// The permission mode of the file (Unix only).
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mode: Option<u32>,
+    },
+    /// A preserved symbolic link.
+    Symlink { target: PathBuf },
+}
+
+/// The files in a single pile, keyed by their path relative to the pile.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Manifest {
+    files: BTreeMap<PathBuf, Entry>,
+}
+
+impl Manifest {
+    fn load(path: &Path) -> Result<Self, Error> {
+        let content = fs::read(path).map_err(io_err(path))?;
+        serde_json::from_slice(&content).map_err(|error| Error::Manifest {
+            path: path.to_owned(),
+            error,
+        })
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let content = serde_json::to_vec_pretty(self).map_err(|error| Error::Manifest {
+            path: path.to_owned(),
+            error,
+        })?;
+        create_parent(path)?;
+        atomic::write(path, None, |file| file.write_all(&content)).map_err(io_err(path))
+    }
+}
+
+/// The store of file contents, named by checksum.
+#[derive(Debug, Clone)]
+struct ObjectStore {
+    root: PathBuf,
+}
+
+impl ObjectStore {
+    fn new(hoards_root: &Path) -> Self {
+        Self {
+            root: hoards_root.join(OBJECTS_DIR),
+        }
+    }
+
+    /// The path of the object with the given checksum, e.g. `sha256/ab/cdef...`.
+    fn object_path(&self, checksum: &Checksum) -> PathBuf {
+        let (kind, sum) = match checksum {
+            Checksum::MD5(sum) => ("md5", sum),
+            Checksum::SHA256(sum) => ("sha256", sum),
+            Checksum::BLAKE3(sum) => ("blake3", sum),
+        };
+        let split = sum.len().min(2);
+        self.root.join(kind).join(&sum[..split]).join(&sum[split..])
+    }
+
+    /// Stores the content of the file at `path`, which has the given checksum, if no object
+    /// with that checksum exists yet.
+    fn insert(&self, path: &Path, checksum: &Checksum) -> Result<(), Error> {
+        let object = self.object_path(checksum);
+        if object.exists() {
+            return Ok(());
+        }
+        tracing::trace!(?path, ?object, "storing new object");
+        create_parent(&object)?;
+        link_or_copy(path, &object)
+    }
+}
+
+/// A private copy of some hoards in the directory layout. The copy is deleted when dropped.
+#[derive(Debug)]
+pub(crate) struct Checkout {
+    hoards_root: PathBuf,
+    store: ObjectStore,
+    dir: PathBuf,
+    names: Vec<String>,
+}
+
+impl Checkout {
+    /// Checks out the hoards `names` from the content-addressed `hoards_root` into `dir`,
+    /// replacing anything already there.
+    ///
+    /// # Errors
+    ///
+    /// Any [`enum@Error`] from reading the hoards root or creating the checkout.
+    pub(crate) fn new(hoards_root: &Path, dir: &Path, names: &[&str]) -> Result<Self, Error> {
+        let _span = tracing::debug_span!("checkout", ?hoards_root, ?dir).entered();
+        remove_all(dir)?;
+        fs::create_dir_all(dir).map_err(io_err(dir))?;
+
+        let checkout = Self {
+            hoards_root: hoards_root.to_owned(),
+            store: ObjectStore::new(hoards_root),
+            dir: dir.to_owned(),
+            names: names.iter().copied().map(str::to_owned).collect(),
+        };
+
+        for name in &checkout.names {
+            let anonymous_manifest = manifest_path(&hoards_root.join(name));
+            if anonymous_manifest.exists() {
+                checkout.expand(&anonymous_manifest, &dir.join(name))?;
+            }
+            for entry in Self::entries(name) {
+                let src = hoards_root.join(&entry);
+                if fs::symlink_metadata(&src).is_ok() {
+                    checkout.unpack(&src, &dir.join(&entry))?;
+                }
+            }
+        }
+
+        Ok(checkout)
+    }
+
+    /// The root of the checkout, to use in place of the hoards root.
+    pub(crate) fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The names of the files and directories in the hoards root that belong to the hoard
+    /// `name`, other than the manifest of an anonymous pile.
+    fn entries(name: &str) -> Vec<OsString> {
+        let prefix = Path::new(name);
+        vec![
+            prefix.to_owned(),
+            Sidecar::path(prefix),
+            versions::versions_dir(Path::new(""), name),
+        ]
+        .into_iter()
+        .map(PathBuf::into_os_string)
+        .collect()
+    }
+
+    /// Recreates `src` from the hoards root at `dest` in the checkout, expanding manifests.
+    fn unpack(&self, src: &Path, dest: &Path) -> Result<(), Error> {
+        let meta = fs::symlink_metadata(src).map_err(io_err(src))?;
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(src).map_err(io_err(src))?;
+            create_symlink(&target, dest).map_err(io_err(dest))
+        } else if meta.is_dir() {
+            fs::create_dir_all(dest).map_err(io_err(dest))?;
+            for item in fs::read_dir(src).map_err(io_err(src))? {
+                let item = item.map_err(io_err(src))?;
+                let file_name = item.file_name();
+                match file_name
+                    .to_str()
+                    .and_then(|name| name.strip_suffix(MANIFEST_SUFFIX))
+                {
+                    Some(pile_name) => self.expand(&item.path(), &dest.join(pile_name))?,
+                    None => self.unpack(&item.path(), &dest.join(&file_name))?,
+                }
+            }
+            Ok(())
+        } else {
+            fs::copy(src, dest).map_err(io_err(src))?;
+            Ok(())
+        }
+    }
+
+    /// Recreates the pile described by the manifest at `path` at `prefix` in the checkout.
+    fn expand(&self, path: &Path, prefix: &Path) -> Result<(), Error> {
+        let manifest = Manifest::load(path)?;
+        if manifest.files.is_empty() {
+            return fs::create_dir_all(prefix).map_err(io_err(prefix));
+        }
+
+        for (rel_path, entry) in manifest.files {
+            // Anonymous piles of a single file have an empty relative path.
+            let dest = if rel_path.as_os_str().is_empty() {
+                prefix.to_owned()
+            } else {
+                prefix.join(&rel_path)
+            };
+            create_parent(&dest)?;
+            match entry {
+                Entry::Symlink { target } => {
+                    create_symlink(&target, &dest).map_err(io_err(&dest))?;
+                }
+                Entry::Object { checksum, mode } => {
+                    let object = self.store.object_path(&checksum);
+                    fs::copy(&object, &dest).map_err(io_err(&object))?;
+                    #[cfg(unix)]
+                    if let Some(mode) = mode {
+                        use std::os::unix::fs::PermissionsExt;
+                        fs::set_permissions(&dest, fs::Permissions::from_mode(mode))
+                            .map_err(io_err(&dest))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Packs the checked out hoards back into the hoards root, replacing their previous
+    /// contents.
+    ///
+    /// `piles` maps the path of every pile in the checkout, including those in snapshots, to
+    /// the checksum algorithm to name its objects with.
+    ///
+    /// # Errors
+    ///
+    /// Any [`enum@Error`] from reading the checkout or writing to the hoards root.
+    pub(crate) fn commit(&self, piles: &HashMap<PathBuf, ChecksumType>) -> Result<(), Error> {
+        let _span = tracing::debug_span!("commit_checkout", dir = ?self.dir).entered();
+        // Pack into a staging directory first, so a failure leaves the hoards root untouched.
+        let staging = self
+            .hoards_root
+            .join(format!(".checkout-{}.hoard-tmp", std::process::id()));
+        remove_all(&staging)?;
+
+        let result = (|| {
+            for name in &self.names {
+                for entry in Self::entries(name) {
+                    let src = self.dir.join(&entry);
+                    if fs::symlink_metadata(&src).is_ok() {
+                        self.pack(&src, &staging.join(&entry), piles)?;
+                    }
+                }
+            }
+
+            for name in &self.names {
+                let anonymous_manifest = OsString::from(format!("{name}{MANIFEST_SUFFIX}"));
+                for entry in Self::entries(name)
+                    .into_iter()
+                    .chain(Some(anonymous_manifest))
+                {
+                    let dest = self.hoards_root.join(&entry);
+                    remove_all(&dest)?;
+                    let staged = staging.join(&entry);
+                    if fs::symlink_metadata(&staged).is_ok() {
+                        create_parent(&dest)?;
+                        fs::rename(&staged, &dest).map_err(io_err(&dest))?;
+                    }
+                }
+            }
+
+            Ok(())
+        })();
+
+        remove_all(&staging)?;
+        result
+    }
+
+    /// Recreates `src` from the checkout at `dest`, packing piles into manifests.
+    fn pack(
+        &self,
+        src: &Path,
+        dest: &Path,
+        piles: &HashMap<PathBuf, ChecksumType>,
+    ) -> Result<(), Error> {
+        if let Some(checksum_type) = piles.get(src) {
+            let mut manifest = Manifest::default();
+            self.pack_pile(src, src, *checksum_type, &mut manifest)?;
+            return manifest.save(&manifest_path(dest));
+        }
+
+        let meta = fs::symlink_metadata(src).map_err(io_err(src))?;
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(src).map_err(io_err(src))?;
+            create_parent(dest)?;
+            create_symlink(&target, dest).map_err(io_err(dest))
+        } else if meta.is_dir() {
+            fs::create_dir_all(dest).map_err(io_err(dest))?;
+            for item in fs::read_dir(src).map_err(io_err(src))? {
+                let item = item.map_err(io_err(src))?;
+                self.pack(&item.path(), &dest.join(item.file_name()), piles)?;
+            }
+            Ok(())
+        } else {
+            create_parent(dest)?;
+            link_or_copy(src, dest)
+        }
+    }
+
+    /// Adds `path`, in the pile at `root`, to `manifest`, storing the content of any files.
+    fn pack_pile(
+        &self,
+        root: &Path,
+        path: &Path,
+        checksum_type: ChecksumType,
+        manifest: &mut Manifest,
+    ) -> Result<(), Error> {
+        let rel_path = path
+            .strip_prefix(root)
+            .expect("packed paths should always be children of the pile root")
+            .to_owned();
+        let meta = fs::symlink_metadata(path).map_err(io_err(path))?;
+        if meta.file_type().is_symlink() {
+            let target = fs::read_link(path).map_err(io_err(path))?;
+            manifest.files.insert(rel_path, Entry::Symlink { target });
+        } else if meta.is_dir() {
+            for item in fs::read_dir(path).map_err(io_err(path))? {
+                let item = item.map_err(io_err(path))?;
+                self.pack_pile(root, &item.path(), checksum_type, manifest)?;
+            }
+        } else {
+            let checksum = hash_file(path, checksum_type).map_err(io_err(path))?;
+            self.store.insert(path, &checksum)?;
+            let mode = file_mode(&meta);
+            manifest
+                .files
+                .insert(rel_path, Entry::Object { checksum, mode });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        if let Err(error) = remove_all(&self.dir) {
+            tracing::warn!(%error, "failed to remove checkout");
+        }
+    }
+}
+
+/// Adds the objects referenced by all manifests in or below `path` to `referenced`.
+fn referenced_objects(
+    store: &ObjectStore,
+    path: &Path,
+    referenced: &mut HashSet<PathBuf>,
+) -> Result<(), Error> {
+    let meta = fs::symlink_metadata(path).map_err(io_err(path))?;
+    if meta.is_dir() {
+        if path == store.root {
+            return Ok(());
+        }
+        for item in fs::read_dir(path).map_err(io_err(path))? {
+            let item = item.map_err(io_err(path))?;
+            referenced_objects(store, &item.path(), referenced)?;
+        }
+    } else if meta.is_file()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(MANIFEST_SUFFIX))
+    {
+        let manifest = Manifest::load(path)?;
+        referenced.extend(manifest.files.values().filter_map(|entry| match entry {
+            Entry::Object { checksum, .. } => Some(store.object_path(checksum)),
+            Entry::Symlink { .. } => None,
+        }));
+    }
+    Ok(())
+}
+
+/// Deletes the objects in `dir` that are not in `referenced`, and any directories left empty.
+fn delete_unreferenced(
+    dir: &Path,
+    referenced: &HashSet<PathBuf>,
+    dry_run: Option<&DryRun>,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    for item in fs::read_dir(dir).map_err(io_err(dir))? {
+        let path = item.map_err(io_err(dir))?.path();
+        if path.is_dir() {
+            count += delete_unreferenced(&path, referenced, dry_run)?;
+        } else if !referenced.contains(&path) {
+            count += 1;
+            if let Some(dry_run) = dry_run {
+                dry_run.delete(&path);
+            } else {
+                tracing::debug!(path = path.to_string_lossy().as_ref(), "deleting object");
+                fs::remove_file(&path).map_err(io_err(&path))?;
+            }
+        }
+    }
+
+    let is_empty = fs::read_dir(dir).map_err(io_err(dir))?.next().is_none();
+    if is_empty && dry_run.is_none() {
+        fs::remove_dir(dir).map_err(io_err(dir))?;
+    }
+    Ok(count)
+}
+
+/// Deletes all objects in the store in `hoards_root` that no manifest refers to, returning how
+/// many were (or, if `dry_run` is set, would be) deleted.
+///
+/// # Errors
+///
+/// Any [`enum@Error`] from reading manifests or deleting objects.
+pub(crate) fn collect_garbage(
+    hoards_root: &Path,
+    dry_run: Option<&DryRun>,
+) -> Result<usize, Error> {
+    let _span = tracing::debug_span!("collect_garbage", ?hoards_root).entered();
+    let store = ObjectStore::new(hoards_root);
+    if !store.root.exists() {
+        return Ok(0);
+    }
+
+    let mut referenced = HashSet::new();
+    referenced_objects(&store, hoards_root, &mut referenced)?;
+    delete_unreferenced(&store.root, &referenced, dry_run)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piles(checkout: &Checkout, names: &[&str]) -> HashMap<PathBuf, ChecksumType> {
+        names
+            .iter()
+            .map(|name| (checkout.path().join(name), ChecksumType::SHA256))
+            .collect()
+    }
+
+    #[test]
+    fn test_commit_deduplicates_and_checkout_restores() {
+        let hoards_root = tempfile::tempdir().expect("failed to create temp dir");
+        let work = tempfile::tempdir().expect("failed to create temp dir");
+        let checkout_dir = work.path().join("checkout");
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["first", "second"])
+            .expect("failed to check out");
+        for name in ["first", "second"] {
+            let dir = checkout.path().join(name).join("fonts");
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("font.ttf"), "shared content").unwrap();
+        }
+        fs::write(checkout.path().join("first").join("own"), "own content").unwrap();
+        checkout
+            .commit(&piles(&checkout, &["first", "second"]))
+            .expect("failed to commit checkout");
+        drop(checkout);
+        assert!(!checkout_dir.exists());
+
+        assert!(manifest_path(&hoards_root.path().join("first")).is_file());
+        assert!(!hoards_root.path().join("first").exists());
+        let store = ObjectStore::new(hoards_root.path());
+        let mut referenced = HashSet::new();
+        referenced_objects(&store, hoards_root.path(), &mut referenced).unwrap();
+        assert_eq!(referenced.len(), 2, "shared content should be stored once");
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["second"])
+            .expect("failed to check out");
+        assert_eq!(
+            fs::read_to_string(
+                checkout
+                    .path()
+                    .join("second")
+                    .join("fonts")
+                    .join("font.ttf")
+            )
+            .unwrap(),
+            "shared content"
+        );
+        assert!(!checkout.path().join("first").exists());
+    }
+
+    #[test]
+    fn test_changing_checked_out_files_leaves_objects_untouched() {
+        let hoards_root = tempfile::tempdir().expect("failed to create temp dir");
+        let work = tempfile::tempdir().expect("failed to create temp dir");
+        let checkout_dir = work.path().join("checkout");
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["first", "second"])
+            .expect("failed to check out");
+        for name in ["first", "second"] {
+            fs::write(checkout.path().join(name), "shared content").unwrap();
+        }
+        checkout
+            .commit(&piles(&checkout, &["first", "second"]))
+            .expect("failed to commit checkout");
+        drop(checkout);
+
+        // Write to the checked out file in place, without replacing it.
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["first", "second"])
+            .expect("failed to check out");
+        fs::write(checkout.path().join("first"), "changed content").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(
+                checkout.path().join("first"),
+                fs::Permissions::from_mode(0o600),
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(checkout.path().join("second")).unwrap(),
+            "shared content"
+        );
+        drop(checkout);
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["second"])
+            .expect("failed to check out");
+        let second = checkout.path().join("second");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "shared content");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&second).unwrap().permissions().mode();
+            assert_ne!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_collect_garbage_removes_unreferenced_objects() {
+        let hoards_root = tempfile::tempdir().expect("failed to create temp dir");
+        let work = tempfile::tempdir().expect("failed to create temp dir");
+        let checkout_dir = work.path().join("checkout");
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["hoard"])
+            .expect("failed to check out");
+        fs::write(checkout.path().join("hoard"), "old content").unwrap();
+        checkout
+            .commit(&piles(&checkout, &["hoard"]))
+            .expect("failed to commit checkout");
+        drop(checkout);
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["hoard"])
+            .expect("failed to check out");
+        fs::remove_file(checkout.path().join("hoard")).unwrap();
+        fs::write(checkout.path().join("hoard"), "new content").unwrap();
+        checkout
+            .commit(&piles(&checkout, &["hoard"]))
+            .expect("failed to commit checkout");
+        drop(checkout);
+
+        assert_eq!(
+            collect_garbage(hoards_root.path(), Some(&DryRun::default())).unwrap(),
+            1
+        );
+        assert_eq!(collect_garbage(hoards_root.path(), None).unwrap(), 1);
+        assert_eq!(collect_garbage(hoards_root.path(), None).unwrap(), 0);
+
+        let checkout = Checkout::new(hoards_root.path(), &checkout_dir, &["hoard"])
+            .expect("failed to check out");
+        assert_eq!(
+            fs::read_to_string(checkout.path().join("hoard")).unwrap(),
+            "new content"
+        );
+    }
+}
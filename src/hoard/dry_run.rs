@@ -0,0 +1,79 @@
+//! See [`DryRun`].
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Reports the changes that backing up or restoring would make, without making them.
+///
+/// Passed in place of actually touching the filesystem, so the same logic decides what to
+/// copy, create, and delete in both dry and real runs.
+#[derive(Debug, Default)]
+pub struct DryRun {
+    /// Directories that would have been created, so each is only reported once.
+    created_dirs: Mutex<HashSet<PathBuf>>,
+}
+
+impl DryRun {
+    /// Reports each directory that would be created to make `path` exist.
+    pub(crate) fn create_dir_all(&self, path: &Path) {
+        let mut created_dirs = self
+            .created_dirs
+            .lock()
+            .expect("dry run directory set should not be poisoned");
+
+        let mut missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|dir| !dir.exists() && !created_dirs.contains(*dir))
+            .collect();
+        missing.reverse();
+
+        for dir in missing {
+            tracing::info!("would create directory {}", dir.display());
+            created_dirs.insert(dir.to_owned());
+        }
+    }
+
+    /// Reports that `src` would be copied to `dest`, possibly overwriting it.
+    #[allow(clippy::unused_self)]
+    pub(crate) fn copy(&self, src: &Path, dest: &Path) {
+        if dest.exists() {
+            tracing::info!("would overwrite {} with {}", dest.display(), src.display());
+        } else {
+            tracing::info!("would copy {} to {}", src.display(), dest.display());
+        }
+    }
+
+    /// Reports that a symbolic link to `target` would be created at `link`.
+    #[allow(clippy::unused_self)]
+    pub(crate) fn link(&self, link: &Path, target: &Path) {
+        tracing::info!("would link {} to {}", link.display(), target.display());
+    }
+
+    /// Reports that `path` would be deleted.
+    #[allow(clippy::unused_self)]
+    pub(crate) fn delete(&self, path: &Path) {
+        tracing::info!("would delete {}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_dir_all_records_each_directory_once() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let dry_run = DryRun::default();
+        let nested = root.path().join("a").join("b");
+
+        dry_run.create_dir_all(&nested);
+        dry_run.create_dir_all(&root.path().join("a").join("c"));
+
+        let created_dirs = dry_run.created_dirs.lock().unwrap();
+        assert_eq!(created_dirs.len(), 3);
+        assert!(created_dirs.contains(&root.path().join("a")));
+        assert!(created_dirs.contains(&nested));
+        assert!(!root.path().join("a").exists());
+    }
+}
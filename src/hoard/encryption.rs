@@ -0,0 +1,1055 @@
+//! Encryption of pile files stored in the hoards root.
+//!
+//! # Symmetric encryption
+//!
+//! Files are encrypted with XChaCha20-Poly1305 using a key derived from the configured password
+//! with `scrypt`. Each encrypted file has the following layout:
+//!
+//! ```text
+//! | magic (8 bytes) | version (1 byte) | salt (16 bytes) | nonce (19 bytes) | segments |
+//! ```
+//!
+//! The content is split into segments of 64 KiB, which are encrypted in turn with the STREAM
+//! construction, so files never need to be held in memory as a whole. Each segment is followed by
+//! a 16 byte tag.
+//!
+//! Deriving a key is intentionally slow, so a [`Cipher`] uses a single random salt for all files
+//! it encrypts and caches the keys derived for any salts it encounters while decrypting.
+//!
+//! Passwords can also be fetched from a command (`password_cmd`), in which case the first line
+//! printed to stdout is used. Each command is run at most once per [`PasswordCache`].
+//!
+//! # Asymmetric encryption
+//!
+//! Files are encrypted with [age](https://age-encryption.org) to one or more X25519 public keys
+//! (`age1...`), so only the public keys are required to back up. Decrypting requires an identity
+//! file containing a matching private key, which is only read when it is actually needed.
+//!
+//! # Re-keying
+//!
+//! [`PreviousKeys`] decrypts stored files with whatever keys they were encrypted with before, so
+//! `hoard rekey` can re-encrypt them with the current configuration.
+
+use super::format::EncryptionKind;
+use super::pile_config::{AsymmetricEncryption, Encryption, SymmetricEncryption};
+use crate::env_vars::{expand_env_in_path, Error as EnvError};
+use age::{x25519, IdentityFileEntry};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305};
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::{fmt, fs, io};
+use thiserror::Error;
+
+const MAGIC: &[u8] = b"HOARDENC";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+// STREAM uses the last 5 bytes of the nonce for the segment counter and last segment flag.
+const STREAM_NONCE_LEN: usize = NONCE_LEN - 5;
+const SEGMENT_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+// The start of the header of every age-encrypted file.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/";
+
+// scrypt parameters: N = 2^15, r = 8, p = 1, producing a 32 byte key.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+type Salt = [u8; SALT_LEN];
+
+/// Reasons that running a `password_cmd` may fail.
+#[derive(Debug, Error)]
+pub enum PasswordCmdError {
+    /// The command list is empty.
+    #[error("no command was given")]
+    Empty,
+    /// The command could not be started.
+    #[error("failed to run command: {0}")]
+    Spawn(#[from] io::Error),
+    /// The command exited with a failure status.
+    #[error("command exited with failure status: {0}")]
+    Exit(ExitStatus),
+    /// The command did not print a password on the first line of stdout.
+    #[error("command did not print a password")]
+    NoOutput,
+}
+
+/// Errors that may occur while encrypting or decrypting files.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Fetching the password from a `password_cmd` failed.
+    #[error("failed to get password from {command:?}: {error}")]
+    PasswordCmd {
+        /// The configured command.
+        command: Vec<String>,
+        /// The reason the command failed.
+        #[source]
+        error: PasswordCmdError,
+    },
+    /// No public keys were configured for asymmetric encryption.
+    #[error("at least one public key is required for asymmetric encryption")]
+    NoPublicKeys,
+    /// A configured public key is invalid.
+    #[error("invalid public key \"{key}\": {reason}")]
+    InvalidPublicKey {
+        /// The invalid key.
+        key: String,
+        /// Why the key is invalid.
+        reason: &'static str,
+    },
+    /// No password is available to decrypt a symmetrically encrypted file.
+    #[error("no password is available to decrypt symmetrically encrypted data")]
+    NoPassword,
+    /// No identity file was configured, so asymmetrically encrypted files cannot be decrypted.
+    #[error("no identity_file is configured: cannot decrypt files")]
+    NoIdentityFile,
+    /// The configured identity file path could not be expanded.
+    #[error("failed to expand identity_file path: {0}")]
+    ExpandIdentityFile(#[from] EnvError),
+    /// The identity file could not be read.
+    #[error("failed to read identity file {path}: {error}")]
+    ReadIdentityFile {
+        /// The path to the identity file.
+        path: PathBuf,
+        /// The error that occurred while reading.
+        #[source]
+        error: io::Error,
+    },
+    /// Failed to derive a key from the configured password.
+    #[error("failed to derive encryption key from password")]
+    DeriveKey,
+    /// The data is not a file encrypted by `hoard`, or uses an unknown format version.
+    #[error("data is not encrypted by hoard or uses an unsupported format")]
+    InvalidFormat,
+    /// Encrypting the data failed.
+    #[error("failed to encrypt data")]
+    Encrypt,
+    /// Decrypting the data failed, usually due to a wrong password or key, or corrupted data.
+    #[error("failed to decrypt data: wrong password or key, or corrupted data")]
+    Decrypt,
+}
+
+/// Caches passwords fetched from `password_cmd`s so each command only runs once.
+///
+/// A new cache should be created for each run of `hoard`.
+#[derive(Default)]
+pub struct PasswordCache(Mutex<HashMap<Vec<String>, String>>);
+
+impl fmt::Debug for PasswordCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the passwords.
+        f.debug_struct("PasswordCache").finish_non_exhaustive()
+    }
+}
+
+impl PasswordCache {
+    fn run_command(command: &[String]) -> Result<String, PasswordCmdError> {
+        let (program, args) = command.split_first().ok_or(PasswordCmdError::Empty)?;
+        // Inherit stdin and stderr so the command can prompt the user, if necessary.
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(PasswordCmdError::Exit(output.status));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .filter(|line| !line.is_empty())
+            .map(ToOwned::to_owned)
+            .ok_or(PasswordCmdError::NoOutput)
+    }
+
+    /// Returns the password printed by `command`, running it only if it has not been run yet.
+    fn get(&self, command: &[String]) -> Result<String, Error> {
+        let mut passwords = self
+            .0
+            .lock()
+            .expect("password cache should not be poisoned");
+        if let Some(password) = passwords.get(command) {
+            return Ok(password.clone());
+        }
+
+        tracing::debug!(?command, "running command to get encryption password");
+        let password = Self::run_command(command).map_err(|error| Error::PasswordCmd {
+            command: command.to_vec(),
+            error,
+        })?;
+        passwords.insert(command.to_vec(), password.clone());
+        Ok(password)
+    }
+}
+
+/// Encrypts and decrypts file contents for a single pile.
+#[derive(Debug)]
+pub(crate) enum Cipher {
+    /// Symmetric encryption using a password.
+    Password(PasswordCipher),
+    /// Asymmetric encryption to one or more public keys.
+    Recipients(RecipientsCipher),
+}
+
+impl Cipher {
+    /// Create a new [`Cipher`] from the given encryption configuration.
+    ///
+    /// Passwords fetched from a `password_cmd` are stored in and retrieved from `passwords`.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::PasswordCmd`] if a `password_cmd` fails.
+    /// - [`Error::NoPublicKeys`] or [`Error::InvalidPublicKey`] if the configured public keys
+    ///   are not usable.
+    /// - [`Error::ExpandIdentityFile`] if the identity file path cannot be expanded.
+    pub(crate) fn new(encryption: &Encryption, passwords: &PasswordCache) -> Result<Self, Error> {
+        match encryption {
+            Encryption::Symmetric(SymmetricEncryption::Password(password)) => {
+                Ok(Self::Password(PasswordCipher::new(password.clone())))
+            }
+            Encryption::Symmetric(SymmetricEncryption::PasswordCmd(command)) => {
+                let password = passwords.get(command)?;
+                Ok(Self::Password(PasswordCipher::new(password)))
+            }
+            Encryption::Asymmetric(config) => RecipientsCipher::new(config).map(Self::Recipients),
+        }
+    }
+
+    /// The kind of encryption this cipher uses.
+    pub(crate) fn kind(&self) -> EncryptionKind {
+        match self {
+            Self::Password(_) => EncryptionKind::Symmetric,
+            Self::Recipients(_) => EncryptionKind::Asymmetric,
+        }
+    }
+
+    /// Encrypt `plaintext`, returning the encrypted file contents.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::DeriveKey`] or [`Error::Encrypt`] if encryption fails.
+    pub(crate) fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut writer = self.encrypt_writer(Vec::new())?;
+        writer.write_all(plaintext).map_err(|_| Error::Encrypt)?;
+        writer.finish().map_err(|_| Error::Encrypt)
+    }
+
+    /// Returns a writer that encrypts everything written to it and writes the encrypted file
+    /// contents to `writer`. [`EncryptWriter::finish`] must be called once everything is written.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::DeriveKey`] or [`Error::Encrypt`] if encryption fails.
+    pub(crate) fn encrypt_writer<W: Write>(&self, writer: W) -> Result<EncryptWriter<W>, Error> {
+        match self {
+            Self::Password(cipher) => cipher.encrypt_writer(writer).map(EncryptWriter::Password),
+            Self::Recipients(cipher) => {
+                cipher.encrypt_writer(writer).map(EncryptWriter::Recipients)
+            }
+        }
+    }
+
+    /// Decrypt file `content` that was created by [`Cipher::encrypt`].
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::InvalidFormat`] if `content` was not encrypted by `hoard`.
+    /// - [`Error::NoIdentityFile`] or [`Error::ReadIdentityFile`] if the private keys for
+    ///   asymmetric encryption are not available.
+    /// - [`Error::DeriveKey`] or [`Error::Decrypt`] if decryption fails.
+    pub(crate) fn decrypt(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut plaintext = Vec::new();
+        self.decrypt_reader(content)?
+            .read_to_end(&mut plaintext)
+            .map_err(Error::from_io)?;
+        Ok(plaintext)
+    }
+
+    /// Returns a reader of the decrypted content of the encrypted file contents in `reader`.
+    ///
+    /// Only the start of the content is checked here. If a later part of it cannot be decrypted,
+    /// reading fails with an I/O error wrapping the [`Error`].
+    ///
+    /// # Errors
+    ///
+    /// See [`Cipher::decrypt`].
+    pub(crate) fn decrypt_reader<'a, R: Read + 'a>(
+        &'a self,
+        reader: R,
+    ) -> Result<Box<dyn Read + 'a>, Error> {
+        match self {
+            Self::Password(cipher) => cipher.decrypt_reader(reader),
+            Self::Recipients(cipher) => cipher.decrypt_reader(reader),
+        }
+    }
+}
+
+impl Error {
+    /// Recovers the error from an I/O error returned while streaming encrypted content.
+    fn from_io(err: io::Error) -> Self {
+        match err.into_inner() {
+            Some(inner) => inner.downcast().map_or(Self::Decrypt, |err| *err),
+            None => Self::Decrypt,
+        }
+    }
+
+    /// Wraps the error in an I/O error, to return it while streaming encrypted content.
+    fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, self)
+    }
+}
+
+/// Encrypts everything written to it. See [`Cipher::encrypt_writer`].
+pub(crate) enum EncryptWriter<W: Write> {
+    /// Symmetric encryption in segments.
+    Password(SegmentWriter<W>),
+    /// Asymmetric encryption with age.
+    Recipients(age::stream::StreamWriter<W>),
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Encrypts anything still buffered and returns the inner writer.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from writing to the inner writer, or from encrypting.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Password(writer) => writer.finish(),
+            Self::Recipients(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Password(writer) => writer.write(buf),
+            Self::Recipients(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Password(writer) => writer.flush(),
+            Self::Recipients(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Encrypts everything written to it in segments with a [`PasswordCipher`].
+pub(crate) struct SegmentWriter<W: Write> {
+    inner: W,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    /// The plaintext of the segment being written.
+    segment: Vec<u8>,
+}
+
+impl<W: Write> SegmentWriter<W> {
+    fn encryptor(&mut self) -> io::Result<&mut EncryptorBE32<XChaCha20Poly1305>> {
+        self.encryptor
+            .as_mut()
+            .ok_or_else(|| io::Error::other("writing after encryption finished"))
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let encryptor = self
+            .encryptor
+            .take()
+            .ok_or_else(|| io::Error::other("encryption already finished"))?;
+        encryptor
+            .encrypt_last_in_place(b"", &mut self.segment)
+            .map_err(|_| Error::Encrypt.into_io())?;
+        self.inner.write_all(&self.segment)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SegmentWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // The last segment is encrypted differently, so a full segment is only encrypted once
+        // it is known that more content follows.
+        if self.segment.len() == SEGMENT_LEN {
+            let mut segment = std::mem::take(&mut self.segment);
+            self.encryptor()?
+                .encrypt_next_in_place(b"", &mut segment)
+                .map_err(|_| Error::Encrypt.into_io())?;
+            self.inner.write_all(&segment)?;
+            segment.clear();
+            self.segment = segment;
+        }
+        let len = buf.len().min(SEGMENT_LEN - self.segment.len());
+        self.segment.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts content encrypted in segments with a [`PasswordCipher`].
+struct SegmentReader<R: Read> {
+    inner: R,
+    /// `None` once the last segment was decrypted.
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    /// Encrypted content read past the end of the previous segment.
+    read_ahead: Vec<u8>,
+    /// The decrypted content of the current segment.
+    segment: Vec<u8>,
+    /// How much of `segment` was already read.
+    position: usize,
+}
+
+impl<R: Read> SegmentReader<R> {
+    /// Reads and decrypts the next segment.
+    fn next_segment(&mut self) -> io::Result<()> {
+        const ENCRYPTED_LEN: usize = SEGMENT_LEN + TAG_LEN;
+        // Read one byte more than a segment to find out whether it is the last one.
+        let mut segment = std::mem::take(&mut self.read_ahead);
+        let missing = ENCRYPTED_LEN + 1 - segment.len();
+        (&mut self.inner)
+            .take(missing as u64)
+            .read_to_end(&mut segment)?;
+
+        let result = if segment.len() > ENCRYPTED_LEN {
+            self.read_ahead = segment.split_off(ENCRYPTED_LEN);
+            match self.decryptor.as_mut() {
+                Some(decryptor) => decryptor.decrypt_next_in_place(b"", &mut segment),
+                None => return Err(Error::Decrypt.into_io()),
+            }
+        } else {
+            match self.decryptor.take() {
+                Some(decryptor) => decryptor.decrypt_last_in_place(b"", &mut segment),
+                None => return Err(Error::Decrypt.into_io()),
+            }
+        };
+        result.map_err(|_| Error::Decrypt.into_io())?;
+
+        self.segment = segment;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SegmentReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.segment.len() {
+            if self.decryptor.is_none() {
+                return Ok(0);
+            }
+            self.next_segment()?;
+        }
+        let len = buf.len().min(self.segment.len() - self.position);
+        buf[..len].copy_from_slice(&self.segment[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Keys that stored files may have been encrypted with before the current configuration.
+///
+/// Each file is decrypted according to the format it was encrypted with, using the matching
+/// previous key and then the key of the same type from the current [`Cipher`], so that files
+/// already re-encrypted by an interrupted re-key can still be read. Files that are not encrypted
+/// are returned as-is.
+#[derive(Debug, Default)]
+pub(crate) struct PreviousKeys {
+    password: Option<Cipher>,
+    identities: Option<Cipher>,
+}
+
+impl PreviousKeys {
+    /// Create a new [`PreviousKeys`] from a command printing the previous password and/or a file
+    /// containing the previous private keys.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::PasswordCmd`] if `password_cmd` fails.
+    pub(crate) fn new(
+        password_cmd: Option<&[String]>,
+        identity_file: Option<PathBuf>,
+        passwords: &PasswordCache,
+    ) -> Result<Self, Error> {
+        let password = password_cmd
+            .map(|command| {
+                passwords
+                    .get(command)
+                    .map(|password| Cipher::Password(PasswordCipher::new(password)))
+            })
+            .transpose()?;
+        let identities = identity_file.map(|path| {
+            Cipher::Recipients(RecipientsCipher {
+                recipients: Vec::new(),
+                identity_file: Some(path),
+                identities: OnceCell::new(),
+            })
+        });
+
+        Ok(Self {
+            password,
+            identities,
+        })
+    }
+
+    /// Decrypt `content` with the appropriate previous key or, failing that, with `current`.
+    ///
+    /// # Errors
+    ///
+    /// - [`Error::NoPassword`] or [`Error::NoIdentityFile`] if there is no key to decrypt with.
+    /// - Any errors from [`Cipher::decrypt`].
+    pub(crate) fn decrypt(
+        &self,
+        content: &[u8],
+        current: Option<&Cipher>,
+    ) -> Result<Vec<u8>, Error> {
+        let (previous, current, missing) = if content.starts_with(MAGIC) {
+            let current = current.filter(|cipher| matches!(cipher, Cipher::Password(_)));
+            (self.password.as_ref(), current, Error::NoPassword)
+        } else if content.starts_with(AGE_MAGIC) {
+            let current = current.filter(|cipher| matches!(cipher, Cipher::Recipients(_)));
+            (self.identities.as_ref(), current, Error::NoIdentityFile)
+        } else {
+            return Ok(content.to_vec());
+        };
+
+        let mut result = Err(missing);
+        for cipher in previous.into_iter().chain(current) {
+            result = cipher.decrypt(content);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
+
+/// Symmetric encryption with a key derived from a password.
+pub(crate) struct PasswordCipher {
+    password: String,
+    salt: Salt,
+    keys: Mutex<HashMap<Salt, Key>>,
+}
+
+impl fmt::Debug for PasswordCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the password.
+        f.debug_struct("PasswordCipher").finish_non_exhaustive()
+    }
+}
+
+impl PasswordCipher {
+    fn new(password: String) -> Self {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        Self {
+            password,
+            salt,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key_for_salt(&self, salt: &Salt) -> Result<Key, Error> {
+        let mut keys = self
+            .keys
+            .lock()
+            .expect("encryption key cache should not be poisoned");
+        if let Some(key) = keys.get(salt) {
+            return Ok(*key);
+        }
+
+        tracing::trace!("deriving encryption key");
+        let params = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P, 32)
+            .map_err(|_| Error::DeriveKey)?;
+        let mut key = Key::default();
+        scrypt::scrypt(self.password.as_bytes(), salt, &params, &mut key)
+            .map_err(|_| Error::DeriveKey)?;
+        keys.insert(*salt, key);
+        Ok(key)
+    }
+
+    fn encrypt_writer<W: Write>(&self, mut writer: W) -> Result<SegmentWriter<W>, Error> {
+        let key = self.key_for_salt(&self.salt)?;
+        let mut nonce = [0; STREAM_NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let header = [MAGIC, &[VERSION], &self.salt, &nonce].concat();
+        writer.write_all(&header).map_err(|_| Error::Encrypt)?;
+        Ok(SegmentWriter {
+            inner: writer,
+            encryptor: Some(EncryptorBE32::from_aead(
+                XChaCha20Poly1305::new(&key),
+                GenericArray::from_slice(&nonce),
+            )),
+            segment: Vec::with_capacity(SEGMENT_LEN + TAG_LEN),
+        })
+    }
+
+    fn decrypt_reader<'a, R: Read + 'a>(&self, mut reader: R) -> Result<Box<dyn Read + 'a>, Error> {
+        let mut magic = [0; MAGIC.len() + 1];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|_| Error::InvalidFormat)?;
+        let (magic, version) = magic.split_at(MAGIC.len());
+        if magic != MAGIC || version[0] != VERSION {
+            return Err(Error::InvalidFormat);
+        }
+
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; STREAM_NONCE_LEN];
+        reader
+            .read_exact(&mut salt)
+            .and_then(|()| reader.read_exact(&mut nonce))
+            .map_err(|_| Error::InvalidFormat)?;
+        let decryptor = DecryptorBE32::from_aead(
+            XChaCha20Poly1305::new(&self.key_for_salt(&salt)?),
+            GenericArray::from_slice(&nonce),
+        );
+        Ok(Box::new(SegmentReader {
+            inner: reader,
+            decryptor: Some(decryptor),
+            read_ahead: Vec::new(),
+            segment: Vec::new(),
+            position: 0,
+        }))
+    }
+}
+
+/// Asymmetric encryption to age X25519 public keys.
+pub(crate) struct RecipientsCipher {
+    recipients: Vec<x25519::Recipient>,
+    identity_file: Option<PathBuf>,
+    identities: OnceCell<Vec<x25519::Identity>>,
+}
+
+impl fmt::Debug for RecipientsCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the private keys.
+        f.debug_struct("RecipientsCipher")
+            .field("recipients", &self.recipients.len())
+            .field("identity_file", &self.identity_file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RecipientsCipher {
+    fn new(config: &AsymmetricEncryption) -> Result<Self, Error> {
+        if config.public_keys.is_empty() {
+            return Err(Error::NoPublicKeys);
+        }
+
+        let recipients = config
+            .public_keys
+            .iter()
+            .map(|key| {
+                key.parse().map_err(|reason| Error::InvalidPublicKey {
+                    key: key.clone(),
+                    reason,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let identity_file = config
+            .identity_file
+            .as_deref()
+            .map(expand_env_in_path)
+            .transpose()?;
+
+        Ok(Self {
+            recipients,
+            identity_file,
+            identities: OnceCell::new(),
+        })
+    }
+
+    /// Returns the identities from the configured identity file, reading it if necessary.
+    fn identities(&self) -> Result<&[x25519::Identity], Error> {
+        self.identities
+            .get_or_try_init(|| {
+                let path = self.identity_file.as_ref().ok_or(Error::NoIdentityFile)?;
+                tracing::debug!(?path, "reading identity file");
+                let read_err = |error| Error::ReadIdentityFile {
+                    path: path.clone(),
+                    error,
+                };
+                let file = fs::File::open(path).map_err(read_err)?;
+                let identities = age::IdentityFile::from_buffer(io::BufReader::new(file))
+                    .map_err(read_err)?
+                    .into_identities()
+                    .into_iter()
+                    .map(|IdentityFileEntry::Native(identity)| identity)
+                    .collect();
+                Ok(identities)
+            })
+            .map(Vec::as_slice)
+    }
+
+    fn encrypt_writer<W: Write>(&self, writer: W) -> Result<age::stream::StreamWriter<W>, Error> {
+        let recipients = self
+            .recipients
+            .iter()
+            .cloned()
+            .map(|recipient| -> Box<dyn age::Recipient + Send> { Box::new(recipient) })
+            .collect();
+        let encryptor = age::Encryptor::with_recipients(recipients).ok_or(Error::NoPublicKeys)?;
+        encryptor.wrap_output(writer).map_err(|_| Error::Encrypt)
+    }
+
+    fn decrypt_reader<'a, R: Read + 'a>(&self, reader: R) -> Result<Box<dyn Read + 'a>, Error> {
+        let decryptor = match age::Decryptor::new(reader) {
+            Ok(age::Decryptor::Recipients(decryptor)) => decryptor,
+            Ok(age::Decryptor::Passphrase(_)) | Err(_) => return Err(Error::InvalidFormat),
+        };
+
+        let identities = self.identities()?;
+        let reader = decryptor
+            .decrypt(
+                identities
+                    .iter()
+                    .map(|identity| -> &dyn age::Identity { identity }),
+            )
+            .map_err(|_| Error::Decrypt)?;
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher_with_password(password: &str) -> Cipher {
+        Cipher::new(
+            &Encryption::Symmetric(SymmetricEncryption::Password(password.into())),
+            &PasswordCache::default(),
+        )
+        .expect("password encryption should be supported")
+    }
+
+    fn password_cmd(command: &[&str]) -> Encryption {
+        Encryption::Symmetric(SymmetricEncryption::PasswordCmd(
+            command.iter().map(ToString::to_string).collect(),
+        ))
+    }
+
+    #[test]
+    fn test_encrypt_then_decrypt_roundtrip() {
+        let cipher = cipher_with_password("correcthorsebatterystaple");
+        let plaintext = b"some secret file content";
+        let encrypted = cipher
+            .encrypt(plaintext)
+            .expect("encryption should succeed");
+        assert!(encrypted.starts_with(MAGIC));
+        assert!(!encrypted
+            .windows(plaintext.len())
+            .any(|window| window == plaintext));
+
+        // A separate cipher must be able to decrypt, even though it uses a different salt.
+        let other = cipher_with_password("correcthorsebatterystaple");
+        let decrypted = other
+            .decrypt(&encrypted)
+            .expect("decryption should succeed");
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_decrypt_with_wrong_password_fails() {
+        let encrypted = cipher_with_password("right password")
+            .encrypt(b"content")
+            .expect("encryption should succeed");
+        let result = cipher_with_password("wrong password").decrypt(&encrypted);
+        assert!(matches!(result, Err(Error::Decrypt)));
+    }
+
+    #[test]
+    fn test_decrypt_plaintext_fails() {
+        let result = cipher_with_password("password").decrypt(b"not encrypted at all");
+        assert!(matches!(result, Err(Error::InvalidFormat)));
+    }
+
+    #[test]
+    fn test_encrypt_in_segments_roundtrip() {
+        let cipher = cipher_with_password("password");
+        for len in [
+            0,
+            1,
+            SEGMENT_LEN - 1,
+            SEGMENT_LEN,
+            SEGMENT_LEN + 1,
+            3 * SEGMENT_LEN,
+        ] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            // Write in uneven pieces to cross segment boundaries.
+            let mut writer = cipher.encrypt_writer(Vec::new()).unwrap();
+            for chunk in plaintext.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let encrypted = writer.finish().unwrap();
+            let segments = len.max(1).div_ceil(SEGMENT_LEN);
+            assert_eq!(
+                encrypted.len(),
+                MAGIC.len() + 1 + SALT_LEN + STREAM_NONCE_LEN + len + segments * TAG_LEN,
+                "encrypted length for {} bytes",
+                len
+            );
+            assert_eq!(cipher.decrypt(&encrypted).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn test_decrypt_truncated_segments_fails() {
+        let cipher = cipher_with_password("password");
+        let encrypted = cipher.encrypt(&vec![7; 2 * SEGMENT_LEN]).unwrap();
+        // Dropping whole segments must not go unnoticed.
+        let truncated = &encrypted[..encrypted.len() - SEGMENT_LEN - TAG_LEN];
+        assert!(matches!(cipher.decrypt(truncated), Err(Error::Decrypt)));
+        let truncated = &encrypted[..encrypted.len() - 1];
+        assert!(matches!(cipher.decrypt(truncated), Err(Error::Decrypt)));
+    }
+
+    fn asymmetric(public_keys: Vec<String>, identity_file: Option<String>) -> Encryption {
+        Encryption::Asymmetric(AsymmetricEncryption {
+            public_keys,
+            identity_file,
+        })
+    }
+
+    fn write_identity_file(identity: &x25519::Identity) -> tempfile::NamedTempFile {
+        use age::secrecy::ExposeSecret;
+        let mut file = tempfile::NamedTempFile::new().expect("failed to create temp file");
+        writeln!(file, "# created: for testing").unwrap();
+        writeln!(file, "{}", identity.to_string().expose_secret()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_asymmetric_roundtrip_with_multiple_recipients() {
+        let first = x25519::Identity::generate();
+        let second = x25519::Identity::generate();
+        let public_keys = vec![
+            first.to_public().to_string(),
+            second.to_public().to_string(),
+        ];
+
+        // Backing up does not need any private keys.
+        let encrypted = Cipher::new(
+            &asymmetric(public_keys.clone(), None),
+            &PasswordCache::default(),
+        )
+        .unwrap()
+        .encrypt(b"content")
+        .expect("encryption should succeed");
+
+        for identity in &[first, second] {
+            let identity_file = write_identity_file(identity);
+            let cipher = Cipher::new(
+                &asymmetric(
+                    public_keys.clone(),
+                    Some(identity_file.path().to_string_lossy().into_owned()),
+                ),
+                &PasswordCache::default(),
+            )
+            .unwrap();
+            let decrypted = cipher
+                .decrypt(&encrypted)
+                .expect("each recipient should be able to decrypt");
+            assert_eq!(decrypted, b"content");
+        }
+    }
+
+    #[test]
+    fn test_asymmetric_decrypt_errors() {
+        let identity = x25519::Identity::generate();
+        let public_keys = vec![identity.to_public().to_string()];
+        let cipher = Cipher::new(
+            &asymmetric(public_keys.clone(), None),
+            &PasswordCache::default(),
+        )
+        .unwrap();
+        let encrypted = cipher.encrypt(b"content").unwrap();
+        assert!(matches!(
+            cipher.decrypt(&encrypted),
+            Err(Error::NoIdentityFile)
+        ));
+        assert!(matches!(
+            cipher.decrypt(b"not encrypted"),
+            Err(Error::InvalidFormat)
+        ));
+
+        let missing = Cipher::new(
+            &asymmetric(public_keys.clone(), Some("/does/not/exist".into())),
+            &PasswordCache::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            missing.decrypt(&encrypted),
+            Err(Error::ReadIdentityFile { .. })
+        ));
+
+        let other_identity = write_identity_file(&x25519::Identity::generate());
+        let wrong = Cipher::new(
+            &asymmetric(
+                public_keys,
+                Some(other_identity.path().to_string_lossy().into_owned()),
+            ),
+            &PasswordCache::default(),
+        )
+        .unwrap();
+        assert!(matches!(wrong.decrypt(&encrypted), Err(Error::Decrypt)));
+    }
+
+    #[test]
+    fn test_asymmetric_invalid_public_keys() {
+        assert!(matches!(
+            Cipher::new(&asymmetric(Vec::new(), None), &PasswordCache::default()),
+            Err(Error::NoPublicKeys)
+        ));
+        assert!(matches!(
+            Cipher::new(
+                &asymmetric(vec!["not a key".into()], None),
+                &PasswordCache::default()
+            ),
+            Err(Error::InvalidPublicKey { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_password_cmd_uses_first_line() {
+        let passwords = PasswordCache::default();
+        let encrypted = Cipher::new(
+            &password_cmd(&["printf", "hunter2\\nsecond line\\n"]),
+            &passwords,
+        )
+        .expect("password command should succeed")
+        .encrypt(b"content")
+        .expect("encryption should succeed");
+        let decrypted = cipher_with_password("hunter2")
+            .decrypt(&encrypted)
+            .expect("first line should be the password");
+        assert_eq!(decrypted, b"content");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_password_cmd_is_cached() {
+        let passwords = PasswordCache::default();
+        let command = vec!["echo".to_string(), "hunter2".to_string()];
+        assert_eq!(passwords.get(&command).unwrap(), "hunter2");
+        // Replace the cached value to prove the command is not run again.
+        passwords
+            .0
+            .lock()
+            .unwrap()
+            .insert(command.clone(), "cached".into());
+        assert_eq!(passwords.get(&command).unwrap(), "cached");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_password_cmd_errors() {
+        let passwords = PasswordCache::default();
+        for (command, expected) in [
+            (password_cmd(&[]), "Empty"),
+            (password_cmd(&["false"]), "Exit"),
+            (password_cmd(&["true"]), "NoOutput"),
+            (password_cmd(&["/does/not/exist"]), "Spawn"),
+        ] {
+            match Cipher::new(&command, &passwords) {
+                Err(Error::PasswordCmd { error, .. }) => {
+                    assert!(format!("{:?}", error).starts_with(expected));
+                }
+                result => panic!("expected PasswordCmd error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_cipher_debug_hides_password() {
+        let cipher = cipher_with_password("super secret");
+        assert!(!format!("{:?}", cipher).contains("super secret"));
+
+        let passwords = PasswordCache::default();
+        passwords
+            .0
+            .lock()
+            .unwrap()
+            .insert(vec!["cmd".into()], "super secret".into());
+        assert!(!format!("{:?}", passwords).contains("super secret"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_previous_keys_decrypt_by_format() {
+        let old_encrypted = cipher_with_password("old password")
+            .encrypt(b"content")
+            .unwrap();
+        let current = cipher_with_password("new password");
+
+        let previous = PreviousKeys::new(
+            Some(&["printf".to_string(), "old password".to_string()]),
+            None,
+            &PasswordCache::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            previous.decrypt(&old_encrypted, Some(&current)).unwrap(),
+            b"content"
+        );
+        // Unencrypted files are passed through unchanged.
+        assert_eq!(
+            previous.decrypt(b"plaintext", Some(&current)).unwrap(),
+            b"plaintext"
+        );
+
+        let identity = x25519::Identity::generate();
+        let age_encrypted = Cipher::new(
+            &asymmetric(vec![identity.to_public().to_string()], None),
+            &PasswordCache::default(),
+        )
+        .unwrap()
+        .encrypt(b"content")
+        .unwrap();
+        assert!(matches!(
+            previous.decrypt(&age_encrypted, Some(&current)),
+            Err(Error::NoIdentityFile)
+        ));
+
+        let identity_file = write_identity_file(&identity);
+        let previous = PreviousKeys::new(
+            None,
+            Some(identity_file.path().to_owned()),
+            &PasswordCache::default(),
+        )
+        .unwrap();
+        assert_eq!(previous.decrypt(&age_encrypted, None).unwrap(), b"content");
+        // Without a previous password, the current one is tried.
+        assert!(matches!(
+            previous.decrypt(&old_encrypted, Some(&current)),
+            Err(Error::Decrypt)
+        ));
+        let new_encrypted = current.encrypt(b"content").unwrap();
+        assert_eq!(
+            previous.decrypt(&new_encrypted, Some(&current)).unwrap(),
+            b"content"
+        );
+        assert!(matches!(
+            previous.decrypt(&old_encrypted, None),
+            Err(Error::NoPassword)
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+//! How files are encoded when stored in the hoard. See [`StoredFormat`].
+//!
+//! The format of every file is recorded in the pile's metadata sidecar when it is backed up, so
+//! stored files are read according to how they were actually stored instead of how the pile is
+//! configured now. Files without a recorded format are stored as-is.
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::compression::Compression;
+use super::encryption::{Cipher, Error as EncryptionError};
+
+/// The kind of encryption a stored file is encrypted with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EncryptionKind {
+    /// Encrypted with a password.
+    Symmetric,
+    /// Encrypted to one or more public keys.
+    Asymmetric,
+}
+
+/// How a single file is stored in the hoard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredFormat {
+    /// How the file is encrypted, if at all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) encryption: Option<EncryptionKind>,
+    /// How the file is compressed, before any encryption.
+    #[serde(default, skip_serializing_if = "is_uncompressed")]
+    pub(crate) compression: Compression,
+}
+
+// Takes a reference for `skip_serializing_if`.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_uncompressed(compression: &Compression) -> bool {
+    compression.is_none()
+}
+
+impl StoredFormat {
+    /// The format that files are stored in when encrypted with `cipher`, if any, and compressed
+    /// with `compression`.
+    pub(crate) fn new(cipher: Option<&Cipher>, compression: Compression) -> Self {
+        Self {
+            encryption: cipher.map(Cipher::kind),
+            compression,
+        }
+    }
+
+    /// Returns whether files in this format are stored as-is.
+    // Takes a reference for `skip_serializing_if`.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub(crate) fn is_plain(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the cipher to encrypt or decrypt files in this format with, if they are
+    /// encrypted.
+    ///
+    /// # Errors
+    ///
+    /// [`EncryptionError::NoPassword`] or [`EncryptionError::NoIdentityFile`] if files are
+    /// encrypted, but `cipher` is not of the same kind.
+    fn cipher(self, cipher: Option<&Cipher>) -> Result<Option<&Cipher>, EncryptionError> {
+        match (self.encryption, cipher) {
+            (None, _) => Ok(None),
+            (Some(kind), Some(cipher)) if cipher.kind() == kind => Ok(Some(cipher)),
+            (Some(EncryptionKind::Symmetric), _) => Err(EncryptionError::NoPassword),
+            (Some(EncryptionKind::Asymmetric), _) => Err(EncryptionError::NoIdentityFile),
+        }
+    }
+
+    /// Compresses and encrypts the content of `reader` into this format, using `cipher`, and
+    /// writes it to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from reading or writing. Encryption errors are wrapped in an I/O error, see
+    /// [`encryption_error`].
+    pub(crate) fn encode<R: Read, W: Write>(
+        self,
+        cipher: Option<&Cipher>,
+        reader: &mut R,
+        writer: W,
+    ) -> io::Result<()> {
+        match self.cipher(cipher).map_err(wrap)? {
+            None => {
+                let mut encoder = self.compression.encoder(writer)?;
+                io::copy(reader, &mut encoder)?;
+                encoder.finish()?;
+            }
+            Some(cipher) => {
+                let writer = cipher.encrypt_writer(writer).map_err(wrap)?;
+                let mut encoder = self.compression.encoder(writer)?;
+                io::copy(reader, &mut encoder)?;
+                encoder.finish()?.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a reader of the original content of `reader`, which is stored in this format, so
+    /// decrypting it with `cipher` and decompressing it as necessary.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from setting up the decoder. Encryption errors, which may also occur while
+    /// reading, are wrapped in an I/O error, see [`encryption_error`].
+    pub(crate) fn decoder<'a, R: Read + 'a>(
+        self,
+        cipher: Option<&'a Cipher>,
+        reader: R,
+    ) -> io::Result<Box<dyn Read + 'a>> {
+        match self.cipher(cipher).map_err(wrap)? {
+            None => self.compression.decoder(reader),
+            Some(cipher) => {
+                let reader = cipher.decrypt_reader(reader).map_err(wrap)?;
+                self.compression.decoder(reader)
+            }
+        }
+    }
+}
+
+fn wrap(err: EncryptionError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// Returns the encryption error wrapped in `err` by [`StoredFormat::encode`] or
+/// [`StoredFormat::decoder`], or `err` itself if it is any other I/O error.
+///
+/// # Errors
+///
+/// `err` if it does not wrap an encryption error.
+pub(crate) fn encryption_error(err: io::Error) -> Result<EncryptionError, io::Error> {
+    match err.get_ref() {
+        Some(inner) if inner.is::<EncryptionError>() => {}
+        _ => return Err(err),
+    }
+    let kind = err.kind();
+    match err.into_inner() {
+        Some(inner) => inner
+            .downcast()
+            .map(|err| *err)
+            .map_err(|inner| io::Error::new(kind, inner)),
+        None => Err(kind.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoard::encryption::PasswordCache;
+    use crate::hoard::pile_config::{Encryption, SymmetricEncryption};
+
+    fn decode(
+        format: StoredFormat,
+        cipher: Option<&Cipher>,
+        content: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        format.decoder(cipher, content)?.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn test_decode_follows_recorded_format() {
+        let encryption = Encryption::Symmetric(SymmetricEncryption::Password("password".into()));
+        let cipher = Cipher::new(&encryption, &PasswordCache::default()).unwrap();
+        let encoded = StoredFormat::new(Some(&cipher), Compression::Zstd);
+        let mut content = Vec::new();
+        encoded
+            .encode(Some(&cipher), &mut &b"content"[..], &mut content)
+            .unwrap();
+
+        assert_eq!(
+            decode(encoded, Some(&cipher), &content).unwrap(),
+            b"content"
+        );
+        let err = decode(encoded, None, &content).unwrap_err();
+        assert!(matches!(
+            encryption_error(err),
+            Ok(EncryptionError::NoPassword)
+        ));
+        // Files stored before encryption or compression was enabled are read as-is.
+        assert_eq!(
+            decode(StoredFormat::default(), Some(&cipher), b"plaintext").unwrap(),
+            b"plaintext"
+        );
+        assert!(StoredFormat::default().is_plain());
+        assert!(!encoded.is_plain());
+    }
+
+    #[test]
+    fn test_encryption_error_keeps_other_errors() {
+        let err = encryption_error(io::Error::from_raw_os_error(2)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(2));
+    }
+}
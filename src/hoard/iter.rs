@@ -1,14 +1,17 @@
-use md5::Digest;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Formatter;
 use std::io;
 use std::iter::Peekable;
 use std::path::{Path, PathBuf};
 use std::{fmt, fs};
 
-use super::{Direction, Hoard, HoardPath, SystemPath};
+use super::encryption::{Cipher, Error as EncryptionError, PasswordCache};
+use super::format::StoredFormat;
+use super::metadata::{FileMetadata, Sidecar};
+use super::symlinks::{enter_dir, is_symlink};
+use super::{Direction, Hoard, HoardPath, Pile, Symlinks, SystemPath};
 use crate::checkers::history::operation::{
-    Error as OperationError, Hoard as OpHoard, HoardOperation,
+    Checksum, Error as OperationError, Hoard as OpHoard, HoardOperation,
 };
 use crate::diff::{diff_files, Diff};
 use crate::filters::Filter;
@@ -47,8 +50,14 @@ pub(crate) enum HoardDiff {
     },
     PermissionsModified {
         path: PathBuf,
-        hoard_perms: std::fs::Permissions,
-        system_perms: std::fs::Permissions,
+        hoard_perms: fs::Permissions,
+        system_perms: fs::Permissions,
+        diff_source: DiffSource,
+    },
+    MetadataModified {
+        path: PathBuf,
+        /// Descriptions of the metadata that changed.
+        changes: Vec<&'static str>,
         diff_source: DiffSource,
     },
     Created {
@@ -61,6 +70,10 @@ pub(crate) enum HoardDiff {
     },
     Deleted {
         path: PathBuf,
+        /// The path of the file in the hoard.
+        hoard_path: PathBuf,
+        /// The name of the pile the file belongs to, if the hoard has named piles.
+        pile_name: Option<String>,
         diff_source: DiffSource,
     },
 }
@@ -70,19 +83,36 @@ pub enum Error {
     #[error("failed to create diff: {0}")]
     Diff(#[from] FilterError),
     #[error("I/O error occurred: {0}")]
-    IO(#[from] std::io::Error),
+    IO(#[from] io::Error),
     #[error("failed to check hoard operations: {0}")]
     Operation(#[from] OperationError),
+    #[error("failed to set up decryption: {0}")]
+    Encryption(#[from] EncryptionError),
+}
+
+/// A file or directory in a pile that is yet to be visited.
+struct RootPath {
+    pile_name: Option<String>,
+    hoard_path: HoardPath,
+    system_path: SystemPath,
+    filters: Option<Filters>,
+    /// The path of the pile on the system, which `filters` are applied relative to.
+    pile_root: PathBuf,
+    symlinks: Symlinks,
+    /// Canonical paths of the directories containing this one, to detect symbolic link loops.
+    ancestors: Vec<PathBuf>,
 }
 
 pub(crate) struct HoardFilesIter {
-    root_paths: Vec<(Option<String>, HoardPath, SystemPath, Option<Filters>)>,
+    root_paths: Vec<RootPath>,
     direction: Direction,
     pile_name: Option<String>,
     dir_entries: Option<Peekable<fs::ReadDir>>,
-    src_root: Option<PathBuf>,
     dest_root: Option<PathBuf>,
     filter: Option<Filters>,
+    pile_root: PathBuf,
+    symlinks: Symlinks,
+    ancestors: Vec<PathBuf>,
 }
 
 impl HoardFilesIter {
@@ -95,28 +125,38 @@ impl HoardFilesIter {
         let root_paths = match hoard {
             Hoard::Anonymous(pile) => {
                 let path = pile.path.clone();
-                let filters = pile.config.as_ref().map(Filters::new).transpose()?;
+                let filters = Some(pile.filters(direction)?);
                 match path {
                     None => Vec::new(),
-                    Some(path) => {
-                        let hoard_path = HoardPath(hoards_root.join(hoard_name));
-                        let system_path = SystemPath(path);
-                        vec![(None, hoard_path, system_path, filters)]
-                    }
+                    Some(path) => vec![RootPath {
+                        pile_name: None,
+                        hoard_path: HoardPath(hoards_root.join(hoard_name)),
+                        pile_root: path.clone(),
+                        system_path: SystemPath(path),
+                        filters,
+                        symlinks: pile.symlinks(),
+                        ancestors: Vec::new(),
+                    }],
                 }
             }
             Hoard::Named(piles) => piles
                 .piles
                 .iter()
                 .filter_map(|(name, pile)| {
-                    let filters = match pile.config.as_ref().map(Filters::new).transpose() {
-                        Ok(filters) => filters,
+                    let filters = match pile.filters(direction) {
+                        Ok(filters) => Some(filters),
                         Err(err) => return Some(Err(err)),
                     };
                     pile.path.as_ref().map(|path| {
-                        let hoard_path = HoardPath(hoards_root.join(hoard_name).join(name));
-                        let system_path = SystemPath(path.clone());
-                        Ok((Some(name.clone()), hoard_path, system_path, filters))
+                        Ok(RootPath {
+                            pile_name: Some(name.clone()),
+                            hoard_path: HoardPath(hoards_root.join(hoard_name).join(name)),
+                            system_path: SystemPath(path.clone()),
+                            pile_root: path.clone(),
+                            filters,
+                            symlinks: pile.symlinks(),
+                            ancestors: Vec::new(),
+                        })
                     })
                 })
                 .collect::<Result<_, _>>()?,
@@ -127,52 +167,227 @@ impl HoardFilesIter {
             direction,
             pile_name: None,
             dir_entries: None,
-            src_root: None,
             dest_root: None,
             filter: None,
+            pile_root: PathBuf::new(),
+            symlinks: Symlinks::default(),
+            ancestors: Vec::new(),
         })
     }
 
+    /// Returns a [`Cipher`] for every pile in `hoard` that has encryption configured, keyed by
+    /// pile name.
+    fn pile_ciphers(
+        hoard: &Hoard,
+        passwords: &PasswordCache,
+    ) -> Result<HashMap<Option<String>, Cipher>, Error> {
+        let ciphers = match hoard {
+            Hoard::Anonymous(pile) => pile
+                .cipher(passwords)?
+                .map(|cipher| (None, cipher))
+                .into_iter()
+                .collect(),
+            Hoard::Named(piles) => piles
+                .piles
+                .iter()
+                .filter_map(|(name, pile)| {
+                    pile.cipher(passwords)
+                        .transpose()
+                        .map(|cipher| cipher.map(|cipher| (Some(name.clone()), cipher)))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(ciphers)
+    }
+
+    /// Loads the metadata [`Sidecar`] of every pile in `hoard`, keyed by pile name.
+    fn pile_sidecars(
+        hoards_root: &Path,
+        hoard_name: &str,
+        hoard: &Hoard,
+    ) -> Result<HashMap<Option<String>, Sidecar>, Error> {
+        let prefix = hoards_root.join(hoard_name);
+        let sidecars = match hoard {
+            Hoard::Anonymous(_) => vec![(None, Sidecar::load(&prefix)?)],
+            Hoard::Named(piles) => piles
+                .piles
+                .keys()
+                .map(|name| Ok((Some(name.clone()), Sidecar::load(&prefix.join(name))?)))
+                .collect::<io::Result<_>>()?,
+        };
+
+        Ok(sidecars.into_iter().collect())
+    }
+
+    /// Returns the format that the file at `system_path` is stored in, according to `sidecar`.
+    fn stored_format(
+        pile: Option<&Pile>,
+        sidecar: Option<&Sidecar>,
+        system_path: &Path,
+    ) -> StoredFormat {
+        let rel_path = pile
+            .and_then(|pile| pile.path.as_ref())
+            .and_then(|root| system_path.strip_prefix(root).ok());
+        match (sidecar, rel_path) {
+            (Some(sidecar), Some(rel_path)) => sidecar.format(rel_path),
+            _ => StoredFormat::default(),
+        }
+    }
+
+    /// Compares the metadata of `system_path` with the metadata recorded when it was last
+    /// backed up, if any.
+    fn metadata_diff(
+        pile: Option<&Pile>,
+        sidecar: Option<&Sidecar>,
+        system_path: &Path,
+    ) -> io::Result<Option<Diff>> {
+        let (Some(pile), Some(sidecar)) = (pile, sidecar) else {
+            return Ok(None);
+        };
+        let recorded = pile
+            .path
+            .as_ref()
+            .and_then(|root| system_path.strip_prefix(root).ok())
+            .and_then(|rel_path| sidecar.get(rel_path));
+        let Some(recorded) = recorded else {
+            return Ok(None);
+        };
+
+        let options = pile.metadata_options();
+        let current = FileMetadata::read(system_path, options)?;
+        let changes = recorded.changes(&current, options);
+        Ok((!changes.is_empty()).then_some(Diff::Metadata(changes)))
+    }
+
+    /// Returns every file in the hoard `hoard_name` that exists in the hoards root, on the system,
+    /// or both.
+    fn all_paths(
+        hoards_root: &Path,
+        hoard_name: &str,
+        hoard: &Hoard,
+    ) -> Result<HashSet<(Option<String>, HoardPath, SystemPath)>, Error> {
+        Self::new(hoards_root, Direction::Backup, hoard_name, hoard)?
+            .chain(Self::new(
+                hoards_root,
+                Direction::Restore,
+                hoard_name,
+                hoard,
+            )?)
+            .collect::<Result<_, _>>()
+            .map_err(Error::from)
+    }
+
+    /// Returns the path of `system_path` relative to the pile in `hoard` that contains it.
+    fn pile_rel_path<'a>(hoard: &Hoard, system_path: &'a Path) -> &'a Path {
+        let prefix = match hoard {
+            Hoard::Anonymous(pile) => pile
+                .path
+                .as_ref()
+                .expect("hoard path should be guaranteed here"),
+            Hoard::Named(piles) => piles
+                .piles
+                .values()
+                .filter_map(|pile| pile.path.as_ref())
+                .find(|path| system_path.starts_with(path))
+                .expect("path should always start with a pile path"),
+        };
+
+        system_path
+            .strip_prefix(prefix)
+            .expect("prefix should always match path")
+    }
+
+    /// Returns the files in the hoard `hoard_name` that were deleted on one side since the last
+    /// operation on this system, as [`HoardDiff::Deleted`].
+    ///
+    /// These are the same deletions that [`HoardFilesIter::file_diffs`] reports, but only
+    /// whether files exist and the operation logs are checked. Stored files are never read, so
+    /// this works for encrypted piles without any keys to decrypt them.
+    pub(crate) fn deletions(
+        hoards_root: &Path,
+        hoard_name: &str,
+        hoard: &Hoard,
+    ) -> Result<Vec<HoardDiff>, Error> {
+        let _span = tracing::trace_span!("deletions_iterator").entered();
+        let mut deletions = Vec::new();
+        for (pile_name, hoard_path, system_path) in Self::all_paths(hoards_root, hoard_name, hoard)?
+        {
+            let preserves_symlinks = hoard
+                .pile(pile_name.as_deref())
+                .is_some_and(|pile| pile.symlinks() == Symlinks::Preserve);
+            // Matches how `diff_files` decides whether a file is missing.
+            let exists = |path: &Path| {
+                if preserves_symlinks {
+                    fs::symlink_metadata(path).is_ok()
+                } else {
+                    path.exists()
+                }
+            };
+            let (in_hoard, on_system) = (exists(hoard_path.as_ref()), exists(system_path.as_ref()));
+            if in_hoard == on_system {
+                continue;
+            }
+
+            let rel_path = Self::pile_rel_path(hoard, system_path.as_ref());
+            if !HoardOperation::file_has_records(hoard_name, rel_path)? {
+                continue;
+            }
+            let has_remote_changes = HoardOperation::file_has_remote_changes(hoard_name, rel_path)?;
+            let diff_source = if on_system {
+                // Deleted from the hoard by another system.
+                (has_remote_changes || HoardOperation::file_deleted_remotely(hoard_name, rel_path)?)
+                    .then_some(DiffSource::Remote)
+            } else {
+                // Deleted from this system after it was last backed up or restored here.
+                let has_local_records =
+                    HoardOperation::latest_local(hoard_name, Some(rel_path))?.is_some();
+                (has_local_records && !has_remote_changes).then_some(DiffSource::Local)
+            };
+
+            if let Some(diff_source) = diff_source {
+                deletions.push(HoardDiff::Deleted {
+                    path: system_path.0,
+                    hoard_path: hoard_path.0,
+                    pile_name,
+                    diff_source,
+                });
+            }
+        }
+
+        Ok(deletions)
+    }
+
     #[allow(clippy::too_many_lines)]
     pub(crate) fn file_diffs(
         hoards_root: &Path,
         hoard_name: &str,
         hoard: &Hoard,
+        passwords: &PasswordCache,
     ) -> Result<Vec<HoardDiff>, Error> {
         let _span = tracing::trace_span!("file_diffs_iterator").entered();
-        let paths: HashSet<(Option<String>, HoardPath, SystemPath)> =
-            Self::new(hoards_root, Direction::Backup, hoard_name, hoard)?
-                .chain(Self::new(
-                    hoards_root,
-                    Direction::Restore,
-                    hoard_name,
-                    hoard,
-                )?)
-                .collect::<Result<_, _>>()?;
+        let paths = Self::all_paths(hoards_root, hoard_name, hoard)?;
+
+        let ciphers = Self::pile_ciphers(hoard, passwords)?;
+        let sidecars = Self::pile_sidecars(hoards_root, hoard_name, hoard)?;
 
         paths
             .into_iter()
             .filter_map(|(pile_name, h, s)| {
-                diff_files(h.as_ref(), s.as_ref()).transpose().map(|diff| (pile_name, h, s, diff))
+                let cipher = ciphers.get(&pile_name);
+                let pile = hoard.pile(pile_name.as_deref());
+                let preserve_symlinks = pile.is_some_and(|pile| pile.symlinks() == Symlinks::Preserve);
+                let format = Self::stored_format(pile, sidecars.get(&pile_name), s.as_ref());
+                let diff = match diff_files(h.as_ref(), s.as_ref(), format, cipher, preserve_symlinks) {
+                    Ok(None) if !preserve_symlinks || !is_symlink(s.as_ref()) => {
+                        Self::metadata_diff(pile, sidecars.get(&pile_name), s.as_ref())
+                    }
+                    result => result,
+                };
+                diff.transpose().map(|diff| (pile_name, h, s, diff))
             })
             .map(move |(pile_name, hoard_path, system_path, diff)| {
-                let prefix = match hoard {
-                    Hoard::Anonymous(pile) => pile
-                        .path
-                        .as_ref()
-                        .expect("hoard path should be guaranteed here"),
-                    Hoard::Named(piles) => piles
-                        .piles
-                        .values()
-                        .filter_map(|pile| pile.path.as_ref())
-                        .find(|path| system_path.as_ref().starts_with(path))
-                        .expect("path should always start with a pile path"),
-                };
-
-                let rel_path = system_path
-                    .as_ref()
-                    .strip_prefix(prefix)
-                    .expect("prefix should always match path");
+                let rel_path = Self::pile_rel_path(hoard, system_path.as_ref());
 
                 let has_same_permissions = {
                     let hoard_perms = fs::File::open(hoard_path.as_ref())
@@ -194,6 +409,10 @@ impl HoardFilesIter {
                 let local_record = HoardOperation::latest_local(hoard_name, Some(rel_path))?;
                 let has_local_records = local_record.is_some();
 
+                let preserves_symlinks = hoard
+                    .pile(pile_name.as_deref())
+                    .is_some_and(|pile| pile.symlinks() == Symlinks::Preserve);
+
                 let has_local_content_changes = if let Some(HoardOperation { ref hoard, .. }) = local_record {
                     tracing::trace!("operation hoard: {:?}, pile: {:?}, rel_path: {:?}", hoard, pile_name, rel_path);
                     let checksum = match hoard {
@@ -201,21 +420,27 @@ impl HoardFilesIter {
                             op_pile.get(rel_path).map(ToOwned::to_owned)
                         },
                         OpHoard::Named(op_piles) => {
-                            let pile_name = pile_name.expect("pile name should exist");
-                            op_piles.get(&pile_name).and_then(|op_pile| op_pile.get(rel_path)).map(ToOwned::to_owned)
+                            let pile_name = pile_name.as_ref().expect("pile name should exist");
+                            op_piles.get(pile_name).and_then(|op_pile| op_pile.get(rel_path)).map(ToOwned::to_owned)
                         },
                     };
 
                     if let Some(checksum) = checksum {
                         tracing::trace!("{} ({}) previously had checksum {} on this system", system_path.as_ref().display(), rel_path.display(), checksum);
-                        match fs::read(system_path.as_ref()) {
+                        let content = if preserves_symlinks && is_symlink(system_path.as_ref()) {
+                            fs::read_link(system_path.as_ref())
+                                .map(|target| target.to_string_lossy().into_owned().into_bytes())
+                        } else {
+                            fs::read(system_path.as_ref())
+                        };
+                        match content {
                             Err(err) => if let io::ErrorKind::NotFound = err.kind() {
                                 false
                             } else {
                                 return Err(OperationError::IO(err));
                             },
                             Ok(content) => {
-                                let new_sum = format!("{:x}", md5::Md5::digest(&content));
+                                let new_sum = Checksum::from_content(&content, checksum.checksum_type());
                                 tracing::trace!("{} currently has checksum {}", system_path.as_ref().display(), new_sum);
                                 new_sum != checksum
                             }
@@ -270,13 +495,18 @@ impl HoardFilesIter {
                         // Cannot track sources of permissions changes, so just mark Mixed
                         path, diff_source: DiffSource::Mixed, hoard_perms, system_perms
                     },
+                    Diff::Metadata(changes) => HoardDiff::MetadataModified {
+                        // Metadata is recorded on every backup, so a difference is either from
+                        // a newer remote backup or a local change since the last operation.
+                        path, changes, diff_source: if has_remote_changes { DiffSource::Remote } else { DiffSource::Local },
+                    },
                     Diff::LeftNotExists => {
                         // File not in hoard directory
                         if has_hoard_records {
                             // Used to exist in hoard directory
-                            if has_remote_changes {
+                            if has_remote_changes || HoardOperation::file_deleted_remotely(hoard_name, rel_path)? {
                                 // Most recent operation is remote, probably deleted
-                                HoardDiff::Deleted { path, diff_source: DiffSource::Remote }
+                                HoardDiff::Deleted { path, hoard_path: hoard_path.0, pile_name, diff_source: DiffSource::Remote }
                             } else {
                                 // Most recent operation is local, probably recreated file
                                 HoardDiff::Recreated { path, diff_source: DiffSource::Local }
@@ -296,7 +526,7 @@ impl HoardFilesIter {
                                     HoardDiff::Recreated { path, diff_source: DiffSource::Remote }
                                 } else {
                                     // Deleted locally
-                                    HoardDiff::Deleted { path, diff_source: DiffSource::Local }
+                                    HoardDiff::Deleted { path, hoard_path: hoard_path.0, pile_name, diff_source: DiffSource::Local }
                                 }
                             } else {
                                 // Created remotely
@@ -325,23 +555,39 @@ impl Iterator for HoardFilesIter {
             {
                 match self.root_paths.pop() {
                     None => return None,
-                    Some((pile_name, hoard_path, system_path, filters)) => {
+                    Some(RootPath {
+                        pile_name,
+                        hoard_path,
+                        system_path,
+                        filters,
+                        pile_root,
+                        symlinks,
+                        mut ancestors,
+                    }) => {
                         let (src, dest) = match self.direction {
                             Direction::Backup => (&system_path.0, &hoard_path.0),
                             Direction::Restore => (&hoard_path.0, &system_path.0),
                         };
 
+                        // Filters always apply to the path on the system.
                         if src.is_file()
                             && filters
                                 .as_ref()
-                                .map_or(true, |filter| filter.keep(&PathBuf::new(), src))
+                                .is_none_or(|filter| filter.keep(&pile_root, &system_path.0))
                         {
                             return Some(Ok((pile_name, hoard_path, system_path)));
                         } else if src.is_dir() {
-                            self.src_root = Some(src.clone());
+                            match enter_dir(&ancestors, src) {
+                                Err(err) => return Some(Err(err)),
+                                Ok(None) => continue,
+                                Ok(Some(canonical)) => ancestors.push(canonical),
+                            }
                             self.dest_root = Some(dest.clone());
                             self.pile_name = pile_name;
                             self.filter = filters;
+                            self.pile_root = pile_root;
+                            self.symlinks = symlinks;
+                            self.ancestors = ancestors;
                             match fs::read_dir(src) {
                                 Ok(iter) => self.dir_entries = Some(iter.peekable()),
                                 Err(err) => return Some(Err(err)),
@@ -363,42 +609,37 @@ impl Iterator for HoardFilesIter {
                     .as_ref()
                     .expect("dest_root should not be None")
                     .join(entry.file_name());
-                let is_file = src.is_file();
-                let is_dir = src.is_dir();
-
-                let keep = match self.direction {
-                    Direction::Backup => {
-                        let prefix = self.src_root.as_ref().expect("src_root should not be None");
-                        self.filter
-                            .as_ref()
-                            .map_or(true, |filter| filter.keep(prefix, &src))
-                    }
-                    Direction::Restore => {
-                        let prefix = self
-                            .dest_root
-                            .as_ref()
-                            .expect("dest_root should not be None");
-                        self.filter
-                            .as_ref()
-                            .map_or(true, |filter| filter.keep(prefix, &dest))
-                    }
-                };
+                let is_symlink = is_symlink(&src);
+                let is_file = src.is_file() || (is_symlink && self.symlinks == Symlinks::Preserve);
+                let is_dir = src.is_dir() && !is_file;
+
+                if is_symlink && self.symlinks == Symlinks::Skip {
+                    continue;
+                }
 
                 let (hoard_path, system_path) = match self.direction {
                     Direction::Backup => (HoardPath(dest), SystemPath(src)),
                     Direction::Restore => (HoardPath(src), SystemPath(dest)),
                 };
 
+                let keep = self
+                    .filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.keep_path(&self.pile_root, &system_path.0, is_dir));
+
                 if keep {
                     if is_file {
                         return Some(Ok((self.pile_name.clone(), hoard_path, system_path)));
                     } else if is_dir {
-                        self.root_paths.push((
-                            self.pile_name.clone(),
+                        self.root_paths.push(RootPath {
+                            pile_name: self.pile_name.clone(),
                             hoard_path,
                             system_path,
-                            self.filter.clone(),
-                        ));
+                            filters: self.filter.clone(),
+                            pile_root: self.pile_root.clone(),
+                            symlinks: self.symlinks,
+                            ancestors: self.ancestors.clone(),
+                        });
                     }
                 }
             }
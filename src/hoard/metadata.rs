@@ -0,0 +1,385 @@
+//! File metadata that is preserved across backups and restores.
+//!
+//! Modification and access times are always preserved. On Unix, the permission mode is also
+//! recorded and, if enabled for a pile, the owning user and group and any extended attributes.
+//!
+//! Because not every tool that syncs the hoard between systems preserves metadata, the metadata
+//! of every backed up file is also recorded in a sidecar file next to the pile's directory in
+//! the hoard, along with the [`StoredFormat`] of the file. See [`Sidecar`].
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+use serde::{Deserialize, Serialize};
+
+use super::format::StoredFormat;
+
+/// The suffix added to a pile's directory name to get the sidecar file name.
+const SIDECAR_SUFFIX: &str = ".metadata.json";
+
+/// Which optional metadata to preserve.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct MetadataOptions {
+    /// Whether to preserve the owning user and group.
+    pub(crate) ownership: bool,
+    /// Whether to preserve extended attributes.
+    pub(crate) xattrs: bool,
+}
+
+/// The preserved metadata of a single file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    accessed: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    gid: Option<u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+/// Truncates a timestamp to whole seconds, so that comparisons are not affected by filesystems
+/// with coarser timestamps.
+fn whole_seconds(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+impl FileMetadata {
+    /// Reads the metadata of the file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from reading the metadata or extended attributes.
+    pub(crate) fn read(path: &Path, options: MetadataOptions) -> io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        #[allow(unused_mut)]
+        let mut metadata = Self {
+            size: Some(meta.len()),
+            modified: meta.modified().ok(),
+            accessed: meta.accessed().ok(),
+            ..Self::default()
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            metadata.mode = Some(meta.mode());
+            if options.ownership {
+                metadata.uid = Some(meta.uid());
+                metadata.gid = Some(meta.gid());
+            }
+            if options.xattrs && xattr::SUPPORTED_PLATFORM {
+                for name in xattr::list(path)? {
+                    let Some(name_str) = name.to_str() else {
+                        tracing::warn!(
+                            ?path,
+                            ?name,
+                            "skipping extended attribute with non-UTF-8 name"
+                        );
+                        continue;
+                    };
+                    if let Some(value) = xattr::get(path, &name)? {
+                        metadata.xattrs.insert(name_str.to_owned(), value);
+                    }
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = options;
+
+        Ok(metadata)
+    }
+
+    /// The permissions recorded in this metadata, if any.
+    pub(crate) fn permissions(&self) -> Option<fs::Permissions> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            self.mode.map(fs::Permissions::from_mode)
+        }
+        #[cfg(not(unix))]
+        None
+    }
+
+    /// Applies this metadata, except for permissions, to the open `file` at `path`.
+    ///
+    /// Failing to set ownership or an extended attribute is only logged as a warning, as both
+    /// commonly require elevated privileges.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from setting the file times.
+    pub(crate) fn apply(
+        &self,
+        file: &fs::File,
+        path: &Path,
+        options: MetadataOptions,
+    ) -> io::Result<()> {
+        let mut times = fs::FileTimes::new();
+        if let Some(modified) = self.modified {
+            times = times.set_modified(modified);
+        }
+        if let Some(accessed) = self.accessed {
+            times = times.set_accessed(accessed);
+        }
+        file.set_times(times)?;
+
+        #[cfg(unix)]
+        {
+            if options.ownership && (self.uid.is_some() || self.gid.is_some()) {
+                if let Err(error) = std::os::unix::fs::fchown(file, self.uid, self.gid) {
+                    tracing::warn!(?path, %error, "failed to set file owner");
+                }
+            }
+            if options.xattrs && xattr::SUPPORTED_PLATFORM {
+                for (name, value) in &self.xattrs {
+                    if let Err(error) = xattr::FileExt::set_xattr(file, name, value) {
+                        tracing::warn!(?path, %name, %error, "failed to set extended attribute");
+                    }
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = (path, options);
+
+        Ok(())
+    }
+
+    /// Returns whether the file described by `self` appears not to have been modified since
+    /// `previous` was recorded, based on its size and modification time.
+    pub(crate) fn is_unmodified_since(&self, previous: &Self) -> bool {
+        self.size.is_some()
+            && self.modified.is_some()
+            && self.size == previous.size
+            && self.modified == previous.modified
+    }
+
+    /// Returns descriptions of the metadata that differs between `self` and `other`.
+    ///
+    /// Access times are ignored, as they change whenever a file is read.
+    pub(crate) fn changes(&self, other: &Self, options: MetadataOptions) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if whole_seconds(self.modified) != whole_seconds(other.modified) {
+            changes.push("modification time");
+        }
+        if options.ownership {
+            if self.uid != other.uid {
+                changes.push("owner");
+            }
+            if self.gid != other.gid {
+                changes.push("group");
+            }
+        }
+        if options.xattrs && self.xattrs != other.xattrs {
+            changes.push("extended attributes");
+        }
+        changes
+    }
+}
+
+/// The recorded metadata and format of a single stored file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    #[serde(flatten)]
+    metadata: FileMetadata,
+    #[serde(default, skip_serializing_if = "StoredFormat::is_plain")]
+    format: StoredFormat,
+}
+
+/// The metadata of all files in a pile, stored next to the pile's directory in the hoard.
+///
+/// For a pile stored at `$HOARD_ROOT/$HOARD_NAME/$PILE_NAME`, the sidecar is
+/// `$HOARD_ROOT/$HOARD_NAME/$PILE_NAME.metadata.json`. Files are keyed by their path relative
+/// to the pile.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Sidecar {
+    files: BTreeMap<PathBuf, Record>,
+}
+
+impl Sidecar {
+    /// The path to the sidecar file for the pile stored at `prefix`.
+    pub(crate) fn path(prefix: &Path) -> PathBuf {
+        let mut name = prefix.file_name().unwrap_or_default().to_os_string();
+        name.push(SIDECAR_SUFFIX);
+        prefix.with_file_name(name)
+    }
+
+    /// Loads the sidecar for the pile stored at `prefix`, or an empty one if none exists.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from reading the file, or [`io::ErrorKind::InvalidData`] if the file is
+    /// not a valid sidecar.
+    pub(crate) fn load(prefix: &Path) -> io::Result<Self> {
+        let path = Self::path(prefix);
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Saves the sidecar for the pile stored at `prefix`, dropping records of files that no
+    /// longer exist in the hoard.
+    ///
+    /// Nothing is saved for a pile that has no files in the hoard.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from writing the file.
+    pub(crate) fn save(mut self, prefix: &Path) -> io::Result<()> {
+        if !prefix.exists() {
+            return Ok(());
+        }
+        self.files.retain(|rel_path, _| {
+            let path = if rel_path.as_os_str().is_empty() {
+                prefix.to_owned()
+            } else {
+                prefix.join(rel_path)
+            };
+            path.exists()
+        });
+
+        let content = serde_json::to_vec_pretty(&self)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        super::atomic::write(&Self::path(prefix), None, |file| {
+            io::Write::write_all(file, &content)
+        })
+    }
+
+    /// Returns the recorded metadata for `rel_path`.
+    pub(crate) fn get(&self, rel_path: &Path) -> Option<&FileMetadata> {
+        self.files.get(rel_path).map(|record| &record.metadata)
+    }
+
+    /// Returns the format `rel_path` is stored in. Files without a record are stored as-is.
+    pub(crate) fn format(&self, rel_path: &Path) -> StoredFormat {
+        self.files
+            .get(rel_path)
+            .map(|record| record.format)
+            .unwrap_or_default()
+    }
+
+    /// Records the metadata for `rel_path` and the format it is stored in, returning the
+    /// previously recorded metadata and format.
+    pub(crate) fn insert(
+        &mut self,
+        rel_path: PathBuf,
+        metadata: FileMetadata,
+        format: StoredFormat,
+    ) -> Option<(FileMetadata, StoredFormat)> {
+        self.files
+            .insert(rel_path, Record { metadata, format })
+            .map(|record| (record.metadata, record.format))
+    }
+
+    /// Records that `rel_path` is now stored in `format`, keeping its recorded metadata.
+    ///
+    /// Files without a record are recorded with `metadata`, which should be the metadata of the
+    /// stored file, as that is what restoring it would otherwise use.
+    pub(crate) fn set_format(
+        &mut self,
+        rel_path: PathBuf,
+        format: StoredFormat,
+        metadata: FileMetadata,
+    ) {
+        self.files
+            .entry(rel_path)
+            .or_insert_with(|| Record { metadata, format })
+            .format = format;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hoard::Compression;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sidecar_path_is_next_to_pile() {
+        assert_eq!(
+            Sidecar::path(Path::new("/hoards/hoard/pile")),
+            PathBuf::from("/hoards/hoard/pile.metadata.json")
+        );
+    }
+
+    #[test]
+    fn test_sidecar_save_drops_missing_files() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let prefix = dir.path().join("pile");
+        fs::create_dir_all(&prefix).unwrap();
+        fs::write(prefix.join("kept"), "content").unwrap();
+
+        let mut sidecar = Sidecar::default();
+        let format = StoredFormat {
+            compression: Compression::Zstd,
+            ..StoredFormat::default()
+        };
+        sidecar.insert(PathBuf::from("kept"), FileMetadata::default(), format);
+        sidecar.insert(
+            PathBuf::from("deleted"),
+            FileMetadata::default(),
+            StoredFormat::default(),
+        );
+        sidecar.save(&prefix).expect("failed to save sidecar");
+
+        let loaded = Sidecar::load(&prefix).expect("failed to load sidecar");
+        assert!(loaded.get(Path::new("kept")).is_some());
+        assert_eq!(loaded.format(Path::new("kept")), format);
+        assert!(loaded.get(Path::new("deleted")).is_none());
+        assert_eq!(loaded.format(Path::new("deleted")), StoredFormat::default());
+        assert_eq!(
+            Sidecar::load(&dir.path().join("other")).unwrap(),
+            Sidecar::default()
+        );
+
+        // Piles that were never backed up get no sidecar, even if the hoards root is missing.
+        let missing = dir.path().join("missing").join("pile");
+        Sidecar::default()
+            .save(&missing)
+            .expect("failed to save sidecar");
+        assert!(!Sidecar::path(&missing).exists());
+    }
+
+    #[test]
+    fn test_apply_sets_times_and_changes_detects_them() {
+        let dir = tempfile::tempdir().expect("failed to create temp dir");
+        let path = dir.path().join("file");
+        fs::write(&path, "content").unwrap();
+
+        let mut metadata = FileMetadata::read(&path, MetadataOptions::default()).unwrap();
+        metadata.modified = Some(UNIX_EPOCH + Duration::from_secs(1_000_000));
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        metadata
+            .apply(&file, &path, MetadataOptions::default())
+            .expect("failed to apply metadata");
+
+        let current = FileMetadata::read(&path, MetadataOptions::default()).unwrap();
+        assert_eq!(current.modified, metadata.modified);
+        assert!(metadata
+            .changes(&current, MetadataOptions::default())
+            .is_empty());
+
+        let newer = FileMetadata {
+            modified: Some(UNIX_EPOCH + Duration::from_secs(2_000_000)),
+            ..current.clone()
+        };
+        assert_eq!(
+            newer.changes(&current, MetadataOptions::default()),
+            vec!["modification time"]
+        );
+        assert!(current.is_unmodified_since(&metadata));
+        assert!(!newer.is_unmodified_since(&current));
+    }
+}
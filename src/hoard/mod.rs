@@ -2,16 +2,48 @@
 //! [`Hoard`](crate::config::builder::hoard::Hoard)s. See documentation for builder `Hoard`s
 //! for more details.
 
+pub(crate) mod atomic;
+pub(crate) mod compression;
+pub(crate) mod content_addressed;
+pub(crate) mod dry_run;
+pub(crate) mod encryption;
+pub(crate) mod format;
 pub(crate) mod iter;
+pub(crate) mod metadata;
 pub(crate) mod pile_config;
+pub(crate) mod run;
+pub(crate) mod storage;
+pub(crate) mod symlinks;
+pub(crate) mod versions;
 
 use crate::checkers::history::last_paths::HoardPaths;
+use crate::checkers::history::operation::ChecksumType;
+use crate::filters::hoardignore;
 use crate::filters::{Error as FilterError, Filter, Filters};
+pub use compression::Compression;
+pub use content_addressed::Layout;
+pub use dry_run::DryRun;
+pub use encryption::PasswordCache;
+pub(crate) use encryption::PreviousKeys;
+use encryption::{Cipher, Error as EncryptionError};
+use format::StoredFormat;
+use metadata::{FileMetadata, MetadataOptions, Sidecar};
 pub use pile_config::Config as PileConfig;
+pub use pile_config::ContentType;
+pub use pile_config::RegexPattern;
+pub use pile_config::Symlinks;
+pub use run::CopyRun;
 use std::collections::HashMap;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 use std::{fs, io};
+pub use storage::{
+    mirror, Backend as StorageBackend, Directory, EntryKind, Error as StorageError,
+    Metadata as StorageMetadata, S3Config, Storage, S3,
+};
 use thiserror::Error;
+use versions::Snapshot;
 
 /// Errors that can happen while backing up or restoring a hoard.
 #[derive(Debug, Error)]
@@ -56,6 +88,72 @@ pub enum Error {
     /// An error occurred while filtering files.
     #[error("error while filtering files: {0}")]
     Filter(#[from] FilterError),
+    /// An error occurred while setting up encryption for a pile.
+    #[error("error while setting up encryption: {0}")]
+    Encryption(#[from] EncryptionError),
+    /// Error while encrypting a file.
+    #[error("failed to encrypt {path}: {error}")]
+    Encrypt {
+        /// The path of the file being encrypted.
+        path: PathBuf,
+        /// The error that occurred while encrypting.
+        #[source]
+        error: EncryptionError,
+    },
+    /// Error while decrypting a file.
+    #[error("failed to decrypt {path}: {error}")]
+    Decrypt {
+        /// The path of the file being decrypted.
+        path: PathBuf,
+        /// The error that occurred while decrypting.
+        #[source]
+        error: EncryptionError,
+    },
+    /// Error while deleting a file that was deleted on the other side.
+    #[error("failed to delete {path}: {error}")]
+    DeleteFile {
+        /// The path of the file to delete.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// Error while reading or writing a file that is being re-encrypted.
+    #[error("failed to re-encrypt {path}: {error}")]
+    Rekey {
+        /// The path of the file in the hoard.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// Error while reading or writing preserved file metadata.
+    #[error("failed to preserve metadata of {path}: {error}")]
+    Metadata {
+        /// The path of the file or metadata sidecar.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// Error while reading a `.hoardignore` file stored in the hoard.
+    #[error("failed to read ignore file {path}: {error}")]
+    IgnoreFile {
+        /// The path of the file in the hoard.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// Error while saving a file that is about to be replaced or deleted to a snapshot.
+    #[error("failed to save previous version of {path}: {error}")]
+    Snapshot {
+        /// The path of the file in the hoard.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -87,6 +185,25 @@ impl AsRef<Path> for SystemPath {
     }
 }
 
+/// Settings shared by everything copied while backing up or restoring a single [`Pile`].
+struct CopyOptions<'a> {
+    direction: Direction,
+    filters: Option<&'a Filters>,
+    cipher: Option<&'a Cipher>,
+    compression: Compression,
+    run: &'a CopyRun,
+    symlinks: Symlinks,
+    /// The root path of the pile on the system, which `filters` are applied relative to.
+    root_prefix: &'a Path,
+    /// The root path of the pile in the hoard.
+    hoard_prefix: &'a Path,
+    metadata: MetadataOptions,
+    /// The pile's metadata sidecar. Updated when backing up, read when restoring.
+    sidecar: &'a Mutex<Sidecar>,
+    /// Where to save files in the hoard before replacing them, if versions are kept.
+    snapshot: Option<&'a Snapshot>,
+}
+
 /// A single path to hoard, with configuration.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Pile {
@@ -100,16 +217,222 @@ pub struct Pile {
 }
 
 impl Pile {
+    /// Returns whether deletions should be propagated when backing up or restoring this pile.
+    pub(crate) fn propagates_deletions(&self) -> bool {
+        self.config
+            .as_ref()
+            .and_then(|config| config.propagate_deletions)
+            .unwrap_or(true)
+    }
+
+    /// Deletes the file at `path` and any parent directories left empty, up to (not including)
+    /// `root`. If `dry_run` is set, the deletion is only reported to it.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::DeleteFile`] if the file or a directory cannot be deleted.
+    pub(crate) fn delete_file(
+        root: &Path,
+        path: &Path,
+        dry_run: Option<&DryRun>,
+    ) -> Result<(), Error> {
+        let _span = tracing::trace_span!("delete_file", ?root, ?path).entered();
+        if let Some(dry_run) = dry_run {
+            dry_run.delete(path);
+            return Ok(());
+        }
+
+        tracing::debug!(path = path.to_string_lossy().as_ref(), "deleting");
+        fs::remove_file(path).map_err(|error| Error::DeleteFile {
+            path: path.to_owned(),
+            error,
+        })?;
+
+        let mut parent = path.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != root && dir.starts_with(root)) {
+            let is_empty = fs::read_dir(dir)
+                .map_err(|error| Error::ReadDir {
+                    path: dir.to_owned(),
+                    error,
+                })?
+                .next()
+                .is_none();
+            if !is_empty {
+                break;
+            }
+            tracing::trace!(
+                path = dir.to_string_lossy().as_ref(),
+                "deleting empty directory"
+            );
+            fs::remove_dir(dir).map_err(|error| Error::DeleteFile {
+                path: dir.to_owned(),
+                error,
+            })?;
+            parent = dir.parent();
+        }
+
+        Ok(())
+    }
+
+    /// Returns how symbolic links are handled for this pile.
+    pub(crate) fn symlinks(&self) -> Symlinks {
+        self.config
+            .as_ref()
+            .and_then(|config| config.symlinks)
+            .unwrap_or_default()
+    }
+
+    /// Returns the algorithm used to create checksums of files in this pile.
+    pub(crate) fn checksum_type(&self) -> ChecksumType {
+        self.config
+            .as_ref()
+            .and_then(|config| config.checksum_type)
+            .unwrap_or_default()
+    }
+
+    /// Returns which optional file metadata is preserved for this pile.
+    pub(crate) fn metadata_options(&self) -> MetadataOptions {
+        let config = self.config.as_ref();
+        MetadataOptions {
+            ownership: config
+                .and_then(|config| config.preserve_ownership)
+                .unwrap_or(false),
+            xattrs: config
+                .and_then(|config| config.preserve_xattrs)
+                .unwrap_or(false),
+        }
+    }
+
+    /// Returns how many snapshots of files replaced or deleted by backups are kept for this pile.
+    pub(crate) fn versions(&self) -> usize {
+        self.config
+            .as_ref()
+            .and_then(|config| config.versions)
+            .unwrap_or(0)
+    }
+
+    /// Returns how files in this pile are compressed in the hoard.
+    pub(crate) fn compression(&self) -> Compression {
+        self.config
+            .as_ref()
+            .and_then(|config| config.compress)
+            .unwrap_or_default()
+    }
+
+    /// Returns the [`Filters`] deciding which files in this pile are copied in `direction`.
+    ///
+    /// Piles without any configuration still honour `.hoardignore` files.
+    pub(crate) fn filters(&self, direction: Direction) -> Result<Filters, FilterError> {
+        let filters = match &self.config {
+            Some(config) => Filters::new(config)?,
+            None => Filters::new(&PileConfig::default())?,
+        };
+        Ok(match direction {
+            Direction::Backup => filters,
+            Direction::Restore => filters.for_restore(),
+        })
+    }
+
+    /// Returns the [`Cipher`] to use for this pile, if encryption is configured.
+    pub(crate) fn cipher(
+        &self,
+        passwords: &PasswordCache,
+    ) -> Result<Option<Cipher>, EncryptionError> {
+        self.config
+            .as_ref()
+            .and_then(|config| config.encryption.as_ref())
+            .map(|encryption| Cipher::new(encryption, passwords))
+            .transpose()
+    }
+
+    /// Helper function for copying a single file, compressing and encrypting or decrypting and
+    /// decompressing as necessary.
+    ///
+    /// When backing up, the copy in the hoard is compressed, then encrypted. When restoring, the
+    /// copy on the system is decrypted according to the `stored` format, then decompressed.
+    /// `metadata` is applied to the copy, falling back to the permissions of `src` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Various sorts of I/O and encryption errors as the different [`Error`] variants.
+    fn copy_file(
+        options: &CopyOptions<'_>,
+        metadata: &FileMetadata,
+        stored: StoredFormat,
+        src: &Path,
+        dest: &Path,
+    ) -> Result<(), Error> {
+        let copy_err = |err| Error::CopyFile {
+            src: src.to_owned(),
+            dest: dest.to_owned(),
+            error: err,
+        };
+
+        let mut src_file = fs::File::open(src).map_err(copy_err)?;
+        let permissions = match metadata.permissions() {
+            Some(permissions) => permissions,
+            None => src_file.metadata().map_err(copy_err)?.permissions(),
+        };
+
+        // Files are stored in the current format, but read in the one they were stored in.
+        let format = match options.direction {
+            Direction::Backup => StoredFormat::new(options.cipher, options.compression),
+            Direction::Restore => stored,
+        };
+
+        atomic::write(dest, Some(permissions), |file| {
+            if format.is_plain() {
+                io::copy(&mut src_file, file)?;
+            } else {
+                match options.direction {
+                    Direction::Backup => {
+                        format.encode(options.cipher, &mut src_file, &mut *file)?;
+                    }
+                    Direction::Restore => {
+                        io::copy(&mut format.decoder(options.cipher, &mut src_file)?, file)?;
+                    }
+                }
+            }
+            metadata.apply(file, dest, options.metadata)
+        })
+        .map_err(|err| match format::encryption_error(err) {
+            Ok(error) => match options.direction {
+                Direction::Backup => Error::Encrypt {
+                    path: src.to_owned(),
+                    error,
+                },
+                Direction::Restore => Error::Decrypt {
+                    path: src.to_owned(),
+                    error,
+                },
+            },
+            Err(err) => copy_err(err),
+        })
+    }
+
+    /// Whether the filters exclude `src`, which would be copied to `dest`.
+    fn is_filtered(options: &CopyOptions<'_>, src: &Path, dest: &Path, is_dir: bool) -> bool {
+        // Filters always apply to the path on the system.
+        let system_path = match options.direction {
+            Direction::Backup => src,
+            Direction::Restore => dest,
+        };
+        !options
+            .filters
+            .is_none_or(|filters| filters.keep_path(options.root_prefix, system_path, is_dir))
+    }
+
     /// Helper function for copying files and directories.
     ///
-    /// The returned [`PilePaths`] has items inserted as (src, dest).
+    /// `ancestors` holds the canonical paths of the directories currently being copied, to
+    /// detect symbolic link loops.
     ///
     /// # Errors
     ///
     /// Various sorts of I/O errors as the different [`Error`] variants.
     fn copy(
-        filters: Option<&Filters>,
-        root_prefix: &Path,
+        options: &CopyOptions<'_>,
+        ancestors: &mut Vec<PathBuf>,
         src: &Path,
         dest: &Path,
     ) -> Result<(), Error> {
@@ -117,24 +440,37 @@ impl Pile {
             "copy",
             source = ?src,
             destination = ?dest,
-            ?root_prefix,
+            root_prefix = ?options.root_prefix,
         )
         .entered();
 
-        if !src.exists() {
+        let src_is_symlink = symlinks::is_symlink(src);
+        let is_preserved_link = src_is_symlink && options.symlinks == Symlinks::Preserve;
+        if !src.exists() && !is_preserved_link {
             tracing::warn!(path=?src, "source path does not exist; skipping");
             return Ok(());
         }
 
-        if !filters
-            .as_ref()
-            .map_or(true, |filters| filters.keep(root_prefix, src))
-        {
-            // File should be ignored (not kept), so do nothing.
+        let is_dir = src.is_dir() && !is_preserved_link;
+        if Self::is_filtered(options, src, dest, is_dir) {
             tracing::trace!(path=%src.display(), "ignoring path based on filters");
+            if !is_dir {
+                options.run.record_filtered();
+            }
             return Ok(());
         }
 
+        if src_is_symlink {
+            match options.symlinks {
+                Symlinks::Follow => {}
+                Symlinks::Skip => {
+                    tracing::trace!(path=%src.display(), "skipping symbolic link");
+                    return Ok(());
+                }
+                Symlinks::Preserve => return Self::copy_symlink(options, src, dest),
+            }
+        }
+
         // Fail if src and dest exist but are not both file or directory.
         if src.exists() == dest.exists()
             && src.is_dir() != dest.is_dir()
@@ -149,29 +485,136 @@ impl Pile {
         if src.is_dir() {
             let _span = tracing::trace_span!("is_directory").entered();
 
-            let dir_contents = fs::read_dir(src).map_err(|err| Error::ReadDir {
+            let read_dir_err = |err| Error::ReadDir {
                 path: src.to_owned(),
                 error: err,
-            })?;
+            };
+            let Some(canonical) = symlinks::enter_dir(ancestors, src).map_err(read_dir_err)? else {
+                return Ok(());
+            };
+            let dir_contents = fs::read_dir(src).map_err(read_dir_err)?;
 
+            ancestors.push(canonical);
             for item in dir_contents {
-                let item = item.map_err(|err| Error::ReadDir {
-                    path: src.to_owned(),
-                    error: err,
-                })?;
+                let item = item.map_err(read_dir_err)?;
 
                 let dest = dest.join(item.file_name());
                 // No tracing event here because we are recursing
-                Self::copy(filters, root_prefix, &item.path(), &dest)?;
+                Self::copy(options, ancestors, &item.path(), &dest)?;
             }
+            ancestors.pop();
         } else if src.is_file() {
             let _span = tracing::trace_span!("is_file").entered();
 
+            let (metadata, previous, stored) = Self::file_metadata(options, src, dest)?;
+            if options.direction == Direction::Backup && dest.exists() {
+                // Files stored in another format than is configured now are stored again.
+                let same_format = stored == StoredFormat::new(options.cipher, options.compression);
+                let unmodified =
+                    previous.is_some_and(|previous| metadata.is_unmodified_since(&previous));
+                if same_format && (unmodified || options.run.is_unchanged(src)) {
+                    tracing::trace!(path=%src.display(), "skipping unchanged file");
+                    options.run.record_skipped();
+                    return Ok(());
+                }
+            }
+
             // Create parent directory only if there is an actual file to copy.
             // Avoids unnecessarily creating empty directories.
-            if let Some(parent) = dest.parent() {
+            Self::create_parent(options.run.dry_run(), dest)?;
+
+            if let Some(dry_run) = options.run.dry_run() {
+                dry_run.copy(src, dest);
+            } else {
+                tracing::debug!(
+                    source = src.to_string_lossy().as_ref(),
+                    destination = dest.to_string_lossy().as_ref(),
+                    "copying",
+                );
+
+                if let Some(snapshot) = options.snapshot.filter(|_| dest.is_file()) {
+                    snapshot
+                        .save(options.hoard_prefix, dest)
+                        .map_err(|error| Error::Snapshot {
+                            path: dest.to_owned(),
+                            error,
+                        })?;
+                }
+                Self::copy_file(options, &metadata, stored, src, dest)?;
+            }
+            options.run.record_copied();
+        } else {
+            tracing::warn!(
+                source = src.to_string_lossy().as_ref(),
+                "source is not a file or directory",
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Helper function for getting the metadata to give the copy of `src`, along with the format
+    /// that the file in the hoard is stored in.
+    ///
+    /// When backing up, the metadata of `src` is read and recorded in the sidecar with the format
+    /// it is about to be stored in, and the previously recorded metadata is returned with it.
+    /// When restoring, the recorded metadata is used, falling back to the metadata of `src` for
+    /// files backed up before metadata was recorded.
+    fn file_metadata(
+        options: &CopyOptions<'_>,
+        src: &Path,
+        dest: &Path,
+    ) -> Result<(FileMetadata, Option<FileMetadata>, StoredFormat), Error> {
+        let metadata_err = |error| Error::Metadata {
+            path: src.to_owned(),
+            error,
+        };
+        // Records are keyed by the path relative to the pile root on the system.
+        let system_path = match options.direction {
+            Direction::Backup => src,
+            Direction::Restore => dest,
+        };
+        let rel_path = system_path
+            .strip_prefix(options.root_prefix)
+            .expect("copied paths should always be children of the pile root")
+            .to_owned();
+
+        let mut sidecar = options
+            .sidecar
+            .lock()
+            .expect("sidecar lock should not be poisoned");
+        match options.direction {
+            Direction::Backup => {
+                let metadata = FileMetadata::read(src, options.metadata).map_err(metadata_err)?;
+                let format = StoredFormat::new(options.cipher, options.compression);
+                Ok(match sidecar.insert(rel_path, metadata.clone(), format) {
+                    Some((previous, stored)) => (metadata, Some(previous), stored),
+                    None => (metadata, None, StoredFormat::default()),
+                })
+            }
+            Direction::Restore => {
+                let recorded = sidecar.get(&rel_path).cloned();
+                let stored = sidecar.format(&rel_path);
+                drop(sidecar);
+                let metadata = match recorded {
+                    Some(metadata) => metadata,
+                    None => {
+                        FileMetadata::read(src, MetadataOptions::default()).map_err(metadata_err)?
+                    }
+                };
+                Ok((metadata, None, stored))
+            }
+        }
+    }
+
+    /// Helper function for creating the parent directories of `dest`.
+    fn create_parent(dry_run: Option<&DryRun>, dest: &Path) -> Result<(), Error> {
+        if let Some(parent) = dest.parent() {
+            if let Some(dry_run) = dry_run {
+                dry_run.create_dir_all(parent);
+            } else {
                 tracing::trace!(
-                    destination = src.to_string_lossy().as_ref(),
+                    destination = dest.to_string_lossy().as_ref(),
                     "ensuring parent directories for destination",
                 );
                 fs::create_dir_all(parent).map_err(|err| Error::CreateDir {
@@ -179,23 +622,39 @@ impl Pile {
                     error: err,
                 })?;
             }
+        }
 
-            tracing::debug!(
-                source = src.to_string_lossy().as_ref(),
-                destination = dest.to_string_lossy().as_ref(),
-                "copying",
-            );
+        Ok(())
+    }
+
+    /// Helper function for recreating the symbolic link `src` at `dest`.
+    fn copy_symlink(options: &CopyOptions<'_>, src: &Path, dest: &Path) -> Result<(), Error> {
+        let copy_err = |err| Error::CopyFile {
+            src: src.to_owned(),
+            dest: dest.to_owned(),
+            error: err,
+        };
 
-            fs::copy(src.to_owned(), dest).map_err(|err| Error::CopyFile {
+        if dest.is_dir() && !symlinks::is_symlink(dest) {
+            return Err(Error::TypeMismatch {
                 src: src.to_owned(),
                 dest: dest.to_owned(),
-                error: err,
-            })?;
+            });
+        }
+
+        let target = fs::read_link(src).map_err(copy_err)?;
+        Self::create_parent(options.run.dry_run(), dest)?;
+
+        if let Some(dry_run) = options.run.dry_run() {
+            dry_run.link(dest, &target);
         } else {
-            tracing::warn!(
+            tracing::debug!(
                 source = src.to_string_lossy().as_ref(),
-                "source is not a file or directory",
+                destination = dest.to_string_lossy().as_ref(),
+                target = target.to_string_lossy().as_ref(),
+                "copying symbolic link",
             );
+            symlinks::create_symlink(&target, dest).map_err(copy_err)?;
         }
 
         Ok(())
@@ -204,12 +663,19 @@ impl Pile {
     /// Backs up files to the pile directory.
     ///
     /// `prefix` is the root directory for this pile. This should generally be
-    /// `$HOARD_ROOT/$HOARD_NAME/($PILE_NAME)`.
+    /// `$HOARD_ROOT/$HOARD_NAME/($PILE_NAME)`. Encryption passwords are fetched from
+    /// `passwords`. Files that `run` knows to be unchanged are skipped, and changes are only
+    /// reported if it is a dry run.
     ///
     /// # Errors
     ///
     /// Various sorts of I/O errors as the different [`enum@Error`] variants.
-    pub fn backup(&self, prefix: &Path) -> Result<(), Error> {
+    pub fn backup(
+        &self,
+        prefix: &Path,
+        passwords: &PasswordCache,
+        run: &CopyRun,
+    ) -> Result<(), Error> {
         if let Some(path) = &self.path {
             let _span = tracing::debug_span!(
                 "backup_pile",
@@ -218,9 +684,36 @@ impl Pile {
             )
             .entered();
 
-            let filter = self.config.as_ref().map(Filters::new).transpose()?;
+            let filter = Some(self.filters(Direction::Backup)?);
+            let cipher = self.cipher(passwords)?;
+            let metadata_err = |error| Error::Metadata {
+                path: Sidecar::path(prefix),
+                error,
+            };
+            let sidecar = Mutex::new(Sidecar::load(prefix).map_err(metadata_err)?);
+            let options = CopyOptions {
+                direction: Direction::Backup,
+                filters: filter.as_ref(),
+                cipher: cipher.as_ref(),
+                compression: self.compression(),
+                run,
+                symlinks: self.symlinks(),
+                root_prefix: path,
+                hoard_prefix: prefix,
+                metadata: self.metadata_options(),
+                sidecar: &sidecar,
+                snapshot: run.snapshot(prefix),
+            };
+
+            Self::copy(&options, &mut Vec::new(), path, prefix)?;
 
-            Self::copy(filter.as_ref(), path, path, prefix)?;
+            if run.dry_run().is_none() {
+                sidecar
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .save(prefix)
+                    .map_err(metadata_err)?;
+            }
         } else {
             tracing::warn!("pile has no associated path -- perhaps no environment matched?");
         }
@@ -230,11 +723,19 @@ impl Pile {
 
     /// Restores files from the hoard into the filesystem.
     ///
+    /// Files matching the pile's filters are not restored, even if they exist in the hoard.
+    /// Decryption passwords are fetched from `passwords`. If `run` is a dry run, changes are only
+    /// reported.
+    ///
     /// # Errors
     ///
     /// Various sorts of I/O errors as the different [`enum@Error`] variants.
-    pub fn restore(&self, prefix: &Path) -> Result<(), Error> {
-        // TODO: do stuff with pile config
+    pub fn restore(
+        &self,
+        prefix: &Path,
+        passwords: &PasswordCache,
+        run: &CopyRun,
+    ) -> Result<(), Error> {
         if let Some(path) = &self.path {
             let _span = tracing::debug_span!(
                 "restore_pile",
@@ -243,13 +744,193 @@ impl Pile {
             )
             .entered();
 
-            Self::copy(None, prefix, prefix, path)?;
+            let cipher = self.cipher(passwords)?;
+            let sidecar = Sidecar::load(prefix).map_err(|error| Error::Metadata {
+                path: Sidecar::path(prefix),
+                error,
+            })?;
+            // The system may not have the pile's `.hoardignore` files yet, so use the stored ones.
+            let ignore_files = Self::stored_ignore_files(prefix, path, cipher.as_ref(), &sidecar)?;
+            let filter = Some(
+                self.filters(Direction::Restore)?
+                    .with_hoardignore_files(ignore_files),
+            );
+            let sidecar = Mutex::new(sidecar);
+            let options = CopyOptions {
+                direction: Direction::Restore,
+                filters: filter.as_ref(),
+                cipher: cipher.as_ref(),
+                compression: self.compression(),
+                run,
+                symlinks: self.symlinks(),
+                root_prefix: path,
+                hoard_prefix: prefix,
+                metadata: self.metadata_options(),
+                sidecar: &sidecar,
+                snapshot: None,
+            };
+
+            Self::copy(&options, &mut Vec::new(), prefix, path)?;
         } else {
             tracing::warn!("pile has no associated path -- perhaps no environment matched");
         }
 
         Ok(())
     }
+
+    /// Reads the `.hoardignore` files stored in the hoard under `prefix`, decrypting and
+    /// decompressing them as recorded in `sidecar`.
+    ///
+    /// Returns each file's content with the path it is restored to under `root`.
+    fn stored_ignore_files(
+        prefix: &Path,
+        root: &Path,
+        cipher: Option<&Cipher>,
+        sidecar: &Sidecar,
+    ) -> Result<Vec<(PathBuf, String)>, Error> {
+        let mut files = Vec::new();
+        if !prefix.is_dir() {
+            return Ok(files);
+        }
+
+        let mut dirs = vec![prefix.to_owned()];
+        while let Some(dir) = dirs.pop() {
+            let read_dir_err = |error| Error::ReadDir {
+                path: dir.clone(),
+                error,
+            };
+            for entry in fs::read_dir(&dir).map_err(read_dir_err)? {
+                let entry = entry.map_err(read_dir_err)?;
+                // Symbolic links are neither followed nor read.
+                let file_type = entry.file_type().map_err(read_dir_err)?;
+                let path = entry.path();
+                if file_type.is_dir() {
+                    dirs.push(path);
+                } else if file_type.is_file() && entry.file_name() == hoardignore::FILE_NAME {
+                    let rel_path = path
+                        .strip_prefix(prefix)
+                        .expect("ignore files should always be children of the pile root");
+                    let mut content = String::new();
+                    fs::File::open(&path)
+                        .and_then(|file| sidecar.format(rel_path).decoder(cipher, file))
+                        .and_then(|mut reader| reader.read_to_string(&mut content))
+                        .map_err(|err| match format::encryption_error(err) {
+                            Ok(error) => Error::Decrypt {
+                                path: path.clone(),
+                                error,
+                            },
+                            Err(error) => Error::IgnoreFile {
+                                path: path.clone(),
+                                error,
+                            },
+                        })?;
+                    files.push((root.join(rel_path), content));
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Helper function for re-encrypting all files in a directory in the hoard.
+    fn rekey_path(
+        prefix: &Path,
+        path: &Path,
+        previous: &PreviousKeys,
+        cipher: Option<&Cipher>,
+        sidecar: &mut Sidecar,
+    ) -> Result<(), Error> {
+        let rekey_err = |error| Error::Rekey {
+            path: path.to_owned(),
+            error,
+        };
+
+        if path.is_dir() {
+            for item in fs::read_dir(path).map_err(|error| Error::ReadDir {
+                path: path.to_owned(),
+                error,
+            })? {
+                let item = item.map_err(|error| Error::ReadDir {
+                    path: path.to_owned(),
+                    error,
+                })?;
+                Self::rekey_path(prefix, &item.path(), previous, cipher, sidecar)?;
+            }
+        } else if path.is_file() {
+            tracing::debug!(path = path.to_string_lossy().as_ref(), "re-encrypting");
+            let rel_path = path
+                .strip_prefix(prefix)
+                .expect("re-encrypted paths should always be children of the pile root")
+                .to_owned();
+            let stored = sidecar.format(&rel_path);
+            let content = fs::read(path).map_err(rekey_err)?;
+            let content = if stored.encryption.is_some() {
+                previous
+                    .decrypt(&content, cipher)
+                    .map_err(|error| Error::Decrypt {
+                        path: path.to_owned(),
+                        error,
+                    })?
+            } else {
+                content
+            };
+            let content = match cipher {
+                None => content,
+                Some(cipher) => cipher.encrypt(&content).map_err(|error| Error::Encrypt {
+                    path: path.to_owned(),
+                    error,
+                })?,
+            };
+            // Files without a record keep the metadata restoring them would have used.
+            let metadata =
+                FileMetadata::read(path, MetadataOptions::default()).map_err(rekey_err)?;
+            let format = StoredFormat {
+                encryption: cipher.map(Cipher::kind),
+                ..stored
+            };
+            // Replace the file instead of writing to it, as it may be hard linked elsewhere.
+            let permissions = fs::metadata(path).map_err(rekey_err)?.permissions();
+            atomic::write(path, Some(permissions), |file| file.write_all(&content))
+                .map_err(rekey_err)?;
+            sidecar.set_format(rel_path, format, metadata);
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypts the files stored in the pile directory with the current encryption settings.
+    ///
+    /// Files are decrypted using `previous`, so nothing is restored to the filesystem. If the
+    /// pile is not configured to be encrypted, the files are stored decrypted.
+    ///
+    /// # Errors
+    ///
+    /// Various sorts of I/O and encryption errors as the different [`enum@Error`] variants.
+    pub(crate) fn rekey(
+        &self,
+        prefix: &Path,
+        previous: &PreviousKeys,
+        passwords: &PasswordCache,
+    ) -> Result<(), Error> {
+        let _span = tracing::debug_span!("rekey_pile", prefix = prefix.to_string_lossy().as_ref())
+            .entered();
+
+        if !prefix.exists() {
+            tracing::warn!(path=?prefix, "pile has not been backed up yet; skipping");
+            return Ok(());
+        }
+
+        let cipher = self.cipher(passwords)?;
+        let metadata_err = |error| Error::Metadata {
+            path: Sidecar::path(prefix),
+            error,
+        };
+        let mut sidecar = Sidecar::load(prefix).map_err(metadata_err)?;
+        let result = Self::rekey_path(prefix, prefix, previous, cipher.as_ref(), &mut sidecar);
+        // Record the files that were re-encrypted, even if others failed.
+        sidecar.save(prefix).map_err(metadata_err)?;
+        result
+    }
 }
 
 /// A collection of multiple related [`Pile`]s.
@@ -265,7 +946,12 @@ impl MultipleEntries {
     /// # Errors
     ///
     /// See [`Pile::backup`].
-    pub fn backup(&self, prefix: &Path) -> Result<(), Error> {
+    pub fn backup(
+        &self,
+        prefix: &Path,
+        passwords: &PasswordCache,
+        run: &CopyRun,
+    ) -> Result<(), Error> {
         for (name, entry) in &self.piles {
             let _span = tracing::info_span!(
                 "backup_multi_pile",
@@ -274,7 +960,7 @@ impl MultipleEntries {
             .entered();
 
             let sub_prefix = prefix.join(name);
-            entry.backup(&sub_prefix)?;
+            entry.backup(&sub_prefix, passwords, run)?;
         }
 
         Ok(())
@@ -285,7 +971,12 @@ impl MultipleEntries {
     /// # Errors
     ///
     /// See [`Pile::restore`].
-    pub fn restore(&self, prefix: &Path) -> Result<(), Error> {
+    pub fn restore(
+        &self,
+        prefix: &Path,
+        passwords: &PasswordCache,
+        run: &CopyRun,
+    ) -> Result<(), Error> {
         for (name, entry) in &self.piles {
             let _span = tracing::info_span!(
                 "restore_multi_pile",
@@ -294,16 +985,52 @@ impl MultipleEntries {
             .entered();
 
             let sub_prefix = prefix.join(name);
-            entry.restore(&sub_prefix)?;
+            entry.restore(&sub_prefix, passwords, run)?;
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypt all of the contained [`Pile`]s.
+    ///
+    /// # Errors
+    ///
+    /// See [`Pile::rekey`].
+    pub(crate) fn rekey(
+        &self,
+        prefix: &Path,
+        previous: &PreviousKeys,
+        passwords: &PasswordCache,
+    ) -> Result<(), Error> {
+        for (name, entry) in &self.piles {
+            let _span = tracing::info_span!(
+                "rekey_multi_pile",
+                pile = %name
+            )
+            .entered();
+
+            let sub_prefix = prefix.join(name);
+            entry.rekey(&sub_prefix, previous, passwords)?;
         }
 
         Ok(())
     }
 }
 
+/// Returns the paths in `hoards_root` holding the files stored for the hoard `name`: its
+/// metadata sidecar, its snapshots, and the hoard itself, in that order.
+pub(crate) fn stored_paths(hoards_root: &Path, name: &str) -> Vec<PathBuf> {
+    let prefix = hoards_root.join(name);
+    vec![
+        Sidecar::path(&prefix),
+        versions::versions_dir(hoards_root, name),
+        prefix,
+    ]
+}
+
 /// A configured hoard. May contain one or more [`Pile`]s.
 #[derive(Clone, Debug, PartialEq)]
-#[allow(variant_size_differences)]
+#[allow(variant_size_differences, clippy::large_enum_variant)]
 pub enum Hoard {
     /// A single anonymous [`Pile`].
     Anonymous(Pile),
@@ -317,14 +1044,19 @@ impl Hoard {
     /// # Errors
     ///
     /// See [`Pile::backup`].
-    pub fn backup(&self, prefix: &Path) -> Result<(), Error> {
+    pub fn backup(
+        &self,
+        prefix: &Path,
+        passwords: &PasswordCache,
+        run: &CopyRun,
+    ) -> Result<(), Error> {
         let _span =
             tracing::trace_span!("backup_hoard", prefix = prefix.to_string_lossy().as_ref())
                 .entered();
 
         match self {
-            Hoard::Anonymous(single) => single.backup(prefix),
-            Hoard::Named(multiple) => multiple.backup(prefix),
+            Hoard::Anonymous(single) => single.backup(prefix, passwords, run),
+            Hoard::Named(multiple) => multiple.backup(prefix, passwords, run),
         }
     }
 
@@ -333,14 +1065,73 @@ impl Hoard {
     /// # Errors
     ///
     /// See [`Pile::restore`].
-    pub fn restore(&self, prefix: &Path) -> Result<(), Error> {
+    pub fn restore(
+        &self,
+        prefix: &Path,
+        passwords: &PasswordCache,
+        run: &CopyRun,
+    ) -> Result<(), Error> {
         let _span =
             tracing::trace_span!("restore_hoard", prefix = prefix.to_string_lossy().as_ref(),)
                 .entered();
 
         match self {
-            Hoard::Anonymous(single) => single.restore(prefix),
-            Hoard::Named(multiple) => multiple.restore(prefix),
+            Hoard::Anonymous(single) => single.restore(prefix, passwords, run),
+            Hoard::Named(multiple) => multiple.restore(prefix, passwords, run),
+        }
+    }
+
+    /// Re-encrypt the files stored for this [`Hoard`].
+    ///
+    /// Operation logs record checksums of the unencrypted files, so they remain valid and no new
+    /// operation needs to be recorded.
+    ///
+    /// # Errors
+    ///
+    /// See [`Pile::rekey`].
+    pub(crate) fn rekey(
+        &self,
+        prefix: &Path,
+        previous: &PreviousKeys,
+        passwords: &PasswordCache,
+    ) -> Result<(), Error> {
+        let _span = tracing::trace_span!("rekey_hoard", prefix = prefix.to_string_lossy().as_ref())
+            .entered();
+
+        match self {
+            Hoard::Anonymous(single) => single.rekey(prefix, previous, passwords),
+            Hoard::Named(multiple) => multiple.rekey(prefix, previous, passwords),
+        }
+    }
+
+    /// Returns the pile with the given name, or the anonymous pile if `name` is `None`.
+    pub(crate) fn pile(&self, name: Option<&str>) -> Option<&Pile> {
+        match (self, name) {
+            (Hoard::Anonymous(pile), None) => Some(pile),
+            (Hoard::Named(piles), Some(name)) => piles.piles.get(name),
+            _ => None,
+        }
+    }
+
+    /// Returns each [`Pile`] in this `Hoard` with its name (`None` for an anonymous pile) and
+    /// the path it is stored at, given the `prefix` of this `Hoard`.
+    pub(crate) fn piles(&self, prefix: &Path) -> Vec<(Option<&str>, &Pile, PathBuf)> {
+        match self {
+            Hoard::Anonymous(pile) => vec![(None, pile, prefix.to_owned())],
+            Hoard::Named(piles) => piles
+                .piles
+                .iter()
+                .map(|(name, pile)| (Some(name.as_str()), pile, prefix.join(name)))
+                .collect(),
+        }
+    }
+
+    /// Returns how many snapshots of replaced or deleted files are kept for this `Hoard`: the
+    /// most kept for any of its piles.
+    pub(crate) fn versions(&self) -> usize {
+        match self {
+            Hoard::Anonymous(pile) => pile.versions(),
+            Hoard::Named(piles) => piles.piles.values().map(Pile::versions).max().unwrap_or(0),
         }
     }
 
@@ -358,3 +1149,277 @@ impl Hoard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_file_removes_empty_parents_up_to_root() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let nested = root.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.path().join("a").join("keep"), "keep").unwrap();
+        fs::write(nested.join("delete"), "delete").unwrap();
+
+        Pile::delete_file(root.path(), &nested.join("delete"), None)
+            .expect("failed to delete file");
+        assert!(!nested.exists());
+        assert!(root.path().join("a").join("keep").exists());
+
+        Pile::delete_file(root.path(), &root.path().join("a").join("keep"), None)
+            .expect("failed to delete file");
+        assert!(!root.path().join("a").exists());
+        assert!(root.path().exists());
+    }
+
+    #[test]
+    fn test_restore_applies_filters() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        fs::create_dir_all(hoard.path().join("nested")).unwrap();
+        fs::write(hoard.path().join("keep.txt"), "keep").unwrap();
+        fs::write(hoard.path().join("junk.log"), "junk").unwrap();
+        fs::write(hoard.path().join("nested").join("junk.log"), "junk").unwrap();
+
+        let pile = Pile {
+            config: Some(PileConfig {
+                ignore: vec![glob::Pattern::new("**/*.log").unwrap()],
+                ..PileConfig::default()
+            }),
+            path: Some(system.path().to_owned()),
+        };
+        pile.restore(hoard.path(), &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to restore pile");
+
+        assert!(system.path().join("keep.txt").exists());
+        assert!(!system.path().join("junk.log").exists());
+        assert!(!system.path().join("nested").join("junk.log").exists());
+    }
+
+    #[test]
+    fn test_backup_applies_include_filter() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        fs::create_dir_all(system.path().join("slots")).unwrap();
+        fs::write(system.path().join("slots").join("1.sav"), "save").unwrap();
+        fs::write(system.path().join("slots").join("1.log"), "junk").unwrap();
+        fs::write(system.path().join("top.sav"), "save").unwrap();
+
+        let pile = Pile {
+            config: Some(PileConfig {
+                include: vec![glob::Pattern::new("slots/*.sav").unwrap()],
+                ..PileConfig::default()
+            }),
+            path: Some(system.path().to_owned()),
+        };
+        pile.backup(hoard.path(), &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to back up pile");
+
+        assert!(hoard.path().join("slots").join("1.sav").exists());
+        assert!(!hoard.path().join("slots").join("1.log").exists());
+        assert!(!hoard.path().join("top.sav").exists());
+
+        let files: Vec<PathBuf> = iter::HoardFilesIter::new(
+            hoard.path(),
+            Direction::Backup,
+            "hoard",
+            &Hoard::Anonymous(pile),
+        )
+        .expect("failed to create iterator")
+        .map(|item| item.expect("failed to iterate").2 .0)
+        .collect();
+        assert_eq!(files, vec![system.path().join("slots").join("1.sav")]);
+    }
+
+    #[cfg(unix)]
+    fn symlink_pile(system: &Path, symlinks: Symlinks) -> Pile {
+        fs::create_dir_all(system.join("dir")).unwrap();
+        fs::write(system.join("file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("file.txt", system.join("link")).unwrap();
+        std::os::unix::fs::symlink("..", system.join("dir").join("loop")).unwrap();
+        Pile {
+            config: Some(PileConfig {
+                symlinks: Some(symlinks),
+                ..PileConfig::default()
+            }),
+            path: Some(system.to_owned()),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_preserved_symlinks_are_recreated() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        let pile = symlink_pile(system.path(), Symlinks::Preserve);
+
+        pile.backup(hoard.path(), &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to back up pile");
+        let link = hoard.path().join("link");
+        assert!(symlinks::is_symlink(&link));
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("file.txt"));
+
+        fs::remove_file(system.path().join("link")).unwrap();
+        pile.restore(hoard.path(), &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to restore pile");
+        let link = system.path().join("link");
+        assert!(symlinks::is_symlink(&link));
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("file.txt"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_skipped_symlinks_are_ignored() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        let pile = symlink_pile(system.path(), Symlinks::Skip);
+
+        pile.backup(hoard.path(), &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to back up pile");
+        assert!(hoard.path().join("file.txt").is_file());
+        assert!(!hoard.path().join("link").exists());
+        assert!(!hoard.path().join("dir").join("loop").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_followed_symlink_loops_terminate() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        let pile = symlink_pile(system.path(), Symlinks::Follow);
+
+        pile.backup(hoard.path(), &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to back up pile");
+        let link = hoard.path().join("link");
+        assert!(!symlinks::is_symlink(&link));
+        assert_eq!(fs::read_to_string(&link).unwrap(), "content");
+        assert!(!hoard
+            .path()
+            .join("dir")
+            .join("loop")
+            .join("file.txt")
+            .exists());
+    }
+
+    #[test]
+    fn test_backup_and_restore_preserve_modification_time() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        let file = system.path().join("file");
+        fs::write(&file, "content").unwrap();
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let pile = Pile {
+            config: None,
+            path: Some(system.path().to_owned()),
+        };
+        let prefix = hoard.path().join("pile");
+        pile.backup(&prefix, &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to back up pile");
+        let backed_up = fs::metadata(prefix.join("file")).unwrap();
+        assert_eq!(backed_up.modified().unwrap(), modified);
+        assert!(Sidecar::path(&prefix).exists());
+
+        // Simulate a sync tool that does not preserve modification times.
+        fs::File::options()
+            .write(true)
+            .open(prefix.join("file"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now())
+            .unwrap();
+        fs::remove_file(&file).unwrap();
+        pile.restore(&prefix, &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to restore pile");
+        assert_eq!(fs::metadata(&file).unwrap().modified().unwrap(), modified);
+    }
+
+    #[test]
+    fn test_backup_skips_unchanged_files() {
+        let hoard = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        fs::write(system.path().join("unmodified"), "content").unwrap();
+        fs::write(system.path().join("touched"), "content").unwrap();
+        let pile = Pile {
+            config: None,
+            path: Some(system.path().to_owned()),
+        };
+        let prefix = hoard.path().join("pile");
+
+        let run = CopyRun::default();
+        pile.backup(&prefix, &PasswordCache::default(), &run)
+            .expect("failed to back up pile");
+        assert_eq!(
+            run.to_string(),
+            "copied 2 file(s), skipped 0 unchanged file(s)"
+        );
+
+        // Same content with a new modification time, as determined by checksums.
+        fs::File::options()
+            .write(true)
+            .open(system.path().join("touched"))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        let mut run = CopyRun::default();
+        run.mark_unchanged(vec![system.path().join("touched")]);
+        pile.backup(&prefix, &PasswordCache::default(), &run)
+            .expect("failed to back up pile");
+        assert_eq!(
+            run.to_string(),
+            "copied 0 file(s), skipped 2 unchanged file(s)"
+        );
+
+        // Changes are copied, even if they were known to be unchanged, if the hoard copy is gone.
+        fs::write(system.path().join("unmodified"), "changed content").unwrap();
+        fs::remove_file(prefix.join("touched")).unwrap();
+        let run = CopyRun::default();
+        pile.backup(&prefix, &PasswordCache::default(), &run)
+            .expect("failed to back up pile");
+        assert_eq!(
+            run.to_string(),
+            "copied 2 file(s), skipped 0 unchanged file(s)"
+        );
+        assert_eq!(
+            fs::read_to_string(prefix.join("unmodified")).unwrap(),
+            "changed content"
+        );
+    }
+
+    #[test]
+    fn test_backup_saves_replaced_files_to_snapshot() {
+        let hoards_root = tempfile::tempdir().expect("failed to create temp dir");
+        let system = tempfile::tempdir().expect("failed to create temp dir");
+        fs::write(system.path().join("file"), "old content").unwrap();
+        let pile = Pile {
+            config: None,
+            path: Some(system.path().to_owned()),
+        };
+        let prefix = hoards_root.path().join("hoard");
+        pile.backup(&prefix, &PasswordCache::default(), &CopyRun::default())
+            .expect("failed to back up pile");
+
+        fs::write(system.path().join("file"), "new content").unwrap();
+        let snapshot =
+            Snapshot::existing(hoards_root.path(), "hoard", "2021_01_01-00_00_00.000000");
+        let mut run = CopyRun::default();
+        run.add_snapshot(prefix.clone(), snapshot.clone());
+        pile.backup(&prefix, &PasswordCache::default(), &run)
+            .expect("failed to back up pile");
+
+        assert_eq!(
+            fs::read_to_string(prefix.join("file")).unwrap(),
+            "new content"
+        );
+        assert_eq!(
+            fs::read_to_string(snapshot.path_for(&prefix.join("file"))).unwrap(),
+            "old content"
+        );
+    }
+}
@@ -1,5 +1,8 @@
+use crate::checkers::history::operation::ChecksumType;
+use crate::hoard::compression::Compression;
 use serde::de::Error as _;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
 
 /// Configuration for symmetric (password) encryption.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -15,8 +18,39 @@ pub enum SymmetricEncryption {
 /// Configuration for asymmetric (public key) encryption.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AsymmetricEncryption {
-    #[serde(rename = "public_key")]
-    pub(crate) public_key: String,
+    /// The age X25519 public keys (`age1...`) to encrypt files to.
+    ///
+    /// Also accepts a single key, including under the older `public_key` name.
+    #[serde(
+        rename = "public_keys",
+        alias = "public_key",
+        deserialize_with = "deserialize_public_keys"
+    )]
+    pub(crate) public_keys: Vec<String>,
+    /// Path to a file containing private keys (identities) used to decrypt files.
+    ///
+    /// Only required when restoring or diffing. May contain environment variables.
+    #[serde(default)]
+    pub(crate) identity_file: Option<String>,
+}
+
+/// One or more public keys.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PublicKeys {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[allow(single_use_lifetimes)]
+fn deserialize_public_keys<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match PublicKeys::deserialize(deserializer)? {
+        PublicKeys::One(key) => Ok(vec![key]),
+        PublicKeys::Many(keys) => Ok(keys),
+    }
 }
 
 /// Configuration for hoard/pile encryption.
@@ -29,6 +63,39 @@ pub enum Encryption {
     Asymmetric(AsymmetricEncryption),
 }
 
+/// How symbolic links in a pile are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Symlinks {
+    /// Copy the file or directory the link points to.
+    #[default]
+    Follow,
+    /// Store the link itself and recreate it on restore.
+    Preserve,
+    /// Ignore links entirely.
+    Skip,
+}
+
+/// A kind of file content, detected from the start of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// Valid UTF-8 without NUL bytes.
+    Text,
+    /// Anything that is not [`ContentType::Text`], including all the types below.
+    Binary,
+    /// An `SQLite` database.
+    Sqlite,
+    /// A PNG, JPEG, GIF, `WebP`, TIFF, or ICO image.
+    Image,
+    /// A zip, gzip, zstd, xz, bzip2, or 7z archive.
+    Archive,
+    /// An ELF, Mach-O, PE, or `WebAssembly` executable.
+    Executable,
+    /// A PDF document.
+    Pdf,
+}
+
 #[allow(single_use_lifetimes)]
 fn deserialize_glob<'de, D>(deserializer: D) -> Result<Vec<glob::Pattern>, D::Error>
 where
@@ -42,6 +109,48 @@ where
         .map_err(D::Error::custom)
 }
 
+/// A file size, either as a number of bytes or a string like `"10 MiB"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FileSize {
+    Bytes(u64),
+    Text(String),
+}
+
+#[allow(single_use_lifetimes)]
+fn deserialize_file_size<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match FileSize::deserialize(deserializer)? {
+        FileSize::Bytes(bytes) => Ok(Some(bytes)),
+        FileSize::Text(text) => parse_size::parse_size(&text)
+            .map(Some)
+            .map_err(|err| D::Error::custom(format!("invalid file size \"{text}\": {err}"))),
+    }
+}
+
+#[allow(single_use_lifetimes)]
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text)
+        .map(Some)
+        .map_err(|err| D::Error::custom(format!("invalid duration \"{text}\": {err}")))
+}
+
+#[allow(clippy::ref_option)]
+fn serialize_duration<S>(value: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    value
+        .map(|duration| humantime::format_duration(duration).to_string())
+        .serialize(serializer)
+}
+
 #[allow(clippy::ptr_arg)]
 fn serialize_glob<S>(value: &Vec<glob::Pattern>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -55,8 +164,54 @@ where
     value.serialize(serializer)
 }
 
+/// A regular expression from a pile configuration, along with where it was configured.
+///
+/// The expression is only compiled when the filters for a pile are created, so an invalid one
+/// can be reported together with its location.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct RegexPattern {
+    pattern: String,
+    location: Option<String>,
+}
+
+impl RegexPattern {
+    /// Creates a pattern with an unknown location.
+    #[must_use]
+    pub fn new(pattern: impl Into<String>) -> Self {
+        Self {
+            pattern: pattern.into(),
+            location: None,
+        }
+    }
+
+    /// The uncompiled regular expression.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The configuration table the pattern was set in, like `[hoards.games.config]`, if known.
+    #[must_use]
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+}
+
+impl From<String> for RegexPattern {
+    fn from(pattern: String) -> Self {
+        Self::new(pattern)
+    }
+}
+
+impl From<RegexPattern> for String {
+    fn from(pattern: RegexPattern) -> Self {
+        pattern.pattern
+    }
+}
+
 /// Hoard/Pile configuration.
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
     /// The [`Encryption`] configuration for a pile.
@@ -69,6 +224,89 @@ pub struct Config {
         serialize_with = "serialize_glob"
     )]
     pub ignore: Vec<glob::Pattern>,
+    /// A list of glob patterns matching the only files to back up. All files are backed up if
+    /// empty.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_glob",
+        serialize_with = "serialize_glob",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub include: Vec<glob::Pattern>,
+    /// A list of regular expressions matching files to ignore.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_regex: Vec<RegexPattern>,
+    /// A list of regular expressions matching the only files to back up, in addition to those
+    /// matching `include`. All files are backed up if both are empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_regex: Vec<RegexPattern>,
+    /// A list of content types of files to ignore.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_content_types: Vec<ContentType>,
+    /// A list of content types of the only files to back up. All files are backed up if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_content_types: Vec<ContentType>,
+    /// The algorithm used to create the checksums recorded in operation logs.
+    ///
+    /// Defaults to [`ChecksumType::SHA256`] if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_type: Option<ChecksumType>,
+    /// Whether files deleted on one side are deleted on the other when backing up or restoring.
+    ///
+    /// Defaults to `true` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub propagate_deletions: Option<bool>,
+    /// How symbolic links are handled. Defaults to [`Symlinks::Follow`] if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlinks: Option<Symlinks>,
+    /// Whether to preserve the owning user and group of files (Unix only).
+    ///
+    /// Defaults to `false` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_ownership: Option<bool>,
+    /// Whether to preserve the extended attributes of files (Unix only).
+    ///
+    /// Defaults to `false` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_xattrs: Option<bool>,
+    /// How many snapshots of files replaced or deleted by backups to keep.
+    ///
+    /// Defaults to `0` (no snapshots) if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub versions: Option<usize>,
+    /// How files are compressed in the hoard. Defaults to [`Compression::None`] if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress: Option<Compression>,
+    /// Files larger than this many bytes are not backed up.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_file_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_file_size: Option<u64>,
+    /// Files smaller than this many bytes are not backed up.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_file_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_file_size: Option<u64>,
+    /// Only files modified within this long before a backup are backed up.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modified_within: Option<Duration>,
+    /// Only files last modified longer than this before a backup are backed up.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub modified_before: Option<Duration>,
 }
 
 impl Config {
@@ -77,13 +315,100 @@ impl Config {
     fn layer(&mut self, other: &Self) {
         // Overlay a more general encryption config, if a specific one doesn't exist.
         if self.encryption.is_none() {
-            self.encryption = other.encryption.clone();
+            self.encryption.clone_from(&other.encryption);
+        }
+
+        if self.checksum_type.is_none() {
+            self.checksum_type = other.checksum_type;
+        }
+
+        if self.propagate_deletions.is_none() {
+            self.propagate_deletions = other.propagate_deletions;
+        }
+
+        if self.symlinks.is_none() {
+            self.symlinks = other.symlinks;
+        }
+
+        if self.preserve_ownership.is_none() {
+            self.preserve_ownership = other.preserve_ownership;
+        }
+
+        if self.preserve_xattrs.is_none() {
+            self.preserve_xattrs = other.preserve_xattrs;
+        }
+
+        if self.versions.is_none() {
+            self.versions = other.versions;
+        }
+
+        if self.compress.is_none() {
+            self.compress = other.compress;
+        }
+
+        if self.max_file_size.is_none() {
+            self.max_file_size = other.max_file_size;
+        }
+
+        if self.min_file_size.is_none() {
+            self.min_file_size = other.min_file_size;
+        }
+
+        if self.modified_within.is_none() {
+            self.modified_within = other.modified_within;
+        }
+
+        if self.modified_before.is_none() {
+            self.modified_before = other.modified_before;
         }
 
         // Merge ignore lists.
         self.ignore.extend(other.ignore.clone());
         self.ignore.sort_unstable();
         self.ignore.dedup();
+
+        // Merge include lists.
+        self.include.extend(other.include.clone());
+        self.include.sort_unstable();
+        self.include.dedup();
+
+        // Merge content type lists.
+        for (list, other) in [
+            (&mut self.ignore_content_types, &other.ignore_content_types),
+            (
+                &mut self.include_content_types,
+                &other.include_content_types,
+            ),
+        ] {
+            list.extend(other);
+            list.sort_unstable();
+            list.dedup();
+        }
+
+        // Merge regex lists, keeping the most specific location of duplicates.
+        for (list, other) in [
+            (&mut self.ignore_regex, &other.ignore_regex),
+            (&mut self.include_regex, &other.include_regex),
+        ] {
+            for pattern in other {
+                if list
+                    .iter()
+                    .all(|existing| existing.pattern != pattern.pattern)
+                {
+                    list.push(pattern.clone());
+                }
+            }
+            list.sort_unstable();
+        }
+    }
+
+    /// Records `location` as where any regular expressions without a location were configured.
+    pub(crate) fn set_location(&mut self, location: &str) {
+        for pattern in self.ignore_regex.iter_mut().chain(&mut self.include_regex) {
+            if pattern.location.is_none() {
+                pattern.location = Some(location.to_owned());
+            }
+        }
     }
 
     /// Layer the `general` config with the `specific` one, modifying the `specific` one in place.
@@ -0,0 +1,293 @@
+//! See [`CopyRun`].
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::{panic, thread};
+
+use super::versions::Snapshot;
+use super::DryRun;
+
+/// Settings and shared state for a single backup or restore run, across all hoards.
+#[derive(Debug)]
+pub struct CopyRun {
+    /// If set, changes are only reported to it.
+    dry_run: Option<DryRun>,
+    /// The maximum number of jobs to run concurrently.
+    jobs: NonZeroUsize,
+    /// System paths of files known to be unchanged since they were last backed up.
+    unchanged: HashSet<PathBuf>,
+    /// Snapshots to save replaced or deleted files to, keyed by the path the pile is stored at.
+    snapshots: HashMap<PathBuf, Snapshot>,
+    copied: AtomicUsize,
+    skipped: AtomicUsize,
+    filtered: AtomicUsize,
+}
+
+impl Default for CopyRun {
+    fn default() -> Self {
+        Self {
+            dry_run: None,
+            jobs: NonZeroUsize::MIN,
+            unchanged: HashSet::new(),
+            snapshots: HashMap::new(),
+            copied: AtomicUsize::new(0),
+            skipped: AtomicUsize::new(0),
+            filtered: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl CopyRun {
+    /// Creates a new run that runs up to `jobs` jobs at once. If `dry_run` is `true`, changes
+    /// are only reported.
+    #[must_use]
+    pub fn new(dry_run: bool, jobs: NonZeroUsize) -> Self {
+        Self {
+            dry_run: dry_run.then(DryRun::default),
+            jobs,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the [`DryRun`] to report changes to, if this is a dry run.
+    #[must_use]
+    pub fn dry_run(&self) -> Option<&DryRun> {
+        self.dry_run.as_ref()
+    }
+
+    /// Marks the files at the given system paths as unchanged since they were last backed up,
+    /// so backing them up can be skipped.
+    pub(crate) fn mark_unchanged(&mut self, paths: impl IntoIterator<Item = PathBuf>) {
+        self.unchanged.extend(paths);
+    }
+
+    /// Returns whether the file at system path `path` is known to be unchanged.
+    pub(crate) fn is_unchanged(&self, path: &Path) -> bool {
+        self.unchanged.contains(path)
+    }
+
+    /// Saves files replaced or deleted in the pile stored at `prefix` to `snapshot`.
+    pub(crate) fn add_snapshot(&mut self, prefix: PathBuf, snapshot: Snapshot) {
+        self.snapshots.insert(prefix, snapshot);
+    }
+
+    /// Returns the snapshot for the pile stored at `prefix`, if it keeps previous versions.
+    pub(crate) fn snapshot(&self, prefix: &Path) -> Option<&Snapshot> {
+        self.snapshots.get(prefix)
+    }
+
+    /// Calls `f` on each of `items`, running up to the configured number of jobs at once.
+    ///
+    /// Once any call fails, no new calls are started and the first error is returned after
+    /// the running calls finish. With a single job, items are processed in order.
+    ///
+    /// # Errors
+    ///
+    /// The first error returned by `f`.
+    pub(crate) fn try_for_each<T, E, F>(&self, items: Vec<T>, f: F) -> Result<(), E>
+    where
+        T: Send,
+        E: Send,
+        F: Fn(T) -> Result<(), E> + Sync,
+    {
+        let jobs = self.jobs.get().min(items.len());
+        if jobs <= 1 {
+            return items.into_iter().try_for_each(f);
+        }
+
+        let queue = Mutex::new(items.into_iter());
+        let failed = AtomicBool::new(false);
+        let worker = || -> Result<(), E> {
+            while !failed.load(Ordering::Relaxed) {
+                let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                let Some(item) = next else {
+                    break;
+                };
+                if let Err(error) = f(item) {
+                    failed.store(true, Ordering::Relaxed);
+                    return Err(error);
+                }
+            }
+            Ok(())
+        };
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..jobs).map(|_| scope.spawn(worker)).collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|err| panic::resume_unwind(err))
+                })
+                .fold(Ok(()), Result::and)
+        })
+    }
+
+    /// Records that a file was copied.
+    pub(crate) fn record_copied(&self) {
+        self.copied.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that copying an unchanged file was skipped.
+    pub(crate) fn record_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a file was not copied because of the pile's filters.
+    pub(crate) fn record_filtered(&self) {
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for CopyRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let copied = self.copied.load(Ordering::Relaxed);
+        let skipped = self.skipped.load(Ordering::Relaxed);
+        let filtered = self.filtered.load(Ordering::Relaxed);
+        let verb = if self.dry_run.is_some() {
+            "would copy"
+        } else {
+            "copied"
+        };
+        if filtered == 0 {
+            write!(
+                f,
+                "{verb} {copied} file(s), skipped {skipped} unchanged file(s)"
+            )
+        } else {
+            write!(
+                f,
+                "{verb} {copied} file(s), skipped {skipped} unchanged and {filtered} filtered file(s)"
+            )
+        }
+    }
+}
+
+/// Groups `items` so that no two groups share any file, given the path that each item covers, if
+/// any. Items whose paths are equal to or inside each other end up in the same group, so they
+/// can be processed one after another while the groups run concurrently.
+///
+/// Groups keep the relative order of their items.
+pub(crate) fn group_overlapping<T>(
+    items: Vec<T>,
+    path: impl Fn(&T) -> Option<PathBuf>,
+) -> Vec<Vec<T>> {
+    let mut groups: Vec<(Vec<PathBuf>, Vec<T>)> = Vec::new();
+    for item in items {
+        let Some(item_path) = path(&item) else {
+            groups.push((Vec::new(), vec![item]));
+            continue;
+        };
+
+        let overlapping: Vec<usize> = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, (paths, _))| {
+                paths
+                    .iter()
+                    .any(|other| other.starts_with(&item_path) || item_path.starts_with(other))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        match overlapping.split_first() {
+            None => groups.push((vec![item_path], vec![item])),
+            Some((&first, rest)) => {
+                // The item may join groups that did not overlap before, so merge all of them.
+                let removed: Vec<_> = rest
+                    .iter()
+                    .rev()
+                    .map(|&index| groups.remove(index))
+                    .collect();
+                let (paths, members) = &mut groups[first];
+                for (other_paths, other_members) in removed.into_iter().rev() {
+                    paths.extend(other_paths);
+                    members.extend(other_members);
+                }
+                paths.push(item_path);
+                members.push(item);
+            }
+        }
+    }
+    groups.into_iter().map(|(_, members)| members).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_counts() {
+        let mut run = CopyRun::default();
+        run.mark_unchanged(vec![PathBuf::from("/unchanged")]);
+        assert!(run.is_unchanged(Path::new("/unchanged")));
+        assert!(!run.is_unchanged(Path::new("/changed")));
+
+        run.record_copied();
+        run.record_copied();
+        run.record_skipped();
+        assert_eq!(
+            run.to_string(),
+            "copied 2 file(s), skipped 1 unchanged file(s)"
+        );
+        run.record_filtered();
+        assert_eq!(
+            run.to_string(),
+            "copied 2 file(s), skipped 1 unchanged and 1 filtered file(s)"
+        );
+        assert_eq!(
+            CopyRun::new(true, NonZeroUsize::MIN).to_string(),
+            "would copy 0 file(s), skipped 0 unchanged file(s)"
+        );
+    }
+
+    #[test]
+    fn test_try_for_each_runs_all_jobs_and_returns_errors() {
+        let run = CopyRun::new(false, NonZeroUsize::new(4).unwrap());
+        let count = AtomicUsize::new(0);
+        let result: Result<(), ()> = run.try_for_each((0..10).collect(), |_| {
+            count.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(count.load(Ordering::Relaxed), 10);
+
+        let result = run.try_for_each((0..10).collect(), |i| if i == 3 { Err(i) } else { Ok(()) });
+        assert_eq!(result, Err(3));
+    }
+
+    #[test]
+    fn test_group_overlapping_paths() {
+        let items = vec![
+            Some("/home/user/dir/file"),
+            Some("/home/user/other"),
+            None,
+            Some("/home/user/dir"),
+            Some("/home/user/dirname"),
+            Some("/home/user/other/nested"),
+            Some("/home/user/dir/nested/file"),
+        ];
+        let groups = group_overlapping(items, |item| item.map(PathBuf::from));
+        assert_eq!(
+            groups,
+            vec![
+                vec![
+                    Some("/home/user/dir/file"),
+                    Some("/home/user/dir"),
+                    Some("/home/user/dir/nested/file"),
+                ],
+                vec![Some("/home/user/other"), Some("/home/user/other/nested")],
+                vec![None],
+                vec![Some("/home/user/dirname")],
+            ]
+        );
+
+        // A later item can join groups that did not overlap before.
+        let groups = group_overlapping(vec!["/a/b", "/a/c", "/a"], |item| Some(item.into()));
+        assert_eq!(groups, vec![vec!["/a/b", "/a/c", "/a"]]);
+    }
+}
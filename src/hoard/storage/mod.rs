@@ -0,0 +1,403 @@
+//! Storage backends that the hoards root can be mirrored to and from. See [`Storage`].
+//!
+//! This is only used to sync the hoards root with remote storage, such as S3: the hoards root is
+//! [`mirror`]ed from the backend into a local directory before a command runs and back to the
+//! backend afterwards. Backing up, restoring, and every other command keep reading and writing
+//! the local directory with [`std::fs`] directly, so a backend only needs to provide the few
+//! operations that mirroring uses.
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::atomic;
+use super::symlinks::create_symlink;
+pub use s3::{Config as S3Config, S3};
+
+mod s3;
+
+/// Errors that can occur while working with a [`Storage`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// An I/O error occurred while working with a path in a storage.
+    #[error("storage I/O error at {path}: {error}")]
+    IO {
+        /// The path being worked with, relative to the storage root.
+        path: PathBuf,
+        /// The I/O error that occurred.
+        #[source]
+        error: io::Error,
+    },
+    /// A required environment variable with credentials for a storage is not set.
+    #[error("environment variable {0} must be set to use this storage")]
+    Credentials(&'static str),
+    /// The configured endpoint of a storage is not a valid URL.
+    #[error("invalid storage endpoint {endpoint}: {error}")]
+    Endpoint {
+        /// The configured endpoint.
+        endpoint: String,
+        /// The error from parsing the endpoint.
+        #[source]
+        error: url::ParseError,
+    },
+}
+
+/// Returns a closure that wraps an I/O error with `path`.
+fn io_err(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |error| Error::IO {
+        path: path.to_owned(),
+        error,
+    }
+}
+
+/// Where the hoards root is stored, as configured.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Backend {
+    /// The hoards root directory itself.
+    #[default]
+    Directory,
+    /// An S3-compatible bucket, synchronized with a local copy of the hoards root.
+    S3(S3Config),
+}
+
+/// The kind of an entry in a [`Storage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A regular file.
+    File,
+    /// A preserved symbolic link, whose content is its target.
+    Symlink,
+}
+
+/// Metadata of an entry in a [`Storage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// What kind of entry this is.
+    pub kind: EntryKind,
+    /// The length of the content in bytes.
+    pub len: u64,
+    /// When the content was last modified, if known.
+    pub modified: Option<SystemTime>,
+    /// The permission mode of the entry (Unix only).
+    pub mode: Option<u32>,
+}
+
+impl Metadata {
+    /// Returns whether an entry with this metadata is known to have the same content as one
+    /// with `other`, without reading either.
+    fn is_same_as(&self, other: &Self) -> bool {
+        self.kind == other.kind
+            && self.len == other.len
+            && self.mode == other.mode
+            && self.modified.is_some()
+            && self.modified == other.modified
+    }
+}
+
+/// A place the hoards root can be mirrored to and from.
+///
+/// All paths are relative to the root of the storage. Only files and preserved symbolic links
+/// are stored: directories exist implicitly as the parents of the entries in them.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Reads the content of the entry at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from reading the entry, including it not existing.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Creates or replaces the entry at `path` with `content`, giving it `metadata` where the
+    /// storage supports it.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from writing the entry.
+    fn write(&self, path: &Path, content: &[u8], metadata: &Metadata) -> io::Result<()>;
+
+    /// Returns the paths of all entries under `prefix`, recursively.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from listing entries, other than `prefix` not existing.
+    fn list(&self, prefix: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Deletes the entry at `path`, if it exists.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from deleting the entry.
+    fn delete(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns the metadata of the entry at `path`, or `None` if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from reading the metadata.
+    fn metadata(&self, path: &Path) -> io::Result<Option<Metadata>>;
+}
+
+/// A [`Storage`] in a directory on the local filesystem, such as the local hoards root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    /// Creates a storage rooted at the directory `root`.
+    #[must_use]
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    /// The root directory of this storage.
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn list_into(&self, dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                self.list_into(&path, paths)?;
+            } else if path.to_string_lossy().ends_with(atomic::TMP_SUFFIX) {
+                tracing::trace!(?path, "skipping temporary file");
+            } else {
+                let rel_path = path
+                    .strip_prefix(&self.root)
+                    .expect("listed paths should always be in the storage root");
+                paths.push(rel_path.to_owned());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_wraps)]
+fn mode(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn mode(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn permissions(mode: Option<u32>) -> Option<fs::Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    mode.map(fs::Permissions::from_mode)
+}
+
+#[cfg(not(unix))]
+fn permissions(_mode: Option<u32>) -> Option<fs::Permissions> {
+    None
+}
+
+impl Storage for Directory {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = self.root.join(path);
+        if fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            fs::read_link(&path).map(|target| target.to_string_lossy().into_owned().into_bytes())
+        } else {
+            fs::read(&path)
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8], metadata: &Metadata) -> io::Result<()> {
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match metadata.kind {
+            EntryKind::Symlink => {
+                let target = PathBuf::from(String::from_utf8_lossy(content).into_owned());
+                create_symlink(&target, &path)
+            }
+            EntryKind::File => {
+                // Replace a link instead of writing through it.
+                if fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+                    fs::remove_file(&path)?;
+                }
+                atomic::write(&path, permissions(metadata.mode), |file| {
+                    file.write_all(content)?;
+                    if let Some(modified) = metadata.modified {
+                        file.set_modified(modified)?;
+                    }
+                    Ok(())
+                })
+            }
+        }
+    }
+
+    fn list(&self, prefix: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = self.root.join(prefix);
+        let mut paths = Vec::new();
+        match fs::symlink_metadata(&dir) {
+            Ok(meta) if meta.is_dir() => self.list_into(&dir, &mut paths)?,
+            Ok(_) => paths.push(prefix.to_owned()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        let full_path = self.root.join(path);
+        match fs::remove_file(&full_path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        }
+
+        // Directories only exist to hold entries, so remove any left empty.
+        let mut parent = full_path.parent();
+        while let Some(dir) = parent.filter(|dir| *dir != self.root && dir.starts_with(&self.root))
+        {
+            if fs::read_dir(dir)?.next().is_some() {
+                break;
+            }
+            fs::remove_dir(dir)?;
+            parent = dir.parent();
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Option<Metadata>> {
+        let meta = match fs::symlink_metadata(self.root.join(path)) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let kind = if meta.file_type().is_symlink() {
+            EntryKind::Symlink
+        } else if meta.is_file() {
+            EntryKind::File
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Metadata {
+            kind,
+            len: meta.len(),
+            // Link times cannot be set portably, so links are always compared by content.
+            modified: (kind == EntryKind::File)
+                .then(|| meta.modified().ok())
+                .flatten(),
+            mode: (kind == EntryKind::File).then(|| mode(&meta)).flatten(),
+        }))
+    }
+}
+
+/// Makes the entries under `prefix` in `dest` match those in `src`, copying new and changed
+/// entries and deleting entries that no longer exist in `src`.
+///
+/// Files are compared by their metadata and symbolic links by their targets.
+/// Returns the number of entries copied or deleted.
+///
+/// # Errors
+///
+/// [`Error::IO`] for any error from either storage.
+pub fn mirror(src: &dyn Storage, dest: &dyn Storage, prefix: &Path) -> Result<usize, Error> {
+    let _span = tracing::debug_span!("mirror_storage", ?src, ?dest, ?prefix).entered();
+    let src_paths = src.list(prefix).map_err(io_err(prefix))?;
+    let dest_paths = dest.list(prefix).map_err(io_err(prefix))?;
+    let mut changed = 0;
+
+    for path in &src_paths {
+        let Some(src_meta) = src.metadata(path).map_err(io_err(path))? else {
+            continue;
+        };
+        let dest_meta = dest.metadata(path).map_err(io_err(path))?;
+        if dest_meta
+            .as_ref()
+            .is_some_and(|dest_meta| src_meta.is_same_as(dest_meta))
+        {
+            continue;
+        }
+
+        let content = src.read(path).map_err(io_err(path))?;
+        // Link times are not recorded, so compare their targets instead.
+        if src_meta.kind == EntryKind::Symlink
+            && dest_meta.is_some_and(|dest_meta| dest_meta.kind == EntryKind::Symlink)
+            && dest.read(path).map_err(io_err(path))? == content
+        {
+            continue;
+        }
+
+        tracing::debug!(path = path.to_string_lossy().as_ref(), "copying");
+        dest.write(path, &content, &src_meta)
+            .map_err(io_err(path))?;
+        changed += 1;
+    }
+
+    for path in dest_paths {
+        if src_paths.binary_search(&path).is_err() {
+            tracing::debug!(path = path.to_string_lossy().as_ref(), "deleting");
+            dest.delete(&path).map_err(io_err(&path))?;
+            changed += 1;
+        }
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_directories() {
+        let src_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let dest_dir = tempfile::tempdir().expect("failed to create temp dir");
+        let src = Directory::new(src_dir.path().to_owned());
+        let dest = Directory::new(dest_dir.path().to_owned());
+
+        fs::create_dir_all(src_dir.path().join("hoard").join("nested")).unwrap();
+        fs::write(src_dir.path().join("hoard").join("file"), "content").unwrap();
+        fs::write(
+            src_dir.path().join("hoard").join("nested").join("file"),
+            "nested",
+        )
+        .unwrap();
+        fs::write(src_dir.path().join("other"), "not mirrored").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("file", src_dir.path().join("hoard").join("link")).unwrap();
+
+        let prefix = Path::new("hoard");
+        let expected = if cfg!(unix) { 3 } else { 2 };
+        assert_eq!(mirror(&src, &dest, prefix).unwrap(), expected);
+        assert_eq!(src.list(prefix).unwrap(), dest.list(prefix).unwrap());
+        assert!(dest.list(Path::new("other")).unwrap().is_empty());
+        assert_eq!(
+            fs::read_to_string(dest_dir.path().join("hoard").join("nested").join("file")).unwrap(),
+            "nested"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dest_dir.path().join("hoard").join("link")).unwrap(),
+            Path::new("file")
+        );
+
+        // Nothing to do when nothing changed.
+        assert_eq!(mirror(&src, &dest, prefix).unwrap(), 0);
+
+        fs::remove_file(src_dir.path().join("hoard").join("nested").join("file")).unwrap();
+        fs::write(src_dir.path().join("hoard").join("file"), "changed").unwrap();
+        assert_eq!(mirror(&src, &dest, prefix).unwrap(), 2);
+        assert_eq!(
+            fs::read_to_string(dest_dir.path().join("hoard").join("file")).unwrap(),
+            "changed"
+        );
+        assert!(!dest_dir.path().join("hoard").join("nested").exists());
+    }
+}
@@ -0,0 +1,95 @@
+//! Helpers for handling symbolic links according to a pile's
+//! [`Symlinks`](super::Symlinks) setting.
+//!
+//! When following symbolic links, the canonical paths of all directories currently being walked
+//! are tracked so that a link pointing back into one of them is skipped instead of recursing
+//! forever.
+
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Returns whether `path` is itself a symbolic link, without following it.
+pub(crate) fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_symlink())
+}
+
+/// Returns the canonical path of the directory `dir` if walking it would not loop back into
+/// one of its `ancestors`, or `None` if it would.
+///
+/// # Errors
+///
+/// Any I/O error from canonicalizing `dir`.
+pub(crate) fn enter_dir(ancestors: &[PathBuf], dir: &Path) -> io::Result<Option<PathBuf>> {
+    let canonical = fs::canonicalize(dir)?;
+    if ancestors.contains(&canonical) {
+        tracing::warn!(
+            path = dir.to_string_lossy().as_ref(),
+            target = canonical.to_string_lossy().as_ref(),
+            "symbolic link loop detected; skipping",
+        );
+        Ok(None)
+    } else {
+        Ok(Some(canonical))
+    }
+}
+
+/// Creates a symbolic link at `link` pointing to `target`, replacing any existing file or link.
+///
+/// # Errors
+///
+/// Any I/O error from removing the existing file or creating the link.
+pub(crate) fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    if is_symlink(link) || link.is_file() {
+        fs::remove_file(link)?;
+    }
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    {
+        // Relative targets are relative to the link's parent directory.
+        let resolved = link
+            .parent()
+            .map_or_else(|| target.to_owned(), |parent| parent.join(target));
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enter_dir_detects_loops() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let dir = root.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+        let canonical = enter_dir(&[], &dir)
+            .unwrap()
+            .expect("first visit should not loop");
+        assert!(is_symlink(&dir.join("loop")));
+        assert!(!is_symlink(&dir));
+        assert_eq!(enter_dir(&[canonical], &dir.join("loop")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_create_symlink_replaces_existing() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let link = root.path().join("link");
+        fs::write(&link, "regular file").unwrap();
+
+        create_symlink(Path::new("target"), &link).expect("failed to create symlink");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("target"));
+        create_symlink(Path::new("other"), &link).expect("failed to replace symlink");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("other"));
+    }
+}
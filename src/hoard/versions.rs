@@ -0,0 +1,209 @@
+//! Snapshots of hoard files that were replaced or deleted by a backup. See [`Snapshot`].
+//!
+//! All snapshots of a hoard are kept in `$HOARDS_ROOT/.hoard-versions/$HOARD_NAME`, so they
+//! cannot collide with another hoard's files. Each snapshot is a directory named after the time
+//! of the backup that created it, in the same format as operation log files, and mirrors the
+//! layout of the hoards root directory, including metadata sidecars, so it can be restored from
+//! in the same way as the hoard itself.
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use time::OffsetDateTime;
+
+use super::metadata::Sidecar;
+use crate::checkers::history::operation::TIME_FORMAT;
+
+/// The name of the directory in the hoards root that holds the snapshots of all hoards.
+pub(crate) const VERSIONS_DIR: &str = ".hoard-versions";
+
+static SNAPSHOT_NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new("^[0-9]{4}(_[0-9]{2}){2}-([0-9]{2}_){2}([0-9]{2})\\.[0-9]{6}$")
+        .expect("invalid snapshot name regex")
+});
+
+/// The directory containing all snapshots of the hoard `hoard_name`.
+pub(crate) fn versions_dir(hoards_root: &Path, hoard_name: &str) -> PathBuf {
+    hoards_root.join(VERSIONS_DIR).join(hoard_name)
+}
+
+/// Returns the names (timestamps) of all snapshots of the hoard `hoard_name`, oldest first.
+///
+/// # Errors
+///
+/// Any I/O error from reading the snapshot directory, other than it not existing.
+pub(crate) fn list(hoards_root: &Path, hoard_name: &str) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(versions_dir(hoards_root, hoard_name)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            if SNAPSHOT_NAME_REGEX.is_match(name) {
+                names.push(name.to_owned());
+            }
+        }
+    }
+    // The timestamp format sorts chronologically.
+    names.sort();
+    Ok(names)
+}
+
+/// Deletes all but the `keep` most recent snapshots of the hoard `hoard_name`.
+///
+/// # Errors
+///
+/// Any I/O error from listing or deleting snapshots.
+pub(crate) fn prune(hoards_root: &Path, hoard_name: &str, keep: usize) -> io::Result<()> {
+    let names = list(hoards_root, hoard_name)?;
+    let excess = names.len().saturating_sub(keep);
+    for name in names.into_iter().take(excess) {
+        let path = versions_dir(hoards_root, hoard_name).join(name);
+        tracing::debug!(
+            path = path.to_string_lossy().as_ref(),
+            "deleting old snapshot"
+        );
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
+
+/// A snapshot of the files a single backup replaced or deleted from one hoard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    hoards_root: PathBuf,
+    /// The root of this snapshot, which mirrors `hoards_root`.
+    root: PathBuf,
+}
+
+impl Snapshot {
+    /// Creates a new snapshot of the hoard `hoard_name` for a backup at `timestamp`.
+    ///
+    /// Nothing is written to disk until a file is saved to it.
+    ///
+    /// # Errors
+    ///
+    /// Any error from formatting `timestamp`.
+    pub(crate) fn new(
+        hoards_root: &Path,
+        hoard_name: &str,
+        timestamp: OffsetDateTime,
+    ) -> Result<Self, time::error::Format> {
+        let name = timestamp.format(&TIME_FORMAT)?;
+        Ok(Self::existing(hoards_root, hoard_name, &name))
+    }
+
+    /// Returns the snapshot of the hoard `hoard_name` with the given `name` (timestamp).
+    pub(crate) fn existing(hoards_root: &Path, hoard_name: &str, name: &str) -> Self {
+        Self {
+            hoards_root: hoards_root.to_owned(),
+            root: versions_dir(hoards_root, hoard_name).join(name),
+        }
+    }
+
+    /// The path that `path`, somewhere in the hoards root, is saved to in this snapshot.
+    ///
+    /// Passing the prefix of a hoard or pile gives the prefix to restore it from.
+    pub(crate) fn path_for(&self, path: &Path) -> PathBuf {
+        let rel_path = path
+            .strip_prefix(&self.hoards_root)
+            .expect("snapshot paths should always be in the hoards root");
+        self.root.join(rel_path)
+    }
+
+    /// Saves the current version of the file at `path`, which is about to be replaced or
+    /// deleted from the pile stored at `prefix`.
+    ///
+    /// The first time a file of the pile is saved, the pile's metadata sidecar is saved with it,
+    /// so that restoring the snapshot restores the metadata of the saved files as well.
+    ///
+    /// # Errors
+    ///
+    /// Any I/O error from creating the snapshot directory or saving the files.
+    pub(crate) fn save(&self, prefix: &Path, path: &Path) -> io::Result<()> {
+        let sidecar = Sidecar::path(prefix);
+        if sidecar.exists() && !self.path_for(&sidecar).exists() {
+            self.save_file(&sidecar)?;
+        }
+        self.save_file(path)
+    }
+
+    /// Saves the file at `path` to the same place in this snapshot.
+    ///
+    /// The file is hard linked into the snapshot if possible, so that the file in the hoard can
+    /// still be replaced atomically. Otherwise, it is copied.
+    fn save_file(&self, path: &Path) -> io::Result<()> {
+        let dest = self.path_for(path);
+        tracing::debug!(
+            source = path.to_string_lossy().as_ref(),
+            destination = dest.to_string_lossy().as_ref(),
+            "saving previous version",
+        );
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::hard_link(path, &dest).is_err() {
+            fs::copy(path, &dest)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_list_and_prune() {
+        let root = tempfile::tempdir().expect("failed to create temp dir");
+        let prefix = root.path().join("hoard").join("pile");
+        let file = prefix.join("file");
+        fs::create_dir_all(&prefix).unwrap();
+        fs::write(&file, "old content").unwrap();
+        fs::write(Sidecar::path(&prefix), "{}").unwrap();
+
+        let names = [
+            "2021_01_01-00_00_00.000000",
+            "2021_01_02-00_00_00.000000",
+            "2021_01_03-00_00_00.000000",
+        ];
+        for name in names {
+            Snapshot::existing(root.path(), "hoard", name)
+                .save(&prefix, &file)
+                .expect("failed to save file to snapshot");
+        }
+        // Replacing the file does not affect the saved copies.
+        fs::remove_file(&file).unwrap();
+        fs::write(&file, "new content").unwrap();
+
+        assert_eq!(list(root.path(), "hoard").unwrap(), names);
+        assert!(list(root.path(), "other").unwrap().is_empty());
+
+        prune(root.path(), "hoard", 2).expect("failed to prune snapshots");
+        assert_eq!(list(root.path(), "hoard").unwrap(), &names[1..]);
+
+        let snapshot = Snapshot::existing(root.path(), "hoard", names[2]);
+        assert_eq!(
+            fs::read_to_string(snapshot.path_for(&file)).unwrap(),
+            "old content"
+        );
+        assert!(snapshot.path_for(&Sidecar::path(&prefix)).exists());
+    }
+
+    #[test]
+    fn test_versions_dir_is_reserved() {
+        let root = Path::new("/hoards");
+        assert_eq!(
+            versions_dir(root, "hoard"),
+            PathBuf::from("/hoards/.hoard-versions/hoard")
+        );
+    }
+}
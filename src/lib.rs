@@ -65,6 +65,7 @@
 )]
 pub use config::Config;
 
+pub(crate) mod archive;
 pub mod checkers;
 pub mod combinator;
 pub mod command;
@@ -72,6 +73,7 @@ pub mod config;
 pub(crate) mod diff;
 pub mod env_vars;
 pub mod filters;
+pub(crate) mod git;
 pub mod hoard;
 
 /// The default file stem of the configuration file (i.e. without file extension).
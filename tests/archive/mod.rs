@@ -0,0 +1,48 @@
+//! Integration tests for exporting hoards to an archive and importing them elsewhere.
+
+use crate::common::tester::Tester;
+
+const HOARDS: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+[hoards.other]
+    "test" = "${HOME}/other"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_export_then_import_on_new_system() {
+    let archives = tempfile::tempdir().expect("failed to create temp dir");
+    let archive = archives.path().join("backup.tar.zst");
+    let archive = archive.to_str().unwrap();
+
+    let tester = Tester::new(HOARDS);
+    tester.write("notes/note.txt", "note");
+    tester.write("other/other.txt", "other");
+    tester.expect_run(&["backup"]);
+    tester.expect_run(&["export", "-o", archive, "notes"]);
+    drop(tester);
+
+    // A new system, with nothing backed up yet.
+    let tester = Tester::new(HOARDS);
+    tester.expect_run(&["import", archive]);
+    assert!(tester.hoards_root().join("notes").join("note.txt").exists());
+    assert!(!tester.hoards_root().join("other").exists());
+    // The imported operation logs let the consistency checks pass.
+    tester.expect_run(&["restore", "notes"]);
+    assert_eq!(tester.read("notes/note.txt"), b"note");
+
+    // Importing over an existing hoard needs `--force`.
+    tester.write("notes/note.txt", "changed");
+    tester.expect_run(&["backup", "notes"]);
+    assert!(tester.run(&["import", archive]).is_err());
+    assert_eq!(
+        std::fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap(),
+        b"changed"
+    );
+    tester.expect_run(&["--force", "import", archive]);
+    assert_eq!(
+        std::fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap(),
+        b"note"
+    );
+}
@@ -4,6 +4,7 @@ use rand::RngCore;
 use tempfile::NamedTempFile;
 
 pub mod file;
+pub mod tester;
 pub mod toml;
 
 pub fn create_random_file<const SIZE: usize>() -> NamedTempFile {
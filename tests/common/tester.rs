@@ -0,0 +1,94 @@
+//! Running `hoard` commands end to end against temporary directories.
+//!
+//! A [`Tester`] points `HOME` and the XDG directories at a temporary directory, so tests using
+//! it change process-wide state and must be marked `#[serial_test::serial]`.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use hoard::config::builder::Builder;
+use hoard::config::Error;
+use structopt::StructOpt;
+use tempfile::TempDir;
+
+/// An environment that matches whenever a [`Tester`] is in use. Piles should use paths under
+/// `${HOME}` for it.
+const ENV: &str = r#"
+[envs.test]
+    env = [{ var = "HOME" }]
+"#;
+
+pub struct Tester {
+    home: TempDir,
+    config: String,
+}
+
+impl Tester {
+    /// Creates a tester with the given configuration, which is followed by an environment named
+    /// `test`, so that it can also set top-level options.
+    pub fn new(config: &str) -> Self {
+        let home = TempDir::new().expect("failed to create temporary home");
+        let tester = Self {
+            home,
+            config: format!("{config}\n{ENV}"),
+        };
+        std::env::set_var("HOME", tester.home());
+        std::env::set_var("XDG_DATA_HOME", tester.home().join(".local").join("share"));
+        tester.use_system(0);
+        tester
+    }
+
+    /// Replaces the configuration, as with [`Tester::new`].
+    pub fn set_config(&mut self, config: &str) {
+        self.config = format!("{config}\n{ENV}");
+    }
+
+    /// The temporary home directory.
+    pub fn home(&self) -> &Path {
+        self.home.path()
+    }
+
+    /// The default hoards root.
+    pub fn hoards_root(&self) -> PathBuf {
+        self.home()
+            .join(".local")
+            .join("share")
+            .join("hoard")
+            .join("hoards")
+    }
+
+    /// Acts as a different system from now on. Systems share the data directory, and with it
+    /// the hoards root and operation logs, but each has its own ID.
+    pub fn use_system(&self, system: usize) {
+        let config_dir = self.home().join(format!(".config-{system}"));
+        std::env::set_var("XDG_CONFIG_HOME", config_dir);
+    }
+
+    /// Writes `content` to `path` relative to the home directory, creating parent directories.
+    pub fn write(&self, path: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.home().join(path);
+        fs::create_dir_all(path.parent().unwrap()).expect("failed to create parent directory");
+        fs::write(&path, content).expect("failed to write file");
+        path
+    }
+
+    /// Reads the file at `path` relative to the home directory.
+    pub fn read(&self, path: &str) -> Vec<u8> {
+        fs::read(self.home().join(path)).expect("failed to read file")
+    }
+
+    /// Runs `hoard` with the given command line arguments (without the program name).
+    pub fn run(&self, args: &[&str]) -> Result<(), Error> {
+        let from_args =
+            Builder::from_iter_safe(std::iter::once("hoard").chain(args.iter().copied()))
+                .expect("invalid command line arguments");
+        let from_file: Builder = toml::from_str(&self.config).expect("invalid configuration");
+        from_file.layer(from_args).build()?.run()
+    }
+
+    /// Runs `hoard` like [`Tester::run`] and panics if it fails.
+    pub fn expect_run(&self, args: &[&str]) {
+        if let Err(err) = self.run(args) {
+            panic!("`hoard {}` failed: {err}", args.join(" "));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Integration tests for propagating deletions between the system and the hoards root.
+
+use crate::common::tester::Tester;
+
+const PLAIN: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_backup_deletes_files_deleted_locally() {
+    let tester = Tester::new(PLAIN);
+    tester.write("notes/keep.txt", "keep");
+    tester.write("notes/delete.txt", "delete");
+    tester.expect_run(&["backup"]);
+    assert!(tester
+        .hoards_root()
+        .join("notes")
+        .join("delete.txt")
+        .exists());
+
+    std::fs::remove_file(tester.home().join("notes").join("delete.txt")).unwrap();
+    tester.expect_run(&["backup"]);
+    assert!(tester.hoards_root().join("notes").join("keep.txt").exists());
+    assert!(!tester
+        .hoards_root()
+        .join("notes")
+        .join("delete.txt")
+        .exists());
+}
+
+#[test]
+#[serial_test::serial]
+fn test_restore_deletes_files_deleted_remotely() {
+    let tester = Tester::new(PLAIN);
+    tester.write("notes/keep.txt", "keep");
+    tester.write("notes/delete.txt", "delete");
+    tester.expect_run(&["backup"]);
+
+    // Another system restores, deletes a file, and backs up.
+    tester.use_system(1);
+    tester.expect_run(&["restore"]);
+    std::fs::remove_file(tester.home().join("notes").join("delete.txt")).unwrap();
+    tester.expect_run(&["backup"]);
+    assert!(!tester
+        .hoards_root()
+        .join("notes")
+        .join("delete.txt")
+        .exists());
+
+    // Restoring on the first system deletes the file there as well.
+    std::fs::write(tester.home().join("notes").join("delete.txt"), "delete").unwrap();
+    tester.use_system(0);
+    tester.expect_run(&["restore"]);
+    assert!(tester.home().join("notes").join("keep.txt").exists());
+    assert!(!tester.home().join("notes").join("delete.txt").exists());
+}
+
+#[test]
+#[serial_test::serial]
+fn test_backup_to_asymmetric_pile_without_identity() {
+    let identity = age::x25519::Identity::generate();
+    let tester = Tester::new(&format!(
+        r#"
+[hoards.secrets]
+    "test" = "${{HOME}}/secrets"
+[hoards.secrets.config]
+    encrypt = {{ type = "asymmetric", public_keys = ["{}"] }}
+"#,
+        identity.to_public()
+    ));
+    tester.write("secrets/token", "first");
+    tester.write("secrets/old", "old");
+    tester.expect_run(&["backup"]);
+
+    // Backing up again needs to know about deletions, but must not need to decrypt anything.
+    tester.write("secrets/token", "second");
+    std::fs::remove_file(tester.home().join("secrets").join("old")).unwrap();
+    tester.expect_run(&["backup"]);
+    tester.expect_run(&["backup"]);
+
+    let stored = tester.hoards_root().join("secrets");
+    assert!(!stored.join("old").exists());
+    let token = std::fs::read(stored.join("token")).unwrap();
+    assert!(!token.windows(b"second".len()).any(|part| part == b"second"));
+}
@@ -0,0 +1,44 @@
+//! Integration tests for `--dry-run`.
+
+use std::fs;
+
+use crate::common::tester::Tester;
+
+const PLAIN: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_dry_run_changes_nothing() {
+    let tester = Tester::new(PLAIN);
+    tester.write("notes/note.txt", "first");
+    tester.write("notes/delete.txt", "delete");
+    let history = tester.home().join(".local/share/hoard/history");
+
+    tester.expect_run(&["--dry-run", "backup"]);
+    assert!(!tester.hoards_root().join("notes").exists());
+    assert!(!history.exists());
+
+    tester.expect_run(&["backup"]);
+    assert!(history.exists());
+
+    // Neither changes nor deletions are backed up.
+    tester.write("notes/note.txt", "second");
+    fs::remove_file(tester.home().join("notes/delete.txt")).unwrap();
+    tester.expect_run(&["--dry-run", "backup"]);
+    let stored = tester.hoards_root().join("notes");
+    assert_eq!(fs::read(stored.join("note.txt")).unwrap(), b"first");
+    assert!(stored.join("delete.txt").exists());
+
+    // Nor are they restored.
+    tester.expect_run(&["--dry-run", "--force", "restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"second");
+    assert!(!tester.home().join("notes/delete.txt").exists());
+
+    // The dry runs were not recorded, so backing up still sees the changes.
+    tester.expect_run(&["backup"]);
+    assert_eq!(fs::read(stored.join("note.txt")).unwrap(), b"second");
+    assert!(!stored.join("delete.txt").exists());
+}
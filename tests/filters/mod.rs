@@ -0,0 +1,118 @@
+//! Integration tests for filtering the files in a pile.
+
+use std::fs;
+
+use crate::common::tester::Tester;
+
+const PLAIN: &str = r#"
+[hoards.app]
+    "test" = "${HOME}/app"
+"#;
+
+const IGNORE_LOGS: &str = r#"
+[hoards.app]
+    "test" = "${HOME}/app"
+    [hoards.app.config]
+        ignore = ["**/*.log"]
+"#;
+
+const ENCRYPTED: &str = r#"
+[hoards.app]
+    "test" = "${HOME}/app"
+    [hoards.app.config]
+        encrypt = { type = "symmetric", password = "correcthorsebatterystaple" }
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_restore_skips_ignored_files() {
+    let tester = Tester::new(IGNORE_LOGS);
+    tester.write("app/config.toml", "config");
+    tester.write("app/debug.log", "not backed up");
+    tester.expect_run(&["backup"]);
+    assert!(tester
+        .hoards_root()
+        .join("app")
+        .join("config.toml")
+        .exists());
+    assert!(!tester.hoards_root().join("app").join("debug.log").exists());
+
+    // A file that ended up in the hoard anyway, e.g. before it was ignored.
+    let stored = tester
+        .hoards_root()
+        .join("app")
+        .join("nested")
+        .join("old.log");
+    fs::create_dir_all(stored.parent().unwrap()).unwrap();
+    fs::write(&stored, "stale").unwrap();
+
+    fs::remove_dir_all(tester.home().join("app")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("app/config.toml"), b"config");
+    assert!(!tester
+        .home()
+        .join("app")
+        .join("nested")
+        .join("old.log")
+        .exists());
+    assert!(!tester.home().join("app").join("debug.log").exists());
+}
+
+#[test]
+#[serial_test::serial]
+fn test_hoardignore_files() {
+    let tester = Tester::new(PLAIN);
+    tester.write("app/.hoardignore", "*.log\n!important.log\n/cache/\n");
+    tester.write("app/config.toml", "config");
+    tester.write("app/debug.log", "ignored");
+    tester.write("app/important.log", "re-included");
+    tester.write("app/cache/data", "ignored");
+    tester.write("app/nested/cache/data", "not anchored here");
+    // Deeper files take precedence over their parents.
+    tester.write("app/nested/.hoardignore", "!debug.log\n");
+    tester.write("app/nested/debug.log", "re-included");
+    tester.expect_run(&["backup"]);
+
+    let stored = tester.hoards_root().join("app");
+    for path in [
+        ".hoardignore",
+        "config.toml",
+        "important.log",
+        "nested/cache/data",
+        "nested/.hoardignore",
+        "nested/debug.log",
+    ] {
+        assert!(stored.join(path).exists(), "{path} should be backed up");
+    }
+    for path in ["debug.log", "cache"] {
+        assert!(!stored.join(path).exists(), "{path} should be ignored");
+    }
+
+    // The same files are skipped when restoring.
+    fs::write(stored.join("debug.log"), "stale").unwrap();
+    fs::remove_file(tester.home().join("app/debug.log")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert!(!tester.home().join("app/debug.log").exists());
+}
+
+#[test]
+#[serial_test::serial]
+fn test_restore_reads_hoardignore_files_from_hoard() {
+    let tester = Tester::new(ENCRYPTED);
+    tester.write("app/config.toml", "config");
+    tester.write("app/debug.log", "not ignored here");
+    tester.write("app/nested/.hoardignore", "*.log\n");
+    tester.expect_run(&["backup"]);
+
+    // A file that ended up in the hoard anyway, e.g. before it was ignored.
+    let stored = tester.hoards_root().join("app");
+    fs::write(stored.join("nested").join("debug.log"), "stale").unwrap();
+
+    // The system has none of the pile's files, including its `.hoardignore` files.
+    fs::remove_dir_all(tester.home().join("app")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("app/config.toml"), b"config");
+    assert_eq!(tester.read("app/debug.log"), b"not ignored here");
+    assert_eq!(tester.read("app/nested/.hoardignore"), b"*.log\n");
+    assert!(!tester.home().join("app/nested/debug.log").exists());
+}
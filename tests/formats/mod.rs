@@ -0,0 +1,148 @@
+//! Integration tests for changing how an existing pile is stored in the hoard.
+
+use std::fs;
+
+use crate::common::tester::Tester;
+
+const PLAIN: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+"#;
+
+const ENCODED: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+[hoards.notes.config]
+    encrypt = { type = "symmetric", password = "correcthorsebatterystaple" }
+    compress = "zstd"
+"#;
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[test]
+#[serial_test::serial]
+fn test_enabling_encryption_stores_unchanged_files_again() {
+    let mut tester = Tester::new(PLAIN);
+    tester.write("notes/note.txt", "a secret note");
+    tester.expect_run(&["backup"]);
+
+    // Files stored before the change are still read as they were stored.
+    tester.set_config(ENCODED);
+    tester.expect_run(&["status"]);
+    fs::remove_file(tester.home().join("notes").join("note.txt")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"a secret note");
+
+    tester.expect_run(&["backup"]);
+    let stored = fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap();
+    assert!(!contains(&stored, b"a secret note"));
+
+    tester.expect_run(&["status"]);
+    fs::remove_file(tester.home().join("notes").join("note.txt")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"a secret note");
+}
+
+#[test]
+#[serial_test::serial]
+fn test_disabling_encryption_stores_unchanged_files_again() {
+    let mut tester = Tester::new(ENCODED);
+    tester.write("notes/note.txt", "a secret note");
+    tester.expect_run(&["backup"]);
+
+    tester.set_config(PLAIN);
+    tester.expect_run(&["backup"]);
+    assert_eq!(
+        fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap(),
+        b"a secret note"
+    );
+
+    fs::remove_file(tester.home().join("notes").join("note.txt")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"a secret note");
+}
+
+#[test]
+#[serial_test::serial]
+fn test_rekey_records_new_encryption() {
+    let mut tester = Tester::new(PLAIN);
+    tester.write("notes/note.txt", "a secret note");
+    tester.expect_run(&["backup"]);
+
+    tester.set_config(ENCODED);
+    tester.expect_run(&["rekey", "notes"]);
+    let stored = fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap();
+    assert!(!contains(&stored, b"a secret note"));
+
+    fs::remove_file(tester.home().join("notes").join("note.txt")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"a secret note");
+}
+
+const COMPRESSED: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+[hoards.notes.config]
+    compress = "zstd"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_compressed_files_are_restored_unchanged() {
+    let mut tester = Tester::new(PLAIN);
+    // Files that are zstd-compressed already must only be decompressed if hoard compressed them.
+    let archive = zstd::encode_all(&b"an archived note"[..], 0).unwrap();
+    tester.write("notes/note.txt.zst", &archive);
+    tester.expect_run(&["backup"]);
+
+    tester.set_config(COMPRESSED);
+    fs::remove_file(tester.home().join("notes").join("note.txt.zst")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt.zst"), archive);
+
+    tester.expect_run(&["backup"]);
+    fs::remove_file(tester.home().join("notes").join("note.txt.zst")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt.zst"), archive);
+}
+
+#[test]
+#[serial_test::serial]
+fn test_disabling_compression_restores_stored_files() {
+    let mut tester = Tester::new(COMPRESSED);
+    tester.write("notes/note.txt", "a compressed note");
+    tester.expect_run(&["backup"]);
+
+    // Files stored compressed are decompressed even though compression is now off.
+    tester.set_config(PLAIN);
+    fs::remove_file(tester.home().join("notes").join("note.txt")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"a compressed note");
+
+    tester.expect_run(&["backup"]);
+    assert_eq!(
+        fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap(),
+        b"a compressed note"
+    );
+}
+
+#[test]
+#[serial_test::serial]
+fn test_large_files_round_trip() {
+    let tester = Tester::new(ENCODED);
+    // Large enough to be encrypted and compressed in several pieces.
+    let content: Vec<u8> = (0..300_000_u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+    tester.write("notes/large.bin", &content);
+    tester.expect_run(&["backup"]);
+    tester.expect_run(&["status"]);
+
+    fs::remove_file(tester.home().join("notes").join("large.bin")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/large.bin"), content);
+}
@@ -0,0 +1,84 @@
+//! Integration tests for keeping the data directory in a git repository.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::common::tester::Tester;
+
+const GIT: &str = r#"
+[git]
+    branch = "main"
+
+[hoards.notes]
+    "test" = "${HOME}/notes"
+"#;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .expect("failed to run git");
+    assert!(
+        output.status.success(),
+        "`git {}` failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn clone(remote: &Path, dest: &Path) {
+    git(
+        remote.parent().unwrap(),
+        &[
+            "clone",
+            "-q",
+            remote.to_str().unwrap(),
+            dest.to_str().unwrap(),
+        ],
+    );
+    git(dest, &["config", "user.name", "Hoard Test"]);
+    git(dest, &["config", "user.email", "hoard@example.com"]);
+    git(dest, &["checkout", "-q", "-B", "main"]);
+}
+
+#[test]
+#[serial_test::serial]
+fn test_backup_commits_and_syncs_with_remote() {
+    let tester = Tester::new(GIT);
+    let repos = tempfile::tempdir().expect("failed to create temp dir");
+    let remote = repos.path().join("remote.git");
+    git(
+        repos.path(),
+        &["init", "-q", "--bare", remote.to_str().unwrap()],
+    );
+    let data_dir = tester.hoards_root().parent().unwrap().to_owned();
+    clone(&remote, &data_dir);
+
+    tester.write("notes/note.txt", "first");
+    tester.expect_run(&["backup"]);
+    let message = git(&data_dir, &["log", "-1", "--format=%B"]);
+    assert!(message.contains("notes"), "unexpected message: {message}");
+    assert!(git(&data_dir, &["status", "--porcelain"]).is_empty());
+    tester.expect_run(&["push"]);
+
+    // Another clone of the repository sees the backup and changes it.
+    let other = repos.path().join("other");
+    clone(&remote, &other);
+    git(&other, &["pull", "-q", "origin", "main"]);
+    let stored = other.join("hoards").join("notes").join("note.txt");
+    assert_eq!(std::fs::read(&stored).unwrap(), b"first");
+    std::fs::write(&stored, "second").unwrap();
+    git(&other, &["commit", "-q", "-am", "change note"]);
+    git(&other, &["push", "-q", "origin", "main"]);
+
+    tester.expect_run(&["pull"]);
+    assert_eq!(
+        std::fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap(),
+        b"second"
+    );
+    tester.expect_run(&["--force", "restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"second");
+}
@@ -0,0 +1,61 @@
+//! Integration tests for copying piles concurrently.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::common::tester::Tester;
+
+// The `sub` pile lies inside the `parent` pile, and `sibling` shares their parent directory.
+const OVERLAPPING: &str = r#"
+[hoards.parent]
+    "test" = "${HOME}/parent"
+[hoards.sub]
+    "test" = "${HOME}/parent/sub"
+[hoards.sibling]
+    "test" = "${HOME}/sibling"
+"#;
+
+const FILES: usize = 50;
+
+fn leftover_tmp_files(dir: &Path) -> Vec<PathBuf> {
+    let mut leftovers = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            leftovers.extend(leftover_tmp_files(&path));
+        } else if path.to_string_lossy().ends_with("hoard-tmp") {
+            leftovers.push(path);
+        }
+    }
+    leftovers
+}
+
+#[test]
+#[serial_test::serial]
+fn test_overlapping_piles_with_jobs() {
+    let tester = Tester::new(OVERLAPPING);
+    for i in 0..FILES {
+        tester.write(&format!("parent/sub/file{i}.txt"), format!("sub {i}"));
+        tester.write(&format!("sibling/file{i}.txt"), format!("sibling {i}"));
+    }
+    tester.write("parent/top.txt", "top");
+    tester.expect_run(&["--jobs", "4", "backup"]);
+
+    fs::remove_dir_all(tester.home().join("parent")).unwrap();
+    fs::remove_dir_all(tester.home().join("sibling")).unwrap();
+    // Both the `parent` and `sub` piles restore the files in `parent/sub`.
+    tester.expect_run(&["--jobs", "4", "restore"]);
+
+    for i in 0..FILES {
+        assert_eq!(
+            tester.read(&format!("parent/sub/file{i}.txt")),
+            format!("sub {i}").as_bytes()
+        );
+        assert_eq!(
+            tester.read(&format!("sibling/file{i}.txt")),
+            format!("sibling {i}").as_bytes()
+        );
+    }
+    assert_eq!(tester.read("parent/top.txt"), b"top");
+    assert_eq!(leftover_tmp_files(tester.home()), Vec::<PathBuf>::new());
+}
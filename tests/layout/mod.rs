@@ -0,0 +1,57 @@
+//! Integration tests for the content-addressed layout of the hoards root.
+
+use std::fs;
+
+use crate::common::tester::Tester;
+
+const CONTENT_ADDRESSED: &str = r#"
+layout = "content-addressed"
+
+[hoards.first]
+    "test" = "${HOME}/first"
+[hoards.second]
+    "test" = "${HOME}/second"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_shared_content_stays_separate() {
+    let tester = Tester::new(CONTENT_ADDRESSED);
+    tester.write("first/font.ttf", "shared content");
+    tester.write("second/font.ttf", "shared content");
+    tester.expect_run(&["backup"]);
+    assert!(tester.hoards_root().join("first.manifest.json").is_file());
+
+    tester.write("first/font.ttf", "changed content");
+    tester.expect_run(&["backup"]);
+
+    fs::remove_dir_all(tester.home().join("first")).unwrap();
+    fs::remove_dir_all(tester.home().join("second")).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("first/font.ttf"), b"changed content");
+    assert_eq!(tester.read("second/font.ttf"), b"shared content");
+}
+
+#[test]
+#[serial_test::serial]
+#[cfg(unix)]
+fn test_permissions_of_shared_content_stay_separate() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tester = Tester::new(CONTENT_ADDRESSED);
+    let first = tester.write("first/script.sh", "shared content");
+    let second = tester.write("second/script.sh", "shared content");
+    fs::set_permissions(&first, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::set_permissions(&second, fs::Permissions::from_mode(0o644)).unwrap();
+    tester.expect_run(&["backup"]);
+
+    fs::set_permissions(&first, fs::Permissions::from_mode(0o755)).unwrap();
+    tester.expect_run(&["backup"]);
+
+    fs::remove_dir_all(tester.home().join("first")).unwrap();
+    fs::remove_dir_all(tester.home().join("second")).unwrap();
+    tester.expect_run(&["restore"]);
+    let mode = |path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&first), 0o755);
+    assert_eq!(mode(&second), 0o644);
+}
@@ -1,2 +1,13 @@
+mod archive;
 pub mod common;
 pub mod config;
+mod deletions;
+mod dry_run;
+mod filters;
+mod formats;
+mod git;
+mod jobs;
+mod layout;
+mod storage;
+mod unchanged;
+mod versions;
@@ -0,0 +1,52 @@
+//! Integration tests for storing the hoards root in a storage backend.
+
+use std::fs;
+use std::path::Path;
+
+use hoard::hoard::{mirror, Directory, Storage};
+
+use crate::common::tester::Tester;
+
+const NOTES: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_hoards_root_round_trips_through_storage() {
+    let tester = Tester::new(NOTES);
+    tester.write("notes/note.txt", "note");
+    tester.write("notes/nested/other.txt", "other");
+    tester.expect_run(&["backup"]);
+    // Leftovers of an interrupted write are never stored.
+    fs::write(
+        tester
+            .hoards_root()
+            .join("notes")
+            .join(".note.txt.1.0.hoard-tmp"),
+        "partial",
+    )
+    .unwrap();
+
+    let remote_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let remote = Directory::new(remote_dir.path().to_owned());
+    let data = Directory::new(tester.hoards_root().parent().unwrap().to_owned());
+    let hoards = Path::new("hoards");
+    assert!(mirror(&data, &remote, hoards).unwrap() > 0);
+    let stored = remote.list(hoards).unwrap();
+    assert!(stored.contains(&hoards.join("notes").join("note.txt")));
+    assert!(stored.contains(&hoards.join("notes").join("nested").join("other.txt")));
+    assert!(!stored
+        .iter()
+        .any(|path| path.to_string_lossy().ends_with("hoard-tmp")));
+    assert_eq!(mirror(&data, &remote, hoards).unwrap(), 0);
+
+    // Another system starts from what is in the storage.
+    fs::remove_dir_all(tester.hoards_root()).unwrap();
+    fs::remove_dir_all(tester.home().join("notes")).unwrap();
+    mirror(&remote, &data, hoards).unwrap();
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"note");
+    assert_eq!(tester.read("notes/nested/other.txt"), b"other");
+}
@@ -0,0 +1,30 @@
+//! Integration tests for skipping files that are unchanged since the last backup.
+
+use std::fs;
+
+use crate::common::tester::Tester;
+
+const PLAIN: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+"#;
+
+#[test]
+#[serial_test::serial]
+fn test_backup_after_restore_copies_edited_files() {
+    let tester = Tester::new(PLAIN);
+    tester.write("notes/note.txt", "first");
+    tester.expect_run(&["backup"]);
+
+    tester.write("notes/note.txt", "second");
+    tester.expect_run(&["restore"]);
+    assert_eq!(tester.read("notes/note.txt"), b"first");
+
+    // Only the last backup says what is in the hoard, not the restore after it.
+    tester.write("notes/note.txt", "second");
+    tester.expect_run(&["backup"]);
+    assert_eq!(
+        fs::read(tester.hoards_root().join("notes").join("note.txt")).unwrap(),
+        b"second"
+    );
+}
@@ -0,0 +1,80 @@
+//! Integration tests for keeping and restoring previous versions of hoard files.
+
+use std::fs;
+
+use crate::common::tester::Tester;
+
+const VERSIONED: &str = r#"
+[hoards.notes]
+    "test" = "${HOME}/notes"
+    [hoards.notes.config]
+        versions = 2
+        preserve_xattrs = true
+
+[hoards."notes.versions"]
+    "test" = "${HOME}/other"
+"#;
+
+fn snapshot_names(tester: &Tester, hoard: &str) -> Vec<String> {
+    let mut names: Vec<String> =
+        fs::read_dir(tester.hoards_root().join(".hoard-versions").join(hoard))
+            .expect("failed to read snapshot directory")
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+    names.sort();
+    names
+}
+
+#[test]
+#[serial_test::serial]
+fn test_snapshots_do_not_collide_with_hoard_names() {
+    let tester = Tester::new(VERSIONED);
+    tester.write("notes/note.txt", "first");
+    tester.write("other/other.txt", "other");
+    tester.expect_run(&["backup"]);
+    tester.write("notes/note.txt", "second");
+    tester.expect_run(&["backup"]);
+
+    assert_eq!(snapshot_names(&tester, "notes").len(), 1);
+    assert_eq!(
+        tester.read(".local/share/hoard/hoards/notes.versions/other.txt"),
+        b"other"
+    );
+
+    fs::remove_dir_all(tester.home().join("other")).unwrap();
+    tester.expect_run(&["restore", "notes.versions"]);
+    assert_eq!(tester.read("other/other.txt"), b"other");
+}
+
+#[test]
+#[serial_test::serial]
+#[cfg(unix)]
+fn test_restore_version_restores_metadata() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tester = Tester::new(VERSIONED);
+    let note = tester.write("notes/note.txt", "first");
+    fs::set_permissions(&note, fs::Permissions::from_mode(0o600)).unwrap();
+    // Extended attributes are only restored from the recorded metadata.
+    let has_xattrs = xattr::set(&note, "user.hoard-test", b"first").is_ok();
+    tester.expect_run(&["backup"]);
+
+    tester.write("notes/note.txt", "second");
+    fs::set_permissions(&note, fs::Permissions::from_mode(0o644)).unwrap();
+    if has_xattrs {
+        xattr::remove(&note, "user.hoard-test").unwrap();
+    }
+    tester.expect_run(&["backup"]);
+
+    let names = snapshot_names(&tester, "notes");
+    tester.expect_run(&["restore", "--version", &names[0], "notes"]);
+    assert_eq!(tester.read("notes/note.txt"), b"first");
+    let mode = fs::metadata(&note).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    if has_xattrs {
+        assert_eq!(
+            xattr::get(&note, "user.hoard-test").unwrap().as_deref(),
+            Some(&b"first"[..])
+        );
+    }
+}